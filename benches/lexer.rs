@@ -0,0 +1,32 @@
+// Times lexing a large source built by repeating the example article. Run
+// with `cargo bench`, the harness is a plain timing loop so it works on
+// stable without extra dependencies.
+use std::time::Instant;
+
+use blogger::lexer::{lexer::Lexer, tokens::token_specs};
+
+const ARTICLE: &str = include_str!("../blog/article.blog");
+const REPEAT: usize = 20;
+const RUNS: usize = 5;
+
+fn main() {
+    let src = ARTICLE.repeat(REPEAT);
+    let specs = token_specs();
+    let mut best = None;
+    let mut tokens = 0;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        tokens = Lexer::new(&src, specs.clone()).tokens().unwrap().len();
+        let elapsed = start.elapsed();
+        best = Some(best.map_or(elapsed, |best: std::time::Duration| best.min(elapsed)));
+    }
+    let best = best.unwrap();
+    println!(
+        "lexed {} bytes into {} tokens in {:?} (best of {}), {:.1} MB/s",
+        src.len(),
+        tokens,
+        best,
+        RUNS,
+        src.len() as f64 / best.as_secs_f64() / 1_000_000.0
+    );
+}
@@ -1,117 +1,931 @@
 use core::fmt;
-use std::{error::Error, io::Write};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet},
+    error::Error,
+    io::Write,
+};
 
+use super::theme::Theme;
 use crate::{
+    diag::Spanned,
     errors::BloggerError,
     parser::parser::{
         ArticleDeclaration, AstNode, List, Paragraph, Program, SectionDeclaration, Statement,
     },
+    util::{collapse_whitespace, dedent, slugify},
 };
 
+// StatementHook is called after a statement is generated with the statement
+// and the output, to append markup of its own
+pub type StatementHook = Box<dyn Fn(&Statement, &mut dyn Write) -> Result<(), GenerationError>>;
+
 pub struct Generator {
     program: Program,
+    theme: Theme,
+    minify: bool,
+    indent: bool,
+    // collapse whitespace runs in text blocks, as the browser would
+    collapse_whitespace: bool,
+    // self-close void elements (`<br/>`), as JSX and XHTML require
+    xhtml: bool,
+    // strip the indentation shared by every line of a code block
+    dedent_code: bool,
+    // render plain HTML rather than JSX, see with_html
+    html: bool,
+    // nesting depth of the element being written, used for indentation
+    depth: Cell<usize>,
+    // footnote ids whose first reference has been emitted, so only that
+    // reference carries the back-link anchor
+    referenced_footnotes: RefCell<HashSet<String>>,
+    // how many times each section and heading id has been handed out, so
+    // repeated slugs get a numeric suffix
+    element_ids: RefCell<HashMap<String, usize>>,
+    statement_hooks: Vec<StatementHook>,
 }
 
 impl Generator {
-    pub fn new(input: Program) -> Self {
-        Self { program: input }
+    pub fn new(input: Program, theme: Theme) -> Self {
+        Self {
+            program: input,
+            theme,
+            minify: false,
+            indent: false,
+            collapse_whitespace: true,
+            xhtml: true,
+            dedent_code: false,
+            html: false,
+            depth: Cell::new(0),
+            referenced_footnotes: RefCell::new(HashSet::new()),
+            element_ids: RefCell::new(HashMap::new()),
+            statement_hooks: Vec::new(),
+        }
+    }
+
+    pub fn compile<W: Write>(&mut self, buf: &mut W) -> Result<(), GenerationError> {
+        self.generate(buf)
+    }
+
+    // compile_with hands the output to sink one fragment at a time, an
+    // element or a statement hook's write each, for callers that post-process
+    // it. compile shares the same walk but writes directly, so the writer it
+    // is given still gets flushed after every section.
+    pub fn compile_with(
+        &mut self,
+        sink: impl FnMut(&str) -> Result<(), GenerationError>,
+    ) -> Result<(), GenerationError> {
+        let mut writer = FragmentWriter { sink, error: None };
+        let result = self.generate(&mut writer);
+        match writer.error {
+            Some(err) => Err(err),
+            None => result,
+        }
     }
 
-    pub fn compile<'a, W: Write>(&mut self, buf: &'a mut W) -> Result<(), GenerationError> {
+    fn generate<W: Write>(&self, buf: &mut W) -> Result<(), GenerationError> {
+        self.reset();
+        // the AST walk has no exit events, so a section or paragraph is
+        // closed when the next one starts or the walk ends. Output is flushed
+        // after every section so long documents make steady progress.
+        let mut section_open = false;
+        let mut paragraph_open = false;
         self.program.iter_ast().try_for_each(|node| match node {
-            AstNode::Article(v) => Self::generate_article(buf, &v),
-            AstNode::Section(v) => Self::generate_section(buf, &v),
-            AstNode::Paragraph(v) => Self::generate_paragraph(buf, &v),
-            AstNode::Statement(v) => Self::generate_statement(buf, &v),
+            AstNode::Article(v) => self.at_depth(0, || {
+                self.generate_article(buf, v)?;
+                match self.program.preamble() {
+                    Some(preamble) => self.generate_block(buf, "header", preamble),
+                    None => Ok(()),
+                }
+            }),
+            AstNode::Section(v) => {
+                if std::mem::take(&mut paragraph_open) {
+                    self.at_depth(2, || self.close_paragraph(buf))?;
+                }
+                self.at_depth(1, || {
+                    if std::mem::replace(&mut section_open, true) {
+                        self.close_section(buf)?;
+                        buf.flush()?;
+                    }
+                    self.generate_section(buf, v)
+                })
+            }
+            AstNode::Paragraph(v) => self.at_depth(2, || {
+                if std::mem::replace(&mut paragraph_open, true) {
+                    self.close_paragraph(buf)?;
+                }
+                self.generate_paragraph(buf, v)
+            }),
+            AstNode::Statement(v) => self.at_depth(3, || self.generate_statement(buf, v)),
             AstNode::List(_) => Ok(()),
+        })?;
+        if paragraph_open {
+            self.at_depth(2, || self.close_paragraph(buf))?;
+        }
+        if section_open {
+            self.at_depth(1, || self.close_section(buf))?;
+            buf.flush()?;
+        }
+        self.at_depth(0, || {
+            self.generate_footnotes(buf)?;
+            match self.program.footer() {
+                Some(footer) => self.generate_block(buf, "footer", footer),
+                None => Ok(()),
+            }
         })
     }
 
-    fn write_buf<'a, W: Write>(buf: &'a mut W, s: String) -> Result<(), GenerationError> {
-        write!(buf, "{}\n", s).map_err(|e| GenerationError::from(e.to_string()))
+    // compile_section renders a single section exactly as it appears in the
+    // full output, e.g. to refresh one fragment after an edit. A section the
+    // article calls more than once is rendered as its first call.
+    pub fn compile_section<W: Write>(
+        &self,
+        name: &str,
+        buf: &mut W,
+    ) -> Result<(), GenerationError> {
+        let (_, fragment) = self
+            .sections_in_context()?
+            .into_iter()
+            .find(|(section, _)| section == name)
+            .ok_or_else(|| GenerationError::from(format!("Unknown section: {}", name)))?;
+        buf.write_all(&fragment)?;
+        Ok(())
+    }
+
+    // sections_in_context renders every section the article calls, in order
+    // and each into its own buffer, then those it never calls. Everything is
+    // rendered in a single pass as compile does, so heading ids and footnote
+    // anchors account for whatever precedes each section.
+    fn sections_in_context(&self) -> Result<Vec<(String, Vec<u8>)>, GenerationError> {
+        self.reset();
+        if let Some(preamble) = self.program.preamble() {
+            self.at_depth(0, || {
+                self.generate_block(&mut std::io::sink(), "header", preamble)
+            })?;
+        }
+        let uncalled = self
+            .program
+            .sections()
+            .iter()
+            .filter(|(name, _)| self.program.call_count(name) == 0)
+            .map(|(_, section)| &section.node);
+        let mut fragments = Vec::new();
+        for section in self.program.sections_in_order().chain(uncalled) {
+            let mut buf = Vec::new();
+            self.generate_whole_section(&mut buf, section)?;
+            fragments.push((section.name.clone(), buf));
+        }
+        Ok(fragments)
+    }
+
+    // generate_whole_section renders a section with all its paragraphs and
+    // statements, as the AST walk in generate does
+    fn generate_whole_section<W: Write>(
+        &self,
+        buf: &mut W,
+        section: &SectionDeclaration,
+    ) -> Result<(), GenerationError> {
+        self.at_depth(1, || self.generate_section(buf, section))?;
+        section.paragraphs.iter().try_for_each(|paragraph| {
+            self.at_depth(2, || self.generate_paragraph(buf, paragraph))?;
+            paragraph.statements.iter().try_for_each(|statement| {
+                self.at_depth(3, || self.generate_statement(buf, statement))
+            })?;
+            self.at_depth(2, || self.close_paragraph(buf))
+        })?;
+        self.at_depth(1, || self.close_section(buf))
     }
 
-    fn generate_article<'a, W: Write>(
-        buf: &'a mut W,
+    // compile_diff compares the program against a previous version of it and
+    // reports which regions of the output changed, so a live preview can
+    // re-render just those with compile_section. Sections are matched by
+    // name. One has changed if its statements have, spans aside, or if it
+    // renders differently in the context of the full document, e.g. when an
+    // edit above it shifts its heading ids or footnote anchors. Statement
+    // hooks aren't run for the previous version, they're assumed to render
+    // a statement the same way every time.
+    pub fn compile_diff(&self, previous: &Program) -> Vec<Patch> {
+        let mut patches = Vec::new();
+        let program = &self.program;
+        if program.article() != previous.article()
+            || program.preamble() != previous.preamble()
+            || program.footer() != previous.footer()
+            || program.footnotes() != previous.footnotes()
+            || program.footnote_refs() != previous.footnote_refs()
+        {
+            patches.push(Patch::Article);
+        }
+        // a section that fails to render is compared by its statements alone
+        let rendered = |generator: &Generator| -> HashMap<String, Vec<u8>> {
+            let mut rendered = HashMap::new();
+            for (name, fragment) in generator.sections_in_context().unwrap_or_default() {
+                rendered.entry(name).or_insert(fragment);
+            }
+            rendered
+        };
+        let now = rendered(self);
+        let before = rendered(&self.with_program(previous.clone()));
+        for (name, section) in program.sections().iter() {
+            match previous.sections().get(name) {
+                None => patches.push(Patch::Added(name.clone())),
+                Some(old) if old != section || now.get(name) != before.get(name) => {
+                    patches.push(Patch::Changed(name.clone()))
+                }
+                Some(_) => {}
+            }
+        }
+        for (name, _) in previous.sections().iter() {
+            if !program.sections().contains_key(name) {
+                patches.push(Patch::Removed(name.clone()));
+            }
+        }
+        patches
+    }
+
+    // with_program is a generator for program configured as this one is,
+    // without the statement hooks
+    fn with_program(&self, program: Program) -> Generator {
+        Generator {
+            program,
+            theme: self.theme.clone(),
+            minify: self.minify,
+            indent: self.indent,
+            collapse_whitespace: self.collapse_whitespace,
+            xhtml: self.xhtml,
+            dedent_code: self.dedent_code,
+            html: self.html,
+            depth: Cell::new(0),
+            referenced_footnotes: RefCell::new(HashSet::new()),
+            element_ids: RefCell::new(HashMap::new()),
+            statement_hooks: Vec::new(),
+        }
+    }
+
+    // compile_to_string compiles into an in-memory buffer, for callers that
+    // want the output as a String rather than written to a sink
+    pub fn compile_to_string(&mut self) -> Result<String, GenerationError> {
+        let mut buf = Vec::new();
+        self.compile(&mut buf)?;
+        String::from_utf8(buf).map_err(|e| GenerationError::from(e.to_string()))
+    }
+
+    // builder style toggle for minified output, which drops the newline
+    // written after every element
+    pub fn with_minify(mut self, minify: bool) -> Self {
+        self.minify = minify;
+        self
+    }
+
+    // builder style toggle for indenting each element by its depth in the AST.
+    // Has no effect on minified output.
+    pub fn with_indent(mut self, indent: bool) -> Self {
+        self.indent = indent;
+        self
+    }
+
+    // builder style toggle for collapsing each run of whitespace in a text
+    // block to a single space. On by default, code blocks are never touched.
+    pub fn with_collapse_whitespace(mut self, collapse_whitespace: bool) -> Self {
+        self.collapse_whitespace = collapse_whitespace;
+        self
+    }
+
+    // builder style toggle for self-closing void elements. On by default,
+    // since JSX rejects an unclosed `<br>`, turn it off for plain HTML.
+    pub fn with_xhtml(mut self, xhtml: bool) -> Self {
+        self.xhtml = xhtml;
+        self
+    }
+
+    // builder style toggle for dedenting code blocks, so code indented to
+    // line up with the surrounding source renders flush left
+    pub fn with_dedent_code(mut self, dedent_code: bool) -> Self {
+        self.dedent_code = dedent_code;
+        self
+    }
+
+    // builder style toggle for plain HTML output. Off by default, JSX sets
+    // classes with className and takes code blocks as template literals,
+    // HTML uses class and escapes code blocks as any other text.
+    pub fn with_html(mut self, html: bool) -> Self {
+        self.html = html;
+        self
+    }
+
+    // on_statement registers a hook to run after every statement, including
+    // those nested in columns. Hooks run in the order they're registered.
+    pub fn on_statement(mut self, hook: StatementHook) -> Self {
+        self.statement_hooks.push(hook);
+        self
+    }
+
+    fn write_buf<W: Write>(&self, buf: &mut W, s: String) -> Result<(), GenerationError> {
+        let separator = if self.minify { "" } else { "\n" };
+        let indentation = if self.indent && !self.minify {
+            "  ".repeat(self.depth.get())
+        } else {
+            String::new()
+        };
+        // a single write, so compile_with sees the element as one fragment
+        buf.write_all(format!("{}{}{}", indentation, s, separator).as_bytes())
+            .map_err(|e| GenerationError::from(e.to_string()))
+    }
+
+    fn at_depth<T>(&self, depth: usize, f: impl FnOnce() -> T) -> T {
+        let previous = self.depth.replace(depth);
+        let out = f();
+        self.depth.set(previous);
+        out
+    }
+
+    // runs f one level deeper than the current element
+    fn nested<T>(&self, f: impl FnOnce() -> T) -> T {
+        self.at_depth(self.depth.get() + 1, f)
+    }
+
+    fn generate_article<W: Write>(
+        &self,
+        buf: &mut W,
         article: &ArticleDeclaration,
     ) -> Result<(), GenerationError> {
-        Self::write_buf(
+        self.write_buf(
             buf,
-            format!(r"<h1 className='text-4xl font-bold'>{}</h1>", article.name),
+            format!(
+                r"<h1 {}>{}</h1>",
+                self.class(&self.theme.title),
+                article.name
+            ),
         )
     }
 
-    fn generate_section<'a, W: Write>(
-        buf: &'a mut W,
-        _: &SectionDeclaration,
+    fn generate_section<W: Write>(
+        &self,
+        buf: &mut W,
+        section: &SectionDeclaration,
     ) -> Result<(), GenerationError> {
-        Self::write_buf(buf, "<br/>".to_string())
+        let id = self.element_id(&section.name);
+        self.write_buf(
+            buf,
+            format!(
+                "<{} id='{}'{}>",
+                self.theme.section_tag,
+                id,
+                self.class_attr(&self.theme.section)
+            ),
+        )
+    }
+
+    fn close_section<W: Write>(&self, buf: &mut W) -> Result<(), GenerationError> {
+        self.write_buf(buf, format!("</{}>", self.theme.section_tag))
     }
 
-    fn generate_paragraph<'a, W: Write>(
-        buf: &'a mut W,
+    // reset clears the state of a previous compile. Explicit heading ids are
+    // reserved up front so an auto id never takes one, wherever it appears.
+    fn reset(&self) {
+        self.referenced_footnotes.borrow_mut().clear();
+        let mut ids = self.element_ids.borrow_mut();
+        ids.clear();
+        let statements = self
+            .program
+            .sections()
+            .iter()
+            .flat_map(|(_, section)| section.paragraphs.iter())
+            .flat_map(|paragraph| paragraph.statements_deep());
+        for (statement, _) in statements {
+            if let Statement::Heading(_, Some(id), _) = statement {
+                ids.insert(id.clone(), 1);
+            }
+        }
+    }
+
+    // element_id slugifies a section name or heading text, suffixing repeats
+    // (intro, intro-2) so every id in the document is unique. A name with
+    // nothing to slugify, such as one written entirely in CJK, becomes
+    // "anchor".
+    fn element_id(&self, name: &str) -> String {
+        let slug = match slugify(name) {
+            slug if slug.is_empty() => "anchor".to_string(),
+            slug => slug,
+        };
+        let mut ids = self.element_ids.borrow_mut();
+        let count = ids.entry(slug.clone()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            slug
+        } else {
+            format!("{}-{}", slug, count)
+        }
+    }
+
+    // class renders the attribute setting class, className unless the
+    // output is plain HTML
+    fn class(&self, class: &str) -> String {
+        let name = if self.html { "class" } else { "className" };
+        format!("{}='{}'", name, class)
+    }
+
+    // class_attr renders a class attribute, or nothing for an empty class
+    fn class_attr(&self, class: &str) -> String {
+        if class.is_empty() {
+            String::new()
+        } else {
+            format!(" {}", self.class(class))
+        }
+    }
+
+    // void_element writes an element that has no content or closing tag,
+    // self-closed unless xhtml is off
+    fn void_element(&self, element: &str) -> String {
+        if self.xhtml {
+            format!("<{}/>", element)
+        } else {
+            format!("<{}>", element)
+        }
+    }
+
+    fn generate_paragraph<W: Write>(
+        &self,
+        buf: &mut W,
         _: &Paragraph,
     ) -> Result<(), GenerationError> {
-        Self::write_buf(buf, "<br/>".to_string())
+        match self.theme.paragraph_tag.as_str() {
+            "" => self.write_buf(buf, self.void_element("br")),
+            tag => self.write_buf(
+                buf,
+                format!("<{}{}>", tag, self.class_attr(&self.theme.paragraph)),
+            ),
+        }
+    }
+
+    // closes the paragraph's wrapper, if it has one
+    fn close_paragraph<W: Write>(&self, buf: &mut W) -> Result<(), GenerationError> {
+        match self.theme.paragraph_tag.as_str() {
+            "" => Ok(()),
+            tag => self.write_buf(buf, format!("</{}>", tag)),
+        }
+    }
+
+    fn generate_statement<W: Write>(
+        &self,
+        buf: &mut W,
+        statement: &Statement,
+    ) -> Result<(), GenerationError> {
+        self.render_statement(buf, statement)?;
+        self.statement_hooks
+            .iter()
+            .try_for_each(|hook| hook(statement, buf))
     }
 
-    fn generate_statement<'a, W: Write>(
-        buf: &'a mut W,
+    fn render_statement<W: Write>(
+        &self,
+        buf: &mut W,
         statement: &Statement,
     ) -> Result<(), GenerationError> {
         match statement {
-            Statement::Heading(_, c) => Self::write_buf(
+            Statement::Heading(tag, id, c) => {
+                let id = id.clone().unwrap_or_else(|| self.element_id(c));
+                self.write_buf(
+                    buf,
+                    format!(
+                        "<{} id='{}' {}>{}</{}>",
+                        tag,
+                        escape_html(&id),
+                        self.class(&self.theme.heading),
+                        escape_html(c),
+                        tag
+                    ),
+                )
+            }
+            Statement::TextBlock(c) if self.collapse_whitespace => self.write_buf(
                 buf,
-                format!("<h3 className='text-3xl'>{}</h3>", c.to_string()),
+                format!("<p>{}</p>", format_inline(&collapse_whitespace(c))),
             ),
-            Statement::TextBlock(c) => Self::write_buf(buf, format!("<p>{}</p>", c.to_string())),
-            Statement::CodeBlock(c) => Self::write_buf(
+            Statement::TextBlock(c) => self.write_buf(buf, format!("<p>{}</p>", format_inline(c))),
+            // JSX takes the code as a template literal so whitespace survives,
+            // which means it has to be escaped as one. HTML keeps whitespace
+            // inside <pre> as it is.
+            Statement::CodeBlock(c) => {
+                let code = if self.dedent_code {
+                    dedent(c)
+                } else {
+                    c.clone()
+                };
+                let code = if self.html {
+                    escape_html(&code)
+                } else {
+                    format!("{{`{}`}}", escape_template_literal(&code))
+                };
+                self.write_buf(
+                    buf,
+                    format!(
+                        r"<pre {}><code>{}</code></pre>",
+                        self.class(&self.theme.code),
+                        code
+                    ),
+                )
+            }
+            Statement::Aside(c) if self.minify || self.indent => self.write_buf(
                 buf,
                 format!(
-                    r"<pre className='w-full overflow-x-auto'><code>{{`{}`}}</code></pre>",
-                    c.to_string()
+                    "<div {}><p>{}</p></div>",
+                    self.class(&self.theme.aside),
+                    escape_html(c)
                 ),
             ),
-            Statement::Aside(c) => Self::write_buf(
+            Statement::Aside(c) => self.write_buf(
                 buf,
                 format!(
                     r"
-            <div className='p-8 bg-opacity-10 bg-black italic'>
+            <div {}>
                 <p>{}</p>
             </div>
             ",
-                    c.to_string()
+                    self.class(&self.theme.aside),
+                    escape_html(c)
+                ),
+            ),
+            // \( and \) are the inline delimiters KaTeX's auto-render looks for.
+            Statement::Math(c) => self.write_buf(
+                buf,
+                format!(
+                    r"<span {}>\({}\)</span>",
+                    self.class(&self.theme.math),
+                    escape_html(c)
                 ),
             ),
-            Statement::List(l) => Self::generate_list(buf, l),
+            // Deliberately unescaped, see Statement::RawHtml.
+            Statement::RawHtml(c) => self.write_buf(buf, c.to_string()),
+            Statement::Embed(url) => match embed_src(url) {
+                Some(src) => self.write_buf(
+                    buf,
+                    format!(
+                        "<div {}><iframe src='{}' {} {}></iframe></div>",
+                        self.class(&self.theme.embed),
+                        escape_attr(&src),
+                        self.class("w-full h-full"),
+                        if self.html {
+                            "allowfullscreen"
+                        } else {
+                            "allowFullScreen"
+                        }
+                    ),
+                ),
+                // unknown providers get a plain link, or just the text for a
+                // URL that isn't safe to link to
+                None if safe_url(url) => self.write_buf(
+                    buf,
+                    format!(
+                        "<p><a href='{}'>{}</a></p>",
+                        escape_attr(url),
+                        escape_html(url)
+                    ),
+                ),
+                None => self.write_buf(buf, format!("<p>{}</p>", escape_html(url))),
+            },
+            Statement::List(l) => self.generate_list(buf, l),
+            Statement::FootnoteRef(id) => self.generate_footnote_ref(buf, id),
+            // Rendered at the end of the document, see generate_footnotes.
+            Statement::FootnoteDef(..) => Ok(()),
+            Statement::Columns(columns) => self.generate_columns(buf, columns),
+            Statement::Anchor(id) => {
+                self.write_buf(buf, format!("<span id='{}'></span>", escape_attr(id)))
+            }
+        }
+    }
+
+    // renders the preamble or footer, wrapped in the given element
+    fn generate_block<W: Write>(
+        &self,
+        buf: &mut W,
+        tag: &str,
+        block: &Paragraph,
+    ) -> Result<(), GenerationError> {
+        self.write_buf(buf, format!("<{}>", tag))?;
+        self.nested(|| {
+            block
+                .statements
+                .iter()
+                .try_for_each(|statement| self.generate_statement(buf, statement))
+        })?;
+        self.write_buf(buf, format!("</{}>", tag))
+    }
+
+    // columns are rendered here in full rather than through the AST walk,
+    // since every column needs a closing tag
+    fn generate_columns<W: Write>(
+        &self,
+        buf: &mut W,
+        columns: &[Spanned<Paragraph>],
+    ) -> Result<(), GenerationError> {
+        if columns.is_empty() {
+            return Ok(());
+        }
+        self.write_buf(buf, format!("<div {}>", self.class(&self.theme.columns)))?;
+        self.nested(|| {
+            columns.iter().try_for_each(|column| {
+                self.write_buf(buf, format!("<div {}>", self.class(&self.theme.column)))?;
+                self.nested(|| {
+                    column
+                        .statements
+                        .iter()
+                        .try_for_each(|statement| self.generate_statement(buf, statement))
+                })?;
+                self.write_buf(buf, "</div>".to_string())
+            })
+        })?;
+        self.write_buf(buf, "</div>".to_string())
+    }
+
+    fn generate_footnote_ref<W: Write>(
+        &self,
+        buf: &mut W,
+        id: &str,
+    ) -> Result<(), GenerationError> {
+        let number = self
+            .program
+            .footnote_refs()
+            .iter()
+            .position(|r| *r == id)
+            .map_or(0, |idx| idx + 1);
+        let anchor = if self
+            .referenced_footnotes
+            .borrow_mut()
+            .insert(id.to_string())
+        {
+            format!(" id='fnref-{}'", id)
+        } else {
+            String::new()
+        };
+        self.write_buf(
+            buf,
+            format!("<sup{}><a href='#fn-{}'>{}</a></sup>", anchor, id, number),
+        )
+    }
+
+    // lists the referenced footnotes in reference order, each linking back
+    // to its first reference
+    fn generate_footnotes<W: Write>(&self, buf: &mut W) -> Result<(), GenerationError> {
+        let refs = self.program.footnote_refs();
+        if refs.is_empty() {
+            return Ok(());
         }
+        self.write_buf(
+            buf,
+            format!("<section {}>", self.class(&self.theme.footnotes)),
+        )?;
+        self.nested(|| {
+            self.write_buf(buf, "<ol>".to_string())?;
+            self.nested(|| {
+                refs.iter().try_for_each(|id| {
+                    self.write_buf(
+                        buf,
+                        format!(
+                            "<li id='fn-{}'>{} <a href='#fnref-{}'>&#8617;</a></li>",
+                            id,
+                            escape_html(self.program.footnote(id).unwrap_or_default()),
+                            id
+                        ),
+                    )
+                })
+            })?;
+            self.write_buf(buf, "</ol>".to_string())
+        })?;
+        self.write_buf(buf, "</section>".to_string())
+    }
+
+    fn generate_list<W: Write>(&self, buf: &mut W, list: &List) -> Result<(), GenerationError> {
+        let (open, items, close) = match list {
+            List::Checklist(items) => return self.generate_checklist(buf, items),
+            List::Ordered(items) => (
+                format!("<ol {}>", self.class(&self.theme.ordered_list)),
+                items,
+                "</ol>",
+            ),
+            List::Unordered(items) => (
+                format!("<ul {}>", self.class(&self.theme.unordered_list)),
+                items,
+                "</ul>",
+            ),
+        };
+        self.write_buf(buf, open)?;
+        self.nested(|| {
+            items.iter().try_for_each(|item| {
+                self.write_buf(buf, format!("<li>{}</li>", format_inline(item)))
+            })
+        })?;
+        self.write_buf(buf, close.to_string())
     }
 
-    fn generate_list<'a, W: Write>(buf: &'a mut W, list: &List) -> Result<(), GenerationError> {
-        match list {
-            List::Ordered(items) => {
-                Self::write_buf(
+    // a checklist's done state is shown by a checkbox the reader can't toggle
+    fn generate_checklist<W: Write>(
+        &self,
+        buf: &mut W,
+        items: &[(bool, String)],
+    ) -> Result<(), GenerationError> {
+        self.write_buf(buf, format!("<ul {}>", self.class(&self.theme.checklist)))?;
+        self.nested(|| {
+            items.iter().try_for_each(|(done, item)| {
+                let checked = if *done { " checked" } else { "" };
+                let input = format!("input type='checkbox'{} disabled", checked);
+                self.write_buf(
                     buf,
-                    format!("<ol className='list-inside list-decimal px-8'>"),
-                )?;
-                for item in items {
-                    Self::write_buf(buf, format!("<li>{}</li>", item))?;
-                }
-                Self::write_buf(buf, format!("</ol>"))?;
+                    format!(
+                        "<li>{} {}</li>",
+                        self.void_element(&input),
+                        format_inline(item)
+                    ),
+                )
+            })
+        })?;
+        self.write_buf(buf, "</ul>".to_string())
+    }
+}
+
+// embed_src maps the URL of a video on a known provider to the URL of its
+// embeddable player, YouTube and Vimeo are recognised
+fn embed_src(url: &str) -> Option<String> {
+    let rest = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))?;
+    let rest = rest
+        .strip_prefix("www.")
+        .or_else(|| rest.strip_prefix("m."))
+        .unwrap_or(rest);
+    let (host, path) = rest.split_once('/')?;
+    let is_id = |id: &str| {
+        !id.is_empty()
+            && id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+    let id = match host {
+        "youtube.com" => match path.strip_prefix("watch?") {
+            Some(query) => query.split('&').find_map(|param| param.strip_prefix("v=")),
+            None => path.strip_prefix("embed/"),
+        },
+        "youtu.be" => path.split(['?', '#']).next(),
+        "vimeo.com" => path.split(['?', '#']).next(),
+        _ => None,
+    }
+    .filter(|id| is_id(id))?;
+    Some(match host {
+        "vimeo.com" => format!("https://player.vimeo.com/video/{}", id),
+        _ => format!("https://www.youtube.com/embed/{}", id),
+    })
+}
+
+// format_inline escapes text for element content, then turns `*emphasis*`
+// into <em> and `[text](url)` into links. Escaping comes first so the
+// markup added here is the only markup in the result.
+pub fn format_inline(s: &str) -> String {
+    substitute_inline(&escape_html(s))
+}
+
+fn substitute_inline(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find(['*', '[']) {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let replaced = if rest.starts_with('*') {
+            emphasis(rest)
+        } else {
+            link(rest)
+        };
+        let len = match replaced {
+            Some((html, len)) => {
+                out.push_str(&html);
+                len
             }
-            List::Unordered(items) => {
-                Self::write_buf(buf, format!("<ul className='list-disc list-inside px-8'>"))?;
-                for item in items {
-                    Self::write_buf(buf, format!("<li>{}</li>", item))?;
-                }
-                Self::write_buf(buf, format!("</ul>"))?;
+            None => {
+                out.push_str(&rest[..1]);
+                1
             }
+        };
+        rest = &rest[len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// emphasis renders the `*text*` s starts with, returning the markup and how
+// much of s it replaces. Like Markdown, the text can't start or end with
+// whitespace, so a lone `a * b` is left alone.
+fn emphasis(s: &str) -> Option<(String, usize)> {
+    let end = s[1..].find('*')? + 1;
+    let inner = &s[1..end];
+    if inner.is_empty()
+        || inner.starts_with(char::is_whitespace)
+        || inner.ends_with(char::is_whitespace)
+    {
+        return None;
+    }
+    Some((format!("<em>{}</em>", substitute_inline(inner)), end + 1))
+}
+
+// link renders the `[text](url)` s starts with, as emphasis does
+fn link(s: &str) -> Option<(String, usize)> {
+    let (text, url, len) = parse_link(s)?;
+    Some((
+        format!(
+            "<a href='{}'>{}</a>",
+            url.replace('\'', "&#39;"),
+            substitute_inline(text)
+        ),
+        len,
+    ))
+}
+
+// parse_link splits the `[text](url)` s starts with into its text, its URL
+// and how much of s it spans. A URL that isn't safe_url isn't a link.
+pub(super) fn parse_link(s: &str) -> Option<(&str, &str, usize)> {
+    let close = s.find("](")?;
+    let text = &s[1..close];
+    let end = s[close + 2..].find(')')? + close + 2;
+    let url = &s[close + 2..end];
+    if text.is_empty() || url.is_empty() || url.contains(char::is_whitespace) || !safe_url(url) {
+        return None;
+    }
+    Some((text, url, end + 1))
+}
+
+// safe_url allows the URLs a post links to: http(s) and mailto, relative
+// paths and fragments. Any other scheme, such as `javascript:`, could run
+// something when the link is clicked.
+pub(super) fn safe_url(url: &str) -> bool {
+    match url.split_once(':') {
+        // a colon after a path, query or fragment has started isn't a scheme
+        Some((scheme, _)) if !scheme.contains(['/', '?', '#']) => ["http", "https", "mailto"]
+            .iter()
+            .any(|allowed| scheme.eq_ignore_ascii_case(allowed)),
+        _ => true,
+    }
+}
+
+// escape_html replaces the characters that would otherwise be interpreted
+// as markup in element content.
+pub fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// escape_attr escapes s for use in a single quoted attribute value
+fn escape_attr(s: &str) -> String {
+    escape_html(s).replace('\'', "&#39;")
+}
+
+// escape_template_literal escapes s for use between the backticks of a
+// JavaScript template literal, where a backtick would end the literal, `${`
+// would start an interpolation and a backslash would start an escape.
+pub fn escape_template_literal(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('`', "\\`")
+        .replace("${", "\\${")
+}
+
+// FragmentWriter passes each write on to a compile_with sink. The generator
+// only ever writes whole strings, so a write never splits a char. The sink's
+// error is kept to be returned as is rather than through io::Error.
+struct FragmentWriter<F> {
+    sink: F,
+    error: Option<GenerationError>,
+}
+
+impl<F: FnMut(&str) -> Result<(), GenerationError>> Write for FragmentWriter<F> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let fragment = std::str::from_utf8(buf)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        if let Err(err) = (self.sink)(fragment) {
+            let msg = err.msg.clone();
+            self.error = Some(err);
+            return Err(std::io::Error::other(msg));
         }
+        Ok(buf.len())
+    }
 
+    fn flush(&mut self) -> std::io::Result<()> {
         Ok(())
     }
 }
 
+// Patch names a region of the output that differs between two compiles
+#[derive(Debug, Clone, PartialEq)]
+pub enum Patch {
+    // something outside the sections changed (the article's calls, the
+    // preamble, footer or footnotes), so the whole page needs re-rendering
+    Article,
+    Added(String),
+    Changed(String),
+    Removed(String),
+}
+
 #[derive(Debug)]
 pub struct GenerationError {
     pub msg: String,
@@ -155,3 +969,509 @@ impl From<GenerationError> for BloggerError {
         BloggerError::CodegenError(value.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{
+        escape_template_literal, format_inline, safe_url, GenerationError, Generator, Patch,
+    };
+    use crate::{
+        backend::theme::Theme,
+        lexer::{lexer::Lexer, tokens::token_specs},
+        parser::parser::{Parser, Statement},
+    };
+
+    fn build(src: &str, theme: Theme) -> Generator {
+        let src = src.to_string();
+        let lexer = Lexer::new(&src, token_specs());
+        let program = Parser::new(lexer, &src, Path::new("")).parse().unwrap();
+        Generator::new(program, theme)
+    }
+
+    fn run(mut generator: Generator) -> String {
+        let mut buf = Vec::new();
+        generator.compile(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    fn compile_with_theme(src: &str, theme: Theme) -> String {
+        run(build(src, theme))
+    }
+
+    fn compile(src: &str) -> String {
+        compile_with_theme(src, Theme::default())
+    }
+
+    #[test]
+    fn test_raw_html_is_not_escaped() {
+        let out = compile(
+            "article a { s } section s { paragraph { html { `<iframe src=\"x\"></iframe>` } `<b>` } }",
+        );
+        assert!(out.contains("<iframe src=\"x\"></iframe>"));
+        assert!(out.contains("<p>&lt;b&gt;</p>"));
+    }
+
+    #[test]
+    fn test_math_block() {
+        let out = compile("article a { s } section s { paragraph { math { `e^{i\\pi} < 0` } } }");
+        assert!(out.contains(r"<span className='math'>\(e^{i\pi} &lt; 0\)</span>"));
+    }
+
+    #[test]
+    fn test_theme_overrides_heading_class() {
+        let theme = Theme::from_json(r#"{"heading": "my-heading"}"#).unwrap();
+        let out = compile_with_theme(
+            "article a { s } section s { paragraph { h1 { Title } } }",
+            theme,
+        );
+        assert!(out.contains("<h1 id='title' className='my-heading'>Title</h1>"));
+    }
+
+    #[test]
+    fn test_footnotes_in_reference_order() {
+        let out = compile(
+            "article a { s } section s { paragraph { `x` footnote{b} `y` footnote{a} footnote{b} footnote{a}{`Alpha`} footnote{b}{`Beta`} } }",
+        );
+        assert!(out.contains("<sup id='fnref-b'><a href='#fn-b'>1</a></sup>"));
+        assert!(out.contains("<sup id='fnref-a'><a href='#fn-a'>2</a></sup>"));
+        assert!(out.contains("<sup><a href='#fn-b'>1</a></sup>"));
+        let beta = out
+            .find("<li id='fn-b'>Beta <a href='#fnref-b'>&#8617;</a></li>")
+            .unwrap();
+        let alpha = out
+            .find("<li id='fn-a'>Alpha <a href='#fnref-a'>&#8617;</a></li>")
+            .unwrap();
+        assert!(beta < alpha);
+        assert!(out.trim_end().ends_with("</section>"));
+    }
+
+    #[test]
+    fn test_columns() {
+        let out = compile(
+            "article a { s } section s { paragraph { columns { paragraph { `left` } paragraph { `right` } } } }",
+        );
+        assert!(out.contains(
+            "<div className='grid grid-flow-col gap-8'>\n<div className='column'>\n<p>left</p>\n</div>\n<div className='column'>\n<p>right</p>\n</div>\n</div>\n"
+        ));
+
+        let out = compile("article a { s } section s { paragraph { columns { } } }");
+        assert!(!out.contains("column"));
+    }
+
+    #[test]
+    fn test_minify() {
+        let src = "article a { s } section s { paragraph { h1 { Hi } `text` aside { `note` } ul { li { x } } } }";
+        let normal = compile(src);
+        let minified = run(build(src, Theme::default()).with_minify(true));
+        assert!(minified.len() < normal.len());
+        assert!(!minified.contains('\n'));
+        assert!(minified
+            .contains("<div className='p-8 bg-opacity-10 bg-black italic'><p>note</p></div>"));
+        assert_eq!(
+            normal.split_whitespace().collect::<String>(),
+            minified.split_whitespace().collect::<String>()
+        );
+    }
+
+    #[test]
+    fn test_indent_golden() {
+        let src = "article a { s } section s { paragraph { h1 { Hi } ul { li { x } } } }";
+        let out = run(build(src, Theme::default()).with_indent(true));
+        let expected = "<h1 className='text-4xl font-bold'>a</h1>
+  <section id='s'>
+    <br/>
+      <h1 id='hi' className='text-3xl'>Hi</h1>
+      <ul className='list-disc list-inside px-8'>
+        <li>x</li>
+      </ul>
+  </section>
+";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_section_ids_are_unique() {
+        let out = compile(
+            "article a { Intro intro } section Intro { paragraph { x } } section intro { paragraph { y } }",
+        );
+        assert!(out.contains("<section id='intro'>\n<br/>\n<p>x</p>\n</section>"));
+        assert!(out.contains("<section id='intro-2'>\n<br/>\n<p>y</p>\n</section>"));
+    }
+
+    #[test]
+    fn test_code_block_keeps_indentation() {
+        let out =
+            compile("article a { s } section s { paragraph { code {`\n  indented\n\tline\n`} } }");
+        assert!(out.contains("<code>{`\n  indented\n\tline\n`}</code>"));
+    }
+
+    #[test]
+    fn test_text_whitespace_is_collapsed() {
+        let src = "article a { s } section s { paragraph {\n    `\n        Some   hand\n        formatted text.\n    `\n    code {`keep   this`}\n} }";
+        let out = compile(src);
+        assert!(out.contains("<p>Some hand formatted text.</p>"));
+        assert!(out.contains("{`keep   this`}"));
+
+        let out = run(build(src, Theme::default()).with_collapse_whitespace(false));
+        assert!(out.contains("<p>\n        Some   hand\n        formatted text.\n    </p>"));
+    }
+
+    #[test]
+    fn test_list_items_are_formatted() {
+        let out = compile(
+            "article a { s } section s { paragraph { ul { li { `see *this*` } li { `a <b> & [the docs](https://x.dev/?a=1&b=2)` } } ol { li { `*<i>*` } li { `2 * 3 * 4` } } } }",
+        );
+        assert!(out.contains("<li>see <em>this</em></li>"));
+        assert!(out.contains(
+            "<li>a &lt;b&gt; &amp; <a href='https://x.dev/?a=1&amp;b=2'>the docs</a></li>"
+        ));
+        assert!(out.contains("<li><em>&lt;i&gt;</em></li>"));
+        assert!(out.contains("<li>2 * 3 * 4</li>"));
+
+        let out =
+            compile("article a { s } section s { paragraph { cl { li(x) { `*done* <ok>` } } } }");
+        assert!(out.contains("disabled/> <em>done</em> &lt;ok&gt;</li>"));
+    }
+
+    #[test]
+    fn test_void_elements() {
+        let src = "article a { s } section s { paragraph { cl { li(x) { done } } } }";
+        let out = compile(src);
+        assert!(out.contains("<br/>"));
+        assert!(out.contains("<li><input type='checkbox' checked disabled/> done</li>"));
+
+        let out = run(build(src, Theme::default()).with_xhtml(false));
+        assert!(out.contains("<br>\n"));
+        assert!(out.contains("<li><input type='checkbox' checked disabled> done</li>"));
+        assert!(!out.contains("/>"));
+    }
+
+    #[test]
+    fn test_compile_to_string() {
+        let src = "article a { s } section s { paragraph { h1 { Hi } `text` footnote{n} footnote{n}{`note`} } }";
+        let mut generator = build(src, Theme::default());
+        let from_string = generator.compile_to_string().unwrap();
+        assert_eq!(from_string, compile(src));
+        // compiling again starts from a clean slate
+        assert_eq!(generator.compile_to_string().unwrap(), from_string);
+    }
+
+    #[test]
+    fn test_compile_section_matches_full_output() {
+        let src = "article a { one two } section one { paragraph { h1 { First } `x` } } section two { paragraph { `y` ul { li { z } } } paragraph { `w` } }";
+        let full = compile(src);
+        let generator = build(src, Theme::default());
+        for name in ["one", "two"] {
+            let mut buf = Vec::new();
+            generator.compile_section(name, &mut buf).unwrap();
+            let fragment = String::from_utf8(buf).unwrap();
+            assert!(fragment.starts_with(&format!("<section id='{}'>", name)));
+            assert!(full.contains(&fragment), "{}", fragment);
+        }
+        assert!(generator.compile_section("three", &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_compile_section_in_document_context() {
+        // the second section's heading slug collides with the first's, and
+        // the footnote's first reference is in the first section
+        let src = "article a { one two } section one { paragraph { h2 { Intro } `x` footnote{n} } } section two { paragraph { h2 { Intro } footnote{n} footnote{n}{`note`} } }";
+        let full = compile(src);
+        let generator = build(src, Theme::default());
+        let mut buf = Vec::new();
+        generator.compile_section("two", &mut buf).unwrap();
+        let fragment = String::from_utf8(buf).unwrap();
+        assert!(fragment.contains("<h2 id='intro-2'"), "{}", fragment);
+        assert!(
+            fragment.contains("<sup><a href='#fn-n'>1</a></sup>"),
+            "{}",
+            fragment
+        );
+        assert!(!fragment.contains("fnref"));
+        assert!(full.contains(&fragment), "{}", fragment);
+
+        let mut buf = Vec::new();
+        generator.compile_section("one", &mut buf).unwrap();
+        let fragment = String::from_utf8(buf).unwrap();
+        assert!(fragment.contains("<h2 id='intro'"));
+        assert!(fragment.contains("<sup id='fnref-n'>"));
+        assert!(full.contains(&fragment), "{}", fragment);
+    }
+
+    #[test]
+    fn test_code_block_escapes_template_literal() {
+        assert_eq!(
+            escape_template_literal(r"let s = `${x}\n`;"),
+            r"let s = \`\${x}\\n\`;"
+        );
+        let out =
+            compile(r#"article a { s } section s { paragraph { code {`printf("${x}\n");`} } }"#);
+        assert!(out.contains(r#"<code>{`printf("\${x}\\n");`}</code>"#));
+    }
+
+    #[test]
+    fn test_compile_with_sink() {
+        let src = "article a { s } section s { paragraph { `one` `two` } }";
+        let mut fragments = Vec::new();
+        build(src, Theme::default())
+            .compile_with(|fragment| {
+                fragments.push(fragment.to_string());
+                Ok(())
+            })
+            .unwrap();
+        assert!(fragments.contains(&"<p>one</p>\n".to_string()));
+        assert!(fragments.contains(&"<p>two</p>\n".to_string()));
+        assert_eq!(fragments.concat(), compile(src));
+
+        // the sink can rewrite fragments on the way, or stop the compile
+        let mut out = String::new();
+        build(src, Theme::default())
+            .compile_with(|fragment| {
+                out.push_str(&fragment.replace("<p>", "<p class='x'>"));
+                Ok(())
+            })
+            .unwrap();
+        assert!(out.contains("<p class='x'>two</p>"));
+        let err = build(src, Theme::default())
+            .compile_with(|fragment| {
+                if fragment.contains("two") {
+                    return Err(GenerationError::from("rejected"));
+                }
+                Ok(())
+            })
+            .unwrap_err();
+        assert_eq!(err.msg, "rejected");
+    }
+
+    #[test]
+    fn test_flushes_after_each_section() {
+        // records the length of the output at every flush
+        struct FlushRecorder {
+            out: Vec<u8>,
+            flushes: Vec<usize>,
+        }
+
+        impl std::io::Write for FlushRecorder {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.out.write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                self.flushes.push(self.out.len());
+                Ok(())
+            }
+        }
+
+        let mut recorder = FlushRecorder {
+            out: Vec::new(),
+            flushes: Vec::new(),
+        };
+        build(
+            "article a { s t u } section s { paragraph { x } } section t { paragraph { y } } section u { paragraph { z } }",
+            Theme::default(),
+        )
+        .compile(&mut recorder)
+        .unwrap();
+        assert_eq!(recorder.flushes.len(), 3);
+        let out = String::from_utf8(recorder.out).unwrap();
+        for flushed_at in recorder.flushes {
+            assert!(out[..flushed_at].ends_with("</section>\n"));
+        }
+    }
+
+    #[test]
+    fn test_preamble_and_footer() {
+        let out = compile(
+            "preamble { `Draft` } article a { s } section s { paragraph { x } } footer { `Thanks` }",
+        );
+        assert!(out.starts_with(
+            "<h1 className='text-4xl font-bold'>a</h1>\n<header>\n<p>Draft</p>\n</header>\n<section id='s'>"
+        ));
+        assert!(out.ends_with("</section>\n<footer>\n<p>Thanks</p>\n</footer>\n"));
+    }
+
+    #[test]
+    fn test_heading_ids() {
+        let out = compile(
+            "article a { s } section s { paragraph { h2(intro-section) { Getting Started } h2 { Getting Started } } }",
+        );
+        assert!(out.contains("<h2 id='intro-section' className='text-3xl'>Getting Started</h2>"));
+        assert!(out.contains("<h2 id='getting-started' className='text-3xl'>Getting Started</h2>"));
+
+        let out = compile("article a { s } section s { paragraph { h2 { 你好 } } }");
+        assert!(out.contains("<h2 id='anchor' className='text-3xl'>你好</h2>"));
+    }
+
+    #[test]
+    fn test_heading_levels() {
+        let out = compile(
+            "article a { s } section s { paragraph { h4 { Four } h5 { Five } h6 { Six } } }",
+        );
+        assert!(out.contains("<h4 id='four' className='text-3xl'>Four</h4>"));
+        assert!(out.contains("<h5 id='five' className='text-3xl'>Five</h5>"));
+        assert!(out.contains("<h6 id='six' className='text-3xl'>Six</h6>"));
+    }
+
+    #[test]
+    fn test_statement_hooks() {
+        let src = "article a { s } section s { paragraph { h2 { One } `x` h3 { Two } } }";
+        let generator = build(src, Theme::default())
+            .on_statement(Box::new(|statement, buf| {
+                if let Statement::Heading(tag, _, _) = statement {
+                    writeln!(buf, "{{/* {} */}}", tag)?;
+                }
+                Ok(())
+            }))
+            .on_statement(Box::new(|_, buf| {
+                writeln!(buf, "{{/* end */}}")?;
+                Ok(())
+            }));
+        let out = run(generator);
+        assert!(out.contains("</h2>\n{/* h2 */}\n{/* end */}\n<p>x</p>\n{/* end */}\n"));
+        assert!(out.contains("</h3>\n{/* h3 */}\n{/* end */}\n"));
+    }
+
+    #[test]
+    fn test_embed() {
+        let out = compile(
+            "article a { s } section s { paragraph { embed { https://www.youtube.com/watch?v=dQw4w9WgXcQ&t=42 } embed {`https://youtu.be/dQw4w9WgXcQ`} } }",
+        );
+        let iframe = "<div className='w-full aspect-video'><iframe src='https://www.youtube.com/embed/dQw4w9WgXcQ' className='w-full h-full' allowFullScreen></iframe></div>";
+        assert_eq!(out.matches(iframe).count(), 2);
+
+        let out = compile(
+            "article a { s } section s { paragraph { embed { https://example.com/clip?a=1&b='2' } } }",
+        );
+        assert!(out.contains(
+            "<p><a href='https://example.com/clip?a=1&amp;b=&#39;2&#39;'>https://example.com/clip?a=1&amp;b='2'</a></p>"
+        ));
+        assert!(!out.contains("iframe"));
+
+        let out =
+            compile("article a { s } section s { paragraph { embed { javascript:alert(1) } } }");
+        assert!(out.contains("<p>javascript:alert(1)</p>"));
+        assert!(!out.contains("href"));
+    }
+
+    #[test]
+    fn test_unsafe_links_are_left_as_text() {
+        assert!(safe_url("https://example.com"));
+        assert!(safe_url("HTTP://example.com"));
+        assert!(safe_url("mailto:me@example.com"));
+        assert!(safe_url("/posts/one"));
+        assert!(safe_url("../a:b"));
+        assert!(safe_url("#fn-1"));
+        assert!(!safe_url("javascript:alert(1)"));
+        assert!(!safe_url("JavaScript:alert(1)"));
+        assert!(!safe_url("data:text/html,hi"));
+
+        assert_eq!(
+            format_inline("[click](javascript:alert(1)) and [home](/)"),
+            "[click](javascript:alert(1)) and <a href='/'>home</a>"
+        );
+    }
+
+    #[test]
+    fn test_explicit_heading_id_wins_collision() {
+        // the auto id comes first but the explicit one is still kept as is
+        let out = compile(
+            "article a { s } section s { paragraph { h2 { Intro } h2(intro) { Pinned } h2 { Intro } } }",
+        );
+        assert!(out.contains("<h2 id='intro-2' className='text-3xl'>Intro</h2>"));
+        assert!(out.contains("<h2 id='intro' className='text-3xl'>Pinned</h2>"));
+        assert!(out.contains("<h2 id='intro-3' className='text-3xl'>Intro</h2>"));
+    }
+
+    #[test]
+    fn test_dedent_code() {
+        let src = "article a { s } section s { paragraph { code {`\n    if x {\n        y();\n    }\n    `} } }";
+        assert!(compile(src).contains("{`\n    if x {\n        y();\n    }\n    `}"));
+        let out = run(build(src, Theme::default()).with_dedent_code(true));
+        assert!(out.contains("<code>{`\nif x {\n    y();\n}\n`}</code>"));
+    }
+
+    #[test]
+    fn test_anchor() {
+        let out = compile(
+            "article a { s } section s { paragraph { h2 { Intro } `x` anchor{my-target} } }",
+        );
+        assert!(out.contains("<p>x</p>\n<span id='my-target'></span>"));
+    }
+
+    #[test]
+    fn test_checklist() {
+        let out = compile(
+            "article a { s } section s { paragraph { cl { li(x) { Write tests } li() { Ship it } } } }",
+        );
+        assert!(out.contains(
+            "<ul className='list-none px-8'>\n<li><input type='checkbox' checked disabled/> Write tests</li>\n<li><input type='checkbox' disabled/> Ship it</li>\n</ul>"
+        ));
+    }
+
+    #[test]
+    fn test_wrapper_tags_from_theme() {
+        let theme = Theme::from_json(
+            r#"{ "section_tag": "article", "paragraph_tag": "div", "paragraph": "para" }"#,
+        )
+        .unwrap();
+        let src = "article a { s t } section s { paragraph { `one` } paragraph { `two` } } section t { paragraph { `three` } }";
+        let out = build(src, theme.clone()).compile_to_string().unwrap();
+        assert!(out.contains(
+            "<article id='s'>\n<div className='para'>\n<p>one</p>\n</div>\n<div className='para'>\n<p>two</p>\n</div>\n</article>\n<article id='t'>\n<div className='para'>\n<p>three</p>\n</div>\n</article>"
+        ));
+
+        let mut buf = Vec::new();
+        build(src, theme).compile_section("s", &mut buf).unwrap();
+        assert!(out.contains(&String::from_utf8(buf).unwrap()));
+    }
+
+    #[test]
+    fn test_compile_diff() {
+        let before = build(
+            "article a { s t } section s { paragraph { `one` } } section t { paragraph { `two` } }",
+            Theme::default(),
+        );
+        let after = build(
+            "article a { s t }\nsection s {\n    paragraph { `one` }\n}\nsection t { paragraph { `three` } }",
+            Theme::default(),
+        );
+        assert_eq!(
+            after.compile_diff(&before.program),
+            vec![Patch::Changed("t".to_string())]
+        );
+        assert!(before.compile_diff(&before.program).is_empty());
+
+        let added = build(
+            "article a { s u } section s { paragraph { `one` } } section u { paragraph { `two` } }",
+            Theme::default(),
+        );
+        assert_eq!(
+            added.compile_diff(&before.program),
+            vec![
+                Patch::Article,
+                Patch::Added("u".to_string()),
+                Patch::Removed("t".to_string())
+            ]
+        );
+
+        // editing s shifts the id of t's heading and moves the footnote's
+        // first reference into t, though t itself is untouched
+        let before = build(
+            "article a { s t } section s { paragraph { h2 { Intro } footnote{n} } } section t { paragraph { h2 { Intro } footnote{n} footnote{n}{`note`} } }",
+            Theme::default(),
+        );
+        let after = build(
+            "article a { s t } section s { paragraph { h2 { Overview } } } section t { paragraph { h2 { Intro } footnote{n} footnote{n}{`note`} } }",
+            Theme::default(),
+        );
+        assert_eq!(
+            after.compile_diff(&before.program),
+            vec![
+                Patch::Changed("s".to_string()),
+                Patch::Changed("t".to_string())
+            ]
+        );
+    }
+}
@@ -0,0 +1,201 @@
+use std::io::Write;
+
+use super::codegen::{escape_html, GenerationError};
+use crate::parser::parser::{AstNode, Program, Statement};
+
+// DEFAULT_UPDATED is the timestamp of a feed whose article has no date and
+// that isn't given one, fixed so the same source always builds the same feed
+pub const DEFAULT_UPDATED: &str = "1970-01-01T00:00:00Z";
+
+// DEFAULT_AUTHOR names the author of an article without an author
+pub const DEFAULT_AUTHOR: &str = "Anonymous";
+
+// FeedGenerator renders a Program as an Atom feed, one entry per article.
+// The article's `date` and `author` metadata fill in the elements Atom
+// requires.
+pub struct FeedGenerator {
+    program: Program,
+    // timestamp used when the article has no date
+    updated: String,
+}
+
+impl FeedGenerator {
+    pub fn new(input: Program) -> Self {
+        Self {
+            program: input,
+            updated: DEFAULT_UPDATED.to_string(),
+        }
+    }
+
+    // builder style option for the date used when the article has none,
+    // either `YYYY-MM-DD` or a full RFC 3339 timestamp
+    pub fn with_updated(mut self, updated: &str) -> Self {
+        self.updated = updated.to_string();
+        self
+    }
+
+    pub fn compile<W: Write>(&mut self, buf: &mut W) -> Result<(), GenerationError> {
+        let article = self.program.article();
+        let date = article.metadata.get("date").unwrap_or(&self.updated);
+        let updated = timestamp(date)
+            .ok_or_else(|| GenerationError::from(format!("invalid feed date: {}", date)))?;
+        let author = article
+            .metadata
+            .get("author")
+            .map_or(DEFAULT_AUTHOR, String::as_str);
+        let id = format!("urn:blogger:{}", escape_html(&article.name));
+        writeln!(buf, r#"<?xml version="1.0" encoding="utf-8"?>"#)?;
+        writeln!(buf, r#"<feed xmlns="http://www.w3.org/2005/Atom">"#)?;
+        writeln!(buf, "<title>{}</title>", escape_html(&article.name))?;
+        writeln!(buf, "<id>{}:feed</id>", id)?;
+        writeln!(buf, "<updated>{}</updated>", updated)?;
+        writeln!(buf, "<author><name>{}</name></author>", escape_html(author))?;
+        writeln!(buf, "<entry>")?;
+        writeln!(buf, "<title>{}</title>", escape_html(&article.name))?;
+        writeln!(buf, "<id>{}</id>", id)?;
+        writeln!(buf, "<updated>{}</updated>", updated)?;
+        if let Some(summary) = self.summary() {
+            writeln!(buf, "<summary>{}</summary>", escape_html(summary))?;
+        }
+        writeln!(buf, "</entry>")?;
+        writeln!(buf, "</feed>")?;
+        Ok(())
+    }
+
+    // summary is the first text block of the first paragraph the article renders.
+    fn summary(&self) -> Option<&str> {
+        let paragraph = self.program.iter_ast().find_map(|node| match node {
+            AstNode::Paragraph(p) => Some(p),
+            _ => None,
+        })?;
+        paragraph
+            .statements
+            .iter()
+            .find_map(|stmt| match &stmt.node {
+                Statement::TextBlock(text) => Some(text.as_str()),
+                _ => None,
+            })
+    }
+}
+
+// timestamp turns a `YYYY-MM-DD` date into the RFC 3339 timestamp for its
+// midnight UTC, and passes a timestamp that already has a time through
+fn timestamp(date: &str) -> Option<String> {
+    let (day, time) = match date.split_once('T') {
+        Some((day, time)) => (day, Some(time)),
+        None => (date, None),
+    };
+    let parts: Vec<&str> = day.split('-').collect();
+    let is_day = parts.len() == 3
+        && parts
+            .iter()
+            .zip([4, 2, 2])
+            .all(|(part, len)| part.len() == len && part.chars().all(|c| c.is_ascii_digit()));
+    match time {
+        _ if !is_day => None,
+        None => Some(format!("{}T00:00:00Z", day)),
+        Some(time) if !time.is_empty() && !time.contains(char::is_whitespace) => {
+            Some(date.to_string())
+        }
+        Some(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{timestamp, FeedGenerator};
+    use crate::{
+        lexer::{lexer::Lexer, tokens::token_specs},
+        parser::parser::Parser,
+    };
+
+    fn compile(src: &str) -> String {
+        let src = src.to_string();
+        let lexer = Lexer::new(&src, token_specs());
+        let program = Parser::new(lexer, &src, Path::new("")).parse().unwrap();
+        let mut buf = Vec::new();
+        FeedGenerator::new(program).compile(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    // checks every opened element is closed in order
+    fn assert_well_formed(xml: &str) {
+        let mut stack = Vec::new();
+        for tag in xml.split('<').skip(1).map(|t| t.split('>').next().unwrap()) {
+            if tag.starts_with('?') || tag.ends_with('/') {
+                continue;
+            }
+            let name = tag.split_whitespace().next().unwrap();
+            match name.strip_prefix('/') {
+                Some(closing) => assert_eq!(stack.pop(), Some(closing.to_string())),
+                None => stack.push(name.to_string()),
+            }
+        }
+        assert!(stack.is_empty(), "unclosed elements: {:?}", stack);
+    }
+
+    #[test]
+    fn test_feed_entry_per_article() {
+        let feed = compile(
+            "article Post { a b } section a { paragraph { h1 { Hi } `first & best` `second` } } section b { paragraph { `other` } }",
+        );
+        assert_well_formed(&feed);
+        assert_eq!(feed.matches("<entry>").count(), 1);
+        assert!(feed.contains("<title>Post</title>"));
+        assert!(feed.contains("<summary>first &amp; best</summary>"));
+    }
+
+    #[test]
+    fn test_required_atom_elements() {
+        let feed = compile(
+            r#"article Post[date="2026-10-16", author=Sam] { a } section a { paragraph { `x` } }"#,
+        );
+        assert_well_formed(&feed);
+        let (head, entry) = feed.split_once("<entry>").unwrap();
+        assert!(head.contains("<id>urn:blogger:Post:feed</id>"));
+        assert!(head.contains("<updated>2026-10-16T00:00:00Z</updated>"));
+        assert!(head.contains("<author><name>Sam</name></author>"));
+        assert!(entry.contains("<id>urn:blogger:Post</id>"));
+        assert!(entry.contains("<updated>2026-10-16T00:00:00Z</updated>"));
+
+        // without metadata the fallback date and author are used
+        let src = "article Post { a } section a { paragraph { `x` } }".to_string();
+        let program = Parser::new(Lexer::new(&src, token_specs()), &src, Path::new(""))
+            .parse()
+            .unwrap();
+        let mut buf = Vec::new();
+        FeedGenerator::new(program.clone())
+            .with_updated("2026-01-02T03:04:05Z")
+            .compile(&mut buf)
+            .unwrap();
+        let feed = String::from_utf8(buf).unwrap();
+        assert_eq!(
+            feed.matches("<updated>2026-01-02T03:04:05Z</updated>")
+                .count(),
+            2
+        );
+        assert!(feed.contains("<author><name>Anonymous</name></author>"));
+        assert!(compile(&src).contains("<updated>1970-01-01T00:00:00Z</updated>"));
+
+        let result = FeedGenerator::new(program)
+            .with_updated("yesterday")
+            .compile(&mut Vec::new());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Compile error: invalid feed date: yesterday"
+        );
+    }
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp("2026-10-16").unwrap(), "2026-10-16T00:00:00Z");
+        assert_eq!(
+            timestamp("2026-10-16T10:00:00+01:00").unwrap(),
+            "2026-10-16T10:00:00+01:00"
+        );
+        assert_eq!(timestamp("16-10-2026"), None);
+        assert_eq!(timestamp("2026-10-16T"), None);
+    }
+}
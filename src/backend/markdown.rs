@@ -0,0 +1,303 @@
+use std::io::Write;
+
+use super::{
+    codegen::{parse_link, safe_url, GenerationError},
+    plaintext::wrap,
+};
+use crate::{
+    diag::Spanned,
+    parser::parser::{AstNode, List, Paragraph, Program, Statement},
+    util::collapse_whitespace,
+};
+
+// MarkdownBackend renders a Program as CommonMark. The inline `*emphasis*`
+// and `[text](url)` syntax is already Markdown, so text is written as it is
+// apart from the escaping in escape_inline.
+pub struct MarkdownBackend {
+    program: Program,
+    // column to hard wrap text blocks at, None leaves them on one line
+    wrap_width: Option<usize>,
+    // collapse whitespace runs in text blocks to single spaces
+    collapse_whitespace: bool,
+}
+
+impl MarkdownBackend {
+    pub fn new(input: Program) -> Self {
+        Self {
+            program: input,
+            wrap_width: None,
+            collapse_whitespace: false,
+        }
+    }
+
+    // builder style option to hard wrap text blocks at word boundaries.
+    // Code blocks are never wrapped.
+    pub fn with_wrap_width(mut self, wrap_width: Option<usize>) -> Self {
+        self.wrap_width = wrap_width;
+        self
+    }
+
+    // builder style toggle for collapsing each run of whitespace in a text
+    // block to a single space
+    pub fn with_collapse_whitespace(mut self, collapse_whitespace: bool) -> Self {
+        self.collapse_whitespace = collapse_whitespace;
+        self
+    }
+
+    pub fn compile<W: Write>(&mut self, buf: &mut W) -> Result<(), GenerationError> {
+        self.program.iter_ast().try_for_each(|node| match node {
+            AstNode::Article(v) => {
+                if !v.name.is_empty() {
+                    Self::write_block(buf, &format!("# {}", escape_inline(&v.name)))?;
+                }
+                self.generate_block(buf, self.program.preamble())
+            }
+            AstNode::Statement(v) => self.generate_statement(buf, v),
+            _ => Ok(()),
+        })?;
+        self.generate_block(buf, self.program.footer())
+    }
+
+    // writes the statements of the optional preamble or footer
+    fn generate_block<W: Write>(
+        &self,
+        buf: &mut W,
+        block: Option<&Spanned<Paragraph>>,
+    ) -> Result<(), GenerationError> {
+        block
+            .iter()
+            .flat_map(|block| block.statements.iter())
+            .try_for_each(|statement| self.generate_statement(buf, statement))
+    }
+
+    // every block is followed by a blank line
+    fn write_block<W: Write>(buf: &mut W, s: &str) -> Result<(), GenerationError> {
+        writeln!(buf, "{}\n", s)?;
+        Ok(())
+    }
+
+    fn generate_statement<W: Write>(
+        &self,
+        buf: &mut W,
+        statement: &Statement,
+    ) -> Result<(), GenerationError> {
+        match statement {
+            Statement::TextBlock(c) => match self.wrap_width {
+                Some(width) => Self::write_block(buf, &escape_inline(&wrap(c, width))),
+                None if self.collapse_whitespace => {
+                    Self::write_block(buf, &escape_inline(&collapse_whitespace(c)))
+                }
+                None => Self::write_block(buf, &escape_inline(c)),
+            },
+            // Markdown has no syntax for a heading id, renderers slug the
+            // text themselves
+            Statement::Heading(tag, _, c) => {
+                let level = tag.trim_start_matches('h').parse().unwrap_or(1);
+                Self::write_block(buf, &format!("{} {}", "#".repeat(level), escape_inline(c)))
+            }
+            Statement::CodeBlock(c) => Self::write_block(buf, &fenced(c)),
+            Statement::Math(c) => Self::write_block(buf, &format!("$$\n{}\n$$", c.trim())),
+            Statement::Aside(c) => {
+                let quoted: Vec<String> = c
+                    .lines()
+                    .map(|l| format!("> {}", escape_inline(l)))
+                    .collect();
+                Self::write_block(buf, &quoted.join("\n"))
+            }
+            // Markdown passes HTML through, so raw markup and anchors keep
+            // their meaning
+            Statement::RawHtml(c) => Self::write_block(buf, c),
+            Statement::Anchor(id) => Self::write_block(buf, &format!("<a id='{}'></a>", id)),
+            Statement::List(l) => Self::generate_list(buf, l),
+            // an autolink for a URL that is safe to link to, otherwise text
+            Statement::Embed(url) if safe_url(url) && !url.contains(char::is_whitespace) => {
+                Self::write_block(buf, &format!("<{}>", url))
+            }
+            Statement::Embed(url) => Self::write_block(buf, &escape_inline(url)),
+            Statement::FootnoteRef(id) => Self::write_block(buf, &format!("[^{}]", id)),
+            Statement::FootnoteDef(id, text) => {
+                Self::write_block(buf, &format!("[^{}]: {}", id, escape_inline(text)))
+            }
+            Statement::Columns(columns) => columns
+                .iter()
+                .flat_map(|column| column.statements.iter())
+                .try_for_each(|statement| self.generate_statement(buf, statement)),
+        }
+    }
+
+    fn generate_list<W: Write>(buf: &mut W, list: &List) -> Result<(), GenerationError> {
+        let lines: Vec<String> = match list {
+            List::Ordered(items) => items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| format!("{}. {}", i + 1, escape_inline(item)))
+                .collect(),
+            List::Unordered(items) => items
+                .iter()
+                .map(|item| format!("- {}", escape_inline(item)))
+                .collect(),
+            List::Checklist(items) => items
+                .iter()
+                .map(|(done, item)| {
+                    let done = if *done { 'x' } else { ' ' };
+                    format!("- [{}] {}", done, escape_inline(item))
+                })
+                .collect(),
+        };
+        Self::write_block(buf, &lines.join("\n"))
+    }
+}
+
+// escape_inline escapes text so the only markup in it is the emphasis and
+// links the JSX and HTML backends would render. `<`, `>` and `&` become
+// entities, and a `[` that doesn't start a link to a safe_url is escaped so
+// nothing else, such as a `javascript:` URL, can become a link.
+fn escape_inline(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find(['<', '>', '&', '[']) {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let len = match rest.as_bytes()[0] {
+            b'<' => {
+                out.push_str("&lt;");
+                1
+            }
+            b'>' => {
+                out.push_str("&gt;");
+                1
+            }
+            b'&' => {
+                out.push_str("&amp;");
+                1
+            }
+            _ => match parse_link(rest) {
+                Some((text, url, len)) => {
+                    out.push_str(&format!("[{}]({})", escape_inline(text), url));
+                    len
+                }
+                None => {
+                    out.push_str("\\[");
+                    1
+                }
+            },
+        };
+        rest = &rest[len..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// fenced wraps code in a fence one backtick longer than the longest run of
+// backticks in it, and at least three, so the code can't close it early
+fn fenced(code: &str) -> String {
+    let longest = code.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat((longest + 1).max(3));
+    format!("{}\n{}\n{}", fence, code.trim_matches('\n'), fence)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{escape_inline, fenced, MarkdownBackend};
+    use crate::{
+        lexer::{lexer::Lexer, tokens::token_specs},
+        parser::parser::Parser,
+    };
+
+    fn compile(src: &str, wrap_width: Option<usize>) -> String {
+        let src = src.to_string();
+        let lexer = Lexer::new(&src, token_specs());
+        let program = Parser::new(lexer, &src, Path::new("")).parse().unwrap();
+        let mut buf = Vec::new();
+        MarkdownBackend::new(program)
+            .with_wrap_width(wrap_width)
+            .compile(&mut buf)
+            .unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_markdown_golden() {
+        let out = compile(
+            r#"article Post { intro }
+section intro {
+    paragraph {
+        h2 { `Hello` }
+        `Some *text* and [a link](https://example.com).`
+        aside { `Note this` }
+        ol { li { one } li { two } }
+        ul { li { `a` } }
+        code { fn main() {} }
+    }
+}"#,
+            None,
+        );
+        let expected = "# Post
+
+## Hello
+
+Some *text* and [a link](https://example.com).
+
+> Note this
+
+1. one
+2. two
+
+- a
+
+```
+fn main() {}
+```
+
+";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_wrap_width() {
+        let src = "article { s } section s { paragraph { `The quick brown fox jumps over the lazy dog, then an incomprehensibilities word` code { `a b c d e f g h i j k l m n o` } } }";
+        let out = compile(src, Some(20));
+        let (text, code) = out.split_once("```").unwrap();
+        for line in text.lines() {
+            assert!(line.len() <= 20 || !line.contains(' '), "{:?}", line);
+        }
+        assert!(text.lines().any(|line| line == "incomprehensibilities"));
+        // code is never wrapped
+        assert!(code.contains("a b c d e f g h i j k l m n o\n"));
+    }
+
+    #[test]
+    fn test_code_fence_outlasts_backticks() {
+        assert_eq!(fenced("let s = `${x}`;"), "```\nlet s = `${x}`;\n```");
+        assert_eq!(fenced("a ``` b"), "````\na ``` b\n````");
+        let out = compile(
+            "article { s } section s { paragraph { code { let s = `${x}`; } } }",
+            None,
+        );
+        assert_eq!(out, "```\nlet s = `${x}`;\n```\n\n");
+    }
+
+    #[test]
+    fn test_text_is_escaped() {
+        assert_eq!(
+            escape_inline("a <script> & [the docs](https://x.dev/?a=1) *b*"),
+            "a &lt;script&gt; &amp; [the docs](https://x.dev/?a=1) *b*"
+        );
+        // a link to anything but a safe URL is left as text
+        assert_eq!(
+            escape_inline("[click](javascript:alert(1)) [x][ref] [ref]: javascript:y"),
+            "\\[click](javascript:alert(1)) \\[x]\\[ref] \\[ref]: javascript:y"
+        );
+
+        let out = compile(
+            "article { s } section s { paragraph { `<script>alert(1)</script>` ul { li { `[x](javascript:alert(1))` } } embed { javascript:alert(1) } embed { https://example.com/a } } }",
+            None,
+        );
+        assert_eq!(
+            out,
+            "&lt;script&gt;alert(1)&lt;/script&gt;\n\n- \\[x](javascript:alert(1))\n\njavascript:alert(1)\n\n<https://example.com/a>\n\n"
+        );
+    }
+}
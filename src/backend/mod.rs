@@ -1 +1,226 @@
+use std::io::Write;
+
+use crate::{
+    diag::Spanned,
+    parser::parser::{Paragraph, Program, Statement},
+    util,
+};
+
+use codegen::{GenerationError, Generator};
+use markdown::MarkdownBackend;
+use plaintext::PlainTextBackend;
+use theme::Theme;
+
 pub mod codegen;
+pub mod feed;
+pub mod markdown;
+pub mod plaintext;
+pub mod theme;
+
+// Options configures how a Backend renders a Program. Backends ignore the
+// options that don't apply to them.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub theme: Theme,
+    pub minify: bool,
+    pub indent: bool,
+    // column text is hard wrapped at, by backends producing plain text
+    pub wrap_width: Option<usize>,
+    // collapse whitespace runs in text blocks to single spaces. None leaves
+    // it to the backend, JSX collapses and plain text doesn't.
+    pub collapse_whitespace: Option<bool>,
+    // self-close void elements such as `<br/>`. None leaves it to the
+    // backend, JSX needs them closed.
+    pub xhtml: Option<bool>,
+    // decode named entities such as `&mdash;` in text blocks before they're
+    // rendered, see decode_entities
+    pub decode_entities: bool,
+    // strip the indentation shared by the lines of each code block
+    pub dedent_code: bool,
+}
+
+// Backend selects which output format a Program is compiled to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Jsx,
+    Html,
+    PlainText,
+    Markdown,
+}
+
+impl Backend {
+    // from_name maps a `--target` value to its backend.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "jsx" => Some(Backend::Jsx),
+            "html" => Some(Backend::Html),
+            "txt" => Some(Backend::PlainText),
+            "md" => Some(Backend::Markdown),
+            _ => None,
+        }
+    }
+
+    // from_extension maps an output file extension to the backend that
+    // produces it.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext {
+            "jsx" | "tsx" => Some(Backend::Jsx),
+            "html" | "htm" => Some(Backend::Html),
+            "txt" => Some(Backend::PlainText),
+            "md" | "markdown" => Some(Backend::Markdown),
+            _ => None,
+        }
+    }
+
+    // extension is the file extension used for this backend's output.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Backend::Jsx => "jsx",
+            Backend::Html => "html",
+            Backend::PlainText => "txt",
+            Backend::Markdown => "md",
+        }
+    }
+
+    pub fn compile<W: Write>(
+        &self,
+        program: Program,
+        options: &Options,
+        buf: &mut W,
+    ) -> Result<(), GenerationError> {
+        match self {
+            Backend::Jsx => jsx_generator(program, options).compile(buf),
+            Backend::Html => html_generator(program, options).compile(buf),
+            Backend::PlainText => PlainTextBackend::new(prepare(program, options))
+                .with_wrap_width(options.wrap_width)
+                .with_collapse_whitespace(options.collapse_whitespace.unwrap_or(false))
+                .compile(buf),
+            Backend::Markdown => MarkdownBackend::new(prepare(program, options))
+                .with_wrap_width(options.wrap_width)
+                .with_collapse_whitespace(options.collapse_whitespace.unwrap_or(false))
+                .compile(buf),
+        }
+    }
+}
+
+// jsx_generator builds the generator the JSX backend compiles program with,
+// configured by options
+pub fn jsx_generator(program: Program, options: &Options) -> Generator {
+    Generator::new(prepare(program, options), options.theme.clone())
+        .with_minify(options.minify)
+        .with_indent(options.indent)
+        .with_collapse_whitespace(options.collapse_whitespace.unwrap_or(true))
+        .with_xhtml(options.xhtml.unwrap_or(true))
+        .with_dedent_code(options.dedent_code)
+}
+
+// html_generator builds the generator the HTML backend compiles program
+// with. Unlike JSX, HTML has no need to close void elements.
+pub fn html_generator(program: Program, options: &Options) -> Generator {
+    jsx_generator(program, options)
+        .with_html(true)
+        .with_xhtml(options.xhtml.unwrap_or(false))
+}
+
+// prepare runs the pre-passes options ask for over program
+fn prepare(mut program: Program, options: &Options) -> Program {
+    if options.decode_entities {
+        decode_entities(&mut program);
+    }
+    program
+}
+
+// decode_entities is a pre-pass replacing the named HTML entities in every
+// text block with their chars, which the backend then escapes as it would
+// any other text. `&amp;` renders as `&amp;` either way, `&mdash;` becomes
+// an em dash rather than the literal text.
+pub fn decode_entities(program: &mut Program) {
+    program.paragraphs_mut().for_each(decode_paragraph);
+}
+
+fn decode_paragraph(paragraph: &mut Spanned<Paragraph>) {
+    for statement in paragraph.statements.iter_mut() {
+        match &mut statement.node {
+            Statement::TextBlock(text) => *text = util::decode_entities(text),
+            Statement::Columns(columns) => columns.iter_mut().for_each(decode_paragraph),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{theme::Theme, Backend, Options};
+    use crate::{
+        lexer::{lexer::Lexer, tokens::token_specs},
+        parser::parser::Parser,
+    };
+
+    fn compile(backend: Backend, src: &str, options: &Options) -> String {
+        let lexer = Lexer::new(src, token_specs());
+        let program = Parser::new(lexer, src, Path::new("")).parse().unwrap();
+        let mut buf = Vec::new();
+        backend.compile(program, options, &mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_decode_entities() {
+        let src = "article a { s } section s { paragraph { `Wait&hellip; A &amp; B &mdash; <i>` code { `&amp;` } columns { paragraph { `x&ndash;y` } } } }";
+        let decode = Options {
+            decode_entities: true,
+            ..Default::default()
+        };
+
+        let out = compile(Backend::Jsx, src, &decode);
+        assert!(out.contains("<p>Wait… A &amp; B — &lt;i&gt;</p>"));
+        assert!(out.contains("x–y"));
+        // code is verbatim, entities and all
+        assert!(out.contains("{`&amp;`}"));
+
+        let out = compile(Backend::Jsx, src, &Options::default());
+        assert!(out.contains("<p>Wait&amp;hellip; A &amp;amp; B &amp;mdash; &lt;i&gt;</p>"));
+
+        let out = compile(Backend::PlainText, src, &decode);
+        assert!(out.contains("Wait… A & B — <i>"));
+    }
+
+    #[test]
+    fn test_code_blocks_per_backend() {
+        let src = "article a { s } section s { paragraph { code { let s = `${x}`; a < b } } }";
+
+        // the template literal survives a backtick or ${ in the code
+        let out = compile(Backend::Jsx, src, &Options::default());
+        assert!(out.contains(r"<pre className='w-full overflow-x-auto'><code>{`let s = \`\${x}\`; a < b`}</code></pre>"));
+
+        let out = compile(Backend::Html, src, &Options::default());
+        assert!(out.contains(
+            "<pre class='w-full overflow-x-auto'><code>let s = `${x}`; a &lt; b</code></pre>"
+        ));
+        assert!(!out.contains("className"));
+        assert!(!out.contains("{`"));
+
+        let out = compile(Backend::PlainText, src, &Options::default());
+        assert!(out.contains("let s = `${x}`; a < b\n"));
+
+        let out = compile(Backend::Markdown, src, &Options::default());
+        assert!(out.contains("```\nlet s = `${x}`; a < b\n```"));
+    }
+
+    #[test]
+    fn test_html_void_elements() {
+        let src = "article a { s } section s { paragraph { `one` } paragraph { `two` } }";
+        let theme = Theme {
+            paragraph_tag: String::new(),
+            ..Default::default()
+        };
+        let options = Options {
+            theme,
+            ..Default::default()
+        };
+        assert!(compile(Backend::Html, src, &options).contains("<br>"));
+        assert!(compile(Backend::Jsx, src, &options).contains("<br/>"));
+    }
+}
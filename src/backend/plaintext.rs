@@ -0,0 +1,248 @@
+use std::io::Write;
+
+use super::codegen::GenerationError;
+use crate::{
+    diag::Spanned,
+    parser::parser::{AstNode, List, Paragraph, Program, Statement},
+    util::collapse_whitespace,
+};
+
+// PlainTextBackend renders a Program with all markup stripped, for meta
+// descriptions and search indexes.
+pub struct PlainTextBackend {
+    program: Program,
+    // column to hard wrap text blocks at, None leaves them on one line
+    wrap_width: Option<usize>,
+    // collapse whitespace runs in text blocks to single spaces
+    collapse_whitespace: bool,
+}
+
+impl PlainTextBackend {
+    pub fn new(input: Program) -> Self {
+        Self {
+            program: input,
+            wrap_width: None,
+            collapse_whitespace: false,
+        }
+    }
+
+    // builder style option to hard wrap text blocks at word boundaries.
+    // Code blocks are never wrapped.
+    pub fn with_wrap_width(mut self, wrap_width: Option<usize>) -> Self {
+        self.wrap_width = wrap_width;
+        self
+    }
+
+    // builder style toggle for collapsing each run of whitespace in a text
+    // block to a single space. Off by default, wrapping collapses it anyway.
+    pub fn with_collapse_whitespace(mut self, collapse_whitespace: bool) -> Self {
+        self.collapse_whitespace = collapse_whitespace;
+        self
+    }
+
+    pub fn compile<W: Write>(&mut self, buf: &mut W) -> Result<(), GenerationError> {
+        self.program.iter_ast().try_for_each(|node| match node {
+            AstNode::Article(v) => {
+                if !v.name.is_empty() {
+                    Self::write_block(buf, &v.name)?;
+                }
+                self.generate_block(buf, self.program.preamble())
+            }
+            AstNode::Statement(v) => self.generate_statement(buf, v),
+            _ => Ok(()),
+        })?;
+        self.generate_block(buf, self.program.footer())
+    }
+
+    // writes the statements of the optional preamble or footer
+    fn generate_block<W: Write>(
+        &self,
+        buf: &mut W,
+        block: Option<&Spanned<Paragraph>>,
+    ) -> Result<(), GenerationError> {
+        block
+            .iter()
+            .flat_map(|block| block.statements.iter())
+            .try_for_each(|statement| self.generate_statement(buf, statement))
+    }
+
+    // every block is followed by a blank line
+    fn write_block<W: Write>(buf: &mut W, s: &str) -> Result<(), GenerationError> {
+        writeln!(buf, "{}\n", s)?;
+        Ok(())
+    }
+
+    fn generate_statement<W: Write>(
+        &self,
+        buf: &mut W,
+        statement: &Statement,
+    ) -> Result<(), GenerationError> {
+        match statement {
+            Statement::TextBlock(c) => match self.wrap_width {
+                Some(width) => Self::write_block(buf, &wrap(c, width)),
+                None if self.collapse_whitespace => Self::write_block(buf, &collapse_whitespace(c)),
+                None => Self::write_block(buf, c),
+            },
+            Statement::Heading(_, _, c) | Statement::CodeBlock(c) | Statement::Math(c) => {
+                Self::write_block(buf, c)
+            }
+            Statement::Aside(c) => {
+                let quoted: Vec<String> = c.lines().map(|l| format!("> {}", l)).collect();
+                Self::write_block(buf, &quoted.join("\n"))
+            }
+            // Markup only, there is no text to extract.
+            Statement::RawHtml(_) | Statement::Anchor(_) => Ok(()),
+            Statement::List(l) => Self::generate_list(buf, l),
+            Statement::Embed(url) => Self::write_block(buf, url),
+            Statement::FootnoteRef(id) => Self::write_block(buf, &format!("[{}]", id)),
+            Statement::FootnoteDef(id, text) => {
+                Self::write_block(buf, &format!("[{}]: {}", id, text))
+            }
+            Statement::Columns(columns) => columns
+                .iter()
+                .flat_map(|column| column.statements.iter())
+                .try_for_each(|statement| self.generate_statement(buf, statement)),
+        }
+    }
+
+    fn generate_list<W: Write>(buf: &mut W, list: &List) -> Result<(), GenerationError> {
+        let lines: Vec<String> = match list {
+            List::Ordered(items) => items
+                .iter()
+                .enumerate()
+                .map(|(i, item)| format!("  {}. {}", i + 1, item))
+                .collect(),
+            List::Unordered(items) => items.iter().map(|item| format!("  - {}", item)).collect(),
+            List::Checklist(items) => items
+                .iter()
+                .map(|(done, item)| format!("  [{}] {}", if *done { 'x' } else { ' ' }, item))
+                .collect(),
+        };
+        Self::write_block(buf, &lines.join("\n"))
+    }
+}
+
+// wrap greedily fills lines of at most width columns with whole words. A
+// word longer than width gets a line to itself rather than being broken.
+pub(super) fn wrap(text: &str, width: usize) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{wrap, PlainTextBackend};
+    use crate::{
+        lexer::{lexer::Lexer, tokens::token_specs},
+        parser::parser::Parser,
+    };
+
+    fn compile(src: &str) -> String {
+        let src = src.to_string();
+        let lexer = Lexer::new(&src, token_specs());
+        let program = Parser::new(lexer, &src, Path::new("")).parse().unwrap();
+        let mut buf = Vec::new();
+        PlainTextBackend::new(program).compile(&mut buf).unwrap();
+        String::from_utf8(buf).unwrap()
+    }
+
+    #[test]
+    fn test_plaintext_golden() {
+        let out = compile(
+            r#"article Post { intro }
+section intro {
+    paragraph {
+        h1 { `Hello <world>` }
+        `Some text.`
+        aside { `Note this` }
+        ol { li { one } li { two } }
+        ul { li { `a` } }
+        code { `fn main() {}` }
+        html { `<hr/>` }
+    }
+}"#,
+        );
+        let expected = "Post
+
+Hello <world>
+
+Some text.
+
+> Note this
+
+  1. one
+  2. two
+
+  - a
+
+fn main() {}
+
+";
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_wrap_width() {
+        let text =
+            "The quick brown fox jumps over the lazy dog, then an incomprehensibilities word";
+        let wrapped = wrap(text, 20);
+        for line in wrapped.lines() {
+            assert!(line.len() <= 20 || !line.contains(' '), "{:?}", line);
+        }
+        assert_eq!(
+            wrapped.split_whitespace().collect::<Vec<_>>().join(" "),
+            text
+        );
+        assert!(wrapped.lines().any(|line| line == "incomprehensibilities"));
+
+        let src = "article { s } section s { paragraph { `one two three four five six` code { `a b c d e f g h i j k l` } } }".to_string();
+        let lexer = Lexer::new(&src, token_specs());
+        let program = Parser::new(lexer, &src, Path::new("")).parse().unwrap();
+        let mut buf = Vec::new();
+        PlainTextBackend::new(program)
+            .with_wrap_width(Some(10))
+            .compile(&mut buf)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(buf).unwrap(),
+            "one two\nthree four\nfive six\n\na b c d e f g h i j k l\n\n"
+        );
+    }
+
+    #[test]
+    fn test_collapse_whitespace() {
+        let src = "article { s } section s { paragraph { `\n    two\n    lines` } }".to_string();
+        assert_eq!(compile(&src), "\n    two\n    lines\n\n");
+
+        let lexer = Lexer::new(&src, token_specs());
+        let program = Parser::new(lexer, &src, Path::new("")).parse().unwrap();
+        let mut buf = Vec::new();
+        PlainTextBackend::new(program)
+            .with_collapse_whitespace(true)
+            .compile(&mut buf)
+            .unwrap();
+        assert_eq!(String::from_utf8(buf).unwrap(), "two lines\n\n");
+    }
+
+    #[test]
+    fn test_code_block_is_verbatim() {
+        let out =
+            compile(r#"article { s } section s { paragraph { code {`printf("${x}\n");`} } }"#);
+        assert_eq!(out, "printf(\"${x}\\n\");\n\n");
+    }
+}
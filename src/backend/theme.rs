@@ -0,0 +1,186 @@
+// Theme maps each kind of generated element to the class string it is
+// emitted with. The defaults are the Tailwind classes the generator has
+// always used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Theme {
+    pub title: String,
+    pub heading: String,
+    pub code: String,
+    pub aside: String,
+    pub math: String,
+    pub ordered_list: String,
+    pub unordered_list: String,
+    pub checklist: String,
+    pub footnotes: String,
+    pub columns: String,
+    pub column: String,
+    // the responsive box an embedded video's iframe fills
+    pub embed: String,
+    // the element each section is wrapped in, and its class
+    pub section_tag: String,
+    pub section: String,
+    // the element each paragraph is wrapped in, and its class. With no tag
+    // paragraphs aren't wrapped, they're separated by a <br/>.
+    pub paragraph_tag: String,
+    pub paragraph: String,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            title: "text-4xl font-bold".to_string(),
+            heading: "text-3xl".to_string(),
+            code: "w-full overflow-x-auto".to_string(),
+            aside: "p-8 bg-opacity-10 bg-black italic".to_string(),
+            math: "math".to_string(),
+            ordered_list: "list-inside list-decimal px-8".to_string(),
+            unordered_list: "list-disc list-inside px-8".to_string(),
+            checklist: "list-none px-8".to_string(),
+            footnotes: "footnotes".to_string(),
+            columns: "grid grid-flow-col gap-8".to_string(),
+            column: "column".to_string(),
+            embed: "w-full aspect-video".to_string(),
+            section_tag: "section".to_string(),
+            section: String::new(),
+            paragraph_tag: String::new(),
+            paragraph: String::new(),
+        }
+    }
+}
+
+impl Theme {
+    // from_json reads a flat JSON object of element kind to class string,
+    // e.g. `{"heading": "text-2xl"}`. Kinds that are left out keep their
+    // default classes.
+    pub fn from_json(src: &str) -> Result<Self, String> {
+        let mut theme = Self::default();
+        for (key, value) in parse_flat_object(src)? {
+            let slot = match key.as_str() {
+                "title" => &mut theme.title,
+                "heading" => &mut theme.heading,
+                "code" => &mut theme.code,
+                "aside" => &mut theme.aside,
+                "math" => &mut theme.math,
+                "ordered_list" => &mut theme.ordered_list,
+                "unordered_list" => &mut theme.unordered_list,
+                "checklist" => &mut theme.checklist,
+                "footnotes" => &mut theme.footnotes,
+                "columns" => &mut theme.columns,
+                "column" => &mut theme.column,
+                "embed" => &mut theme.embed,
+                "section_tag" => &mut theme.section_tag,
+                "section" => &mut theme.section,
+                "paragraph_tag" => &mut theme.paragraph_tag,
+                "paragraph" => &mut theme.paragraph,
+                other => return Err(format!("unknown theme key '{}'", other)),
+            };
+            *slot = value;
+        }
+        if theme.section_tag.is_empty() {
+            return Err("section_tag can't be empty".into());
+        }
+        for tag in [&theme.section_tag, &theme.paragraph_tag] {
+            if !tag.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(format!("invalid tag name '{}'", tag));
+            }
+        }
+        Ok(theme)
+    }
+}
+
+// parse_flat_object parses a JSON object whose values are all strings.
+// It is deliberately minimal; nesting, numbers and the like are rejected.
+fn parse_flat_object(src: &str) -> Result<Vec<(String, String)>, String> {
+    let mut chars = src.chars().peekable();
+    let mut pairs = Vec::new();
+
+    skip_ws(&mut chars);
+    if chars.next() != Some('{') {
+        return Err("expected '{'".into());
+    }
+    skip_ws(&mut chars);
+    if chars.peek() == Some(&'}') {
+        chars.next();
+    } else {
+        loop {
+            skip_ws(&mut chars);
+            let key = parse_string(&mut chars)?;
+            skip_ws(&mut chars);
+            if chars.next() != Some(':') {
+                return Err(format!("expected ':' after key '{}'", key));
+            }
+            skip_ws(&mut chars);
+            let value = parse_string(&mut chars)?;
+            pairs.push((key, value));
+            skip_ws(&mut chars);
+            match chars.next() {
+                Some(',') => continue,
+                Some('}') => break,
+                _ => return Err("expected ',' or '}'".into()),
+            }
+        }
+    }
+    skip_ws(&mut chars);
+    if chars.next().is_some() {
+        return Err("unexpected content after '}'".into());
+    }
+    Ok(pairs)
+}
+
+fn skip_ws(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while chars.peek().is_some_and(|c| c.is_whitespace()) {
+        chars.next();
+    }
+}
+
+fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<String, String> {
+    if chars.next() != Some('"') {
+        return Err("expected string".into());
+    }
+    let mut out = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(out),
+            Some('\\') => match chars.next() {
+                Some('"') => out.push('"'),
+                Some('\\') => out.push('\\'),
+                Some('/') => out.push('/'),
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).collect();
+                    let ch = u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("invalid unicode escape '\\u{}'", hex))?;
+                    out.push(ch);
+                }
+                other => return Err(format!("invalid escape {:?}", other)),
+            },
+            Some(c) => out.push(c),
+            None => return Err("unterminated string".into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Theme;
+
+    #[test]
+    fn test_partial_theme_keeps_defaults() {
+        let theme = Theme::from_json(r#"{ "heading": "h-big", "code": "mono \"x\"" }"#).unwrap();
+        assert_eq!(theme.heading, "h-big");
+        assert_eq!(theme.code, "mono \"x\"");
+        assert_eq!(theme.aside, Theme::default().aside);
+    }
+
+    #[test]
+    fn test_invalid_theme() {
+        assert!(Theme::from_json(r#"{ "nope": "x" }"#).is_err());
+        assert!(Theme::from_json(r#"{ "heading": 3 }"#).is_err());
+        assert!(Theme::from_json(r#"{ "heading": "x" "#).is_err());
+        assert!(Theme::from_json(r#"{ "paragraph_tag": "div onclick" }"#).is_err());
+        assert!(Theme::from_json(r#"{ "section_tag": "" }"#).is_err());
+    }
+}
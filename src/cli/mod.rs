@@ -1,12 +1,23 @@
-use std::{collections::HashMap, env, path::Path};
+use std::{
+    collections::HashMap,
+    env,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
 
 use crate::{
-    backend::codegen::Generator,
+    backend::{feed::FeedGenerator, html_generator, jsx_generator, theme::Theme, Backend, Options},
+    diag::Diagnostic,
     errors::BloggerError,
-    fs,
+    fs::{
+        self,
+        cache::{hash_source, BuildCache},
+    },
     lexer::{lexer::Lexer, tokens::token_specs},
-    parser::parser::Parser,
+    parser::parser::{Parser, Program, DEFAULT_MAX_ERRORS},
     regex::matcher::Matcher,
+    Timings,
 };
 
 #[derive(Debug)]
@@ -29,30 +40,107 @@ impl Flags {
         self.inner.contains_key(key)
     }
 
+    // get returns the flag's value, valueless flags such as `--watch`
+    // are present but have no value
     fn get(&self, key: &str) -> Option<&String> {
-        self.inner.get(key).and_then(|v| v.as_ref())
+        self.inner
+            .get(key)
+            .and_then(|v| v.as_ref())
+            .filter(|v| !v.is_empty())
+    }
+
+    // switch reads an on/off flag such as `--minify` through boolean, off
+    // when it isn't given
+    fn switch(&self, key: &str) -> Result<bool, BloggerError> {
+        Ok(self.boolean(key)?.unwrap_or(false))
+    }
+
+    // boolean reads a flag that can be switched either way, given bare or
+    // as `=true` to turn it on and as `=false` to turn it off. None when the
+    // flag isn't given, any other value is an error.
+    fn boolean(&self, key: &str) -> Result<Option<bool>, BloggerError> {
+        match self.inner.get(key) {
+            None => Ok(None),
+            Some(Some(v)) if v.is_empty() || v == "true" => Ok(Some(true)),
+            Some(Some(v)) if v == "false" => Ok(Some(false)),
+            Some(v) => Err(BloggerError::CommandError(format!(
+                "expected true or false for flag {}, found {}",
+                key,
+                v.as_deref().unwrap_or_default()
+            ))),
+        }
     }
 
+    // must checks every key is given with a value
     fn must(&self, keys: &[&str]) -> Result<(), BloggerError> {
-        keys.iter().try_for_each(|key| {
-            if !self.contains(key) {
-                return Err(BloggerError::CommandError(format!("expected flag {}", key)));
-            }
+        keys.iter()
+            .try_for_each(|key| self.require(key).map(|_| ()))
+    }
+
+    // require returns the value of a flag that has to be given one
+    fn require(&self, key: &str) -> Result<&String, BloggerError> {
+        match self.get(key) {
+            Some(value) => Ok(value),
+            None if self.contains(key) => Err(BloggerError::CommandError(format!(
+                "expected a value for flag {}",
+                key
+            ))),
+            None => Err(BloggerError::CommandError(format!("expected flag {}", key))),
+        }
+    }
+}
+
+// diagnose converts a pipeline error into a BloggerError, first printing it
+// as a JSON diagnostic on stdout when `--diagnostics=json` is set.
+fn diagnose<E>(flags: &Flags, err: E) -> BloggerError
+where
+    E: Into<BloggerError>,
+    for<'e> Diagnostic: From<&'e E>,
+{
+    if flags.get("--diagnostics").map(String::as_str) == Some("json") {
+        println!("{}", Diagnostic::from(&err).to_json());
+    }
+    err.into()
+}
 
-            Ok(())
-        })
+fn check_diagnostics_flag(flags: &Flags) -> Result<(), BloggerError> {
+    match flags.get("--diagnostics").map(String::as_str) {
+        None | Some("json") => Ok(()),
+        Some(other) => Err(BloggerError::CommandError(format!(
+            "unknown diagnostics format: {}",
+            other
+        ))),
     }
 }
 
-trait Command {
+trait Command: Send + Sync {
+    // name is what the command is invoked as, e.g. `compile`
+    fn name(&self) -> &'static str;
+    // description is the one line summary shown by `help`
+    fn description(&self) -> &'static str;
+    // accepts reports whether flag is one the command reads
+    fn accepts(&self, flag: &str) -> bool;
     fn run(&self, args: &[String], flags: &Flags) -> Result<(), BloggerError>;
 }
 
 macro_rules! new_command {
-    ($name:ident, $cmd_name:expr, ($param:ident, $param2:ident) $run:block) => {
+    ($name:ident, $cmd_name:expr, $description:expr, [$($flags:expr),*], ($param:ident, $param2:ident) $run:block) => {
         struct $name;
 
         impl Command for $name {
+            fn name(&self) -> &'static str {
+                $cmd_name
+            }
+
+            fn description(&self) -> &'static str {
+                $description
+            }
+
+            fn accepts(&self, flag: &str) -> bool {
+                let groups: &[&[&str]] = &[$($flags),*];
+                groups.iter().any(|group| group.contains(&flag))
+            }
+
             fn run(&self, $param: &[String], $param2: &Flags) -> Result<(), BloggerError> {
                 {
                     $run
@@ -62,89 +150,1198 @@ macro_rules! new_command {
     };
 }
 
-new_command!(LexCommand, "tokenises input and outputs token list", (_args, flags) {
-    flags.must(&vec!["--src"])?;
-    let src_location = flags.get("--src").unwrap();
-    let src_path = Path::new(src_location);
-    let src_content = fs::read_file_to_string(src_path)?;
-    let lexer = Lexer::new(&src_content,token_specs());
-    for token in lexer {
-        match token {
-            Ok(spanned_tok) => {
-                println!("{:?}", spanned_tok.kind);
-            },
-            Err(e) => {
-                return Err(e.into());
-            }
+// the flags read by every command that takes a source
+const SOURCE_FLAGS: &[&str] = &[
+    "--src",
+    "--source",
+    "--paragraph-breaks",
+    "--diagnostics",
+    "--max-errors",
+];
+
+// the flags read by lint
+const LINT_FLAGS: &[&str] = &["--format", "--quiet", "--strict"];
+
+// the flags read by backend_from_flags and options_from_flags
+const OUTPUT_FLAGS: &[&str] = &[
+    "--target",
+    "--theme",
+    "--minify",
+    "--indent",
+    "--wrap",
+    "--collapse-whitespace",
+    "--xhtml",
+    "--decode-entities",
+    "--dedent-code",
+];
+
+new_command!(LexCommand, "lex", "tokenises input and outputs token list", [SOURCE_FLAGS], (_args, flags) {
+    emit_to_stdout(flags, Emit::Tokens)
+});
+
+new_command!(ParseCommand, "parse", "tokenises and parses input, outputs AST", [SOURCE_FLAGS, &["--pretty"]], (_args, flags) {
+    emit_to_stdout(flags, Emit::Ast)
+});
+
+new_command!(EmitCommand, "emit", "runs the pipeline up to --emit=tokens|ast|html",
+    [SOURCE_FLAGS, LINT_FLAGS, OUTPUT_FLAGS, &["--emit", "--dst", "--pretty", "--time"]], (_args, flags) {
+    flags.must(&["--emit"])?;
+    let stage = flags.get("--emit").map_or("", String::as_str);
+    let stage = Emit::from_name(stage)
+        .ok_or_else(|| BloggerError::CommandError(format!("unknown emit stage: {}", stage)))?;
+    match flags.get("--dst") {
+        Some(dst_location) => {
+            let mut dst_buf = fs::create_write_buffer(Path::new(dst_location))?;
+            emit(flags, stage, &mut dst_buf)
         }
+        None => emit_to_stdout(flags, stage),
     }
-    Ok(())
 });
 
-new_command!(ParseCommand,"tokenises and parses input, outputs AST", (_args, flags) {
-    flags.must(&vec!["--src"])?;
-    let src_location = flags.get("--src").unwrap();
-    let src_path = Path::new(src_location);
-    let src_content = fs::read_file_to_string(src_path)?;
-    let lexer = Lexer::new(&src_content,token_specs());
-    let parser = Parser::new(lexer,&src_content).parse()?;
-    println!("{:#?}", parser);
+new_command!(StatsCommand, "stats", "parses input and prints counts of its structure", [SOURCE_FLAGS], (_args, flags) {
+    check_diagnostics_flag(flags)?;
+    let source = source_from_flags(flags)?;
+    let program = parse_source(flags, &source)?;
+    print!("{}", program.stats());
     Ok(())
 });
 
-new_command!(CompileCommand, "compiles input into blog output", (_args, flags) {
-    flags.must(&vec!["--src", "--dst"])?;
+new_command!(CompileCommand, "compile", "compiles input into blog output",
+    [SOURCE_FLAGS, LINT_FLAGS, OUTPUT_FLAGS, &["--dst", "--feed", "--feed-date", "--section", "--time"]], (_args, flags) {
+    let dst_path = Path::new(flags.require("--dst")?);
 
-    let src_location = flags.get("--src").unwrap();
-    let src_path = Path::new(src_location);
-
-    let dst_location = flags.get("--dst").unwrap();
-    let dst_path = Path::new(dst_location);
+    // the feed and single section outputs need the parsed program, anything
+    // else is a plain html emit
+    if !flags.contains("--feed") && !flags.contains("--section") {
+        let mut dst_buf = fs::create_write_buffer(dst_path)?;
+        return emit(flags, Emit::Html, &mut dst_buf);
+    }
 
-    let src_content = fs::read_file_to_string(src_path)?;
+    check_diagnostics_flag(flags)?;
+    // a bare --feed has nowhere to write the feed
+    let feed_location = if flags.contains("--feed") {
+        Some(Path::new(flags.require("--feed")?))
+    } else {
+        None
+    };
+    let section = if flags.contains("--section") {
+        Some(flags.require("--section")?)
+    } else {
+        None
+    };
+    let backend = backend_from_flags(flags, Some(dst_path))?;
+    let options = options_from_flags(flags)?;
+    let source = source_from_flags(flags)?;
+    crate::ensure_not_empty(&source.content)?;
     let mut dst_buf = fs::create_write_buffer(dst_path)?;
+    let mut timings = Timings::default();
+    let program = parse_source_timed(flags, &source, &mut timings)?;
+    lint(flags, &program, &mut io::stderr())?;
 
-    let lexer = Lexer::new(&src_content,token_specs());
-    let mut parser = Parser::new(lexer,&src_content);
-    let program = parser.parse()?;
-    let mut compiler = Generator::new(program);
-    compiler.compile(&mut dst_buf)?;
+    // Optionally write an Atom feed alongside the compiled output, dated
+    // --feed-date when the article has no date of its own.
+    if let Some(feed_location) = feed_location {
+        let mut feed_buf = fs::create_write_buffer(feed_location)?;
+        let mut feed = FeedGenerator::new(program.clone());
+        if let Some(date) = flags.get("--feed-date") {
+            feed = feed.with_updated(date);
+        }
+        feed.compile(&mut feed_buf)?;
+    }
+
+    // A single section is rendered as a standalone fragment.
+    let (compiled, codegen) = Timings::time(|| match section {
+        Some(section) => match backend {
+            Backend::Jsx => Ok(jsx_generator(program, &options).compile_section(section, &mut dst_buf)?),
+            Backend::Html => Ok(html_generator(program, &options).compile_section(section, &mut dst_buf)?),
+            _ => Err(BloggerError::CommandError(
+                "--section is only supported by the jsx and html targets".to_string(),
+            )),
+        },
+        None => Ok(backend.compile(program, &options, &mut dst_buf)?),
+    });
+    compiled?;
+    timings.codegen = codegen;
+    report_timings(flags, &timings)?;
     Ok(())
 });
 
-fn parse_flags(args: &[String]) -> Flags {
-    let m = Matcher::new(r"(-.-).([a-z]*).=.(([a-z]|/|\.|_)*)").unwrap();
-    let mut f = Flags::new();
-    for a in args {
-        if m.matches(a) {
-            let halves: Vec<&str> = a.split("=").collect();
-            assert_eq!(
-                halves.len(),
-                2,
-                "flag format must have two halves separated by ="
-            );
-            f.insert(halves[0].to_string(), Some(halves[1].to_string()));
+new_command!(BuildCommand, "build", "compiles every .blog file in a directory",
+    [SOURCE_FLAGS, LINT_FLAGS, OUTPUT_FLAGS, &["--dst", "--fail-fast"]], (_args, flags) {
+    flags.must(&["--src", "--dst"])?;
+    let src_dir = Path::new(flags.require("--src")?);
+    let dst_dir = Path::new(flags.require("--dst")?);
+    check_diagnostics_flag(flags)?;
+    let backend = backend_from_flags(flags, None)?;
+    let options = options_from_flags(flags)?;
+    // stop at the first failure unless given --fail-fast=false
+    let fail_fast = flags.boolean("--fail-fast")?.unwrap_or(true);
+
+    let summary = build_dir(flags, src_dir, dst_dir, backend, &options, fail_fast)?;
+    for (path, err) in &summary.failures {
+        eprintln!("{}: {}", path.display(), err);
+    }
+    if !summary.failures.is_empty() {
+        if fail_fast {
+            eprintln!("build stopped at the first failure, pass --fail-fast=false to build every file");
         }
+        return Err(BloggerError::CommandError(format!(
+            "{} of {} files failed to build",
+            summary.failures.len(),
+            summary.built + summary.cached + summary.failures.len()
+        )));
     }
-    f
+    Ok(())
+});
+
+new_command!(VersionCommand, "version", "prints the version", [&["--version"]], (_args, _flags) {
+    println!("{}", version());
+    Ok(())
+});
+
+new_command!(HelpCommand, "help", "lists the available commands", [], (_args, _flags) {
+    print!("{}", usage());
+    Ok(())
+});
+
+type Registry = HashMap<&'static str, Box<dyn Command>>;
+
+// registry maps every command's name to the command. It's built once, and
+// adding a command only takes listing it here.
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let commands: Vec<Box<dyn Command>> = vec![
+            Box::new(LexCommand),
+            Box::new(ParseCommand),
+            Box::new(StatsCommand),
+            Box::new(CompileCommand),
+            Box::new(EmitCommand),
+            Box::new(BuildCommand),
+            Box::new(VersionCommand),
+            Box::new(HelpCommand),
+        ];
+        commands
+            .into_iter()
+            .map(|command| (command.name(), command))
+            .collect()
+    })
 }
 
-pub fn run() -> Result<(), BloggerError> {
-    let args: Vec<String> = env::args().skip(1).collect();
-    let flags = parse_flags(&args);
-    let command = args[0].clone();
-
-    let command: Box<dyn Command> = match command.as_str() {
-        "lex" => Box::new(LexCommand),
-        "compile" => Box::new(CompileCommand),
-        "parse" => Box::new(ParseCommand),
-        _ => {
+impl TryFrom<&str> for &'static dyn Command {
+    type Error = BloggerError;
+
+    fn try_from(name: &str) -> Result<Self, Self::Error> {
+        registry()
+            .get(name)
+            .map(|command| command.as_ref())
+            .ok_or_else(|| BloggerError::CommandError(format!("unknown command: {}", name)))
+    }
+}
+
+// usage lists every command with its description, sorted by name
+fn usage() -> String {
+    let mut commands: Vec<&dyn Command> = registry().values().map(|c| c.as_ref()).collect();
+    commands.sort_by_key(|command| command.name());
+    let mut out = String::from("usage: blogger <command> [flags]\n\ncommands:\n");
+    for command in commands {
+        out.push_str(&format!(
+            "  {:<10}{}\n",
+            command.name(),
+            command.description()
+        ));
+    }
+    out
+}
+
+// Emit is how far through the pipeline the emit command runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Emit {
+    Tokens,
+    Ast,
+    Html,
+}
+
+impl Emit {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "tokens" => Some(Emit::Tokens),
+            "ast" => Some(Emit::Ast),
+            "html" => Some(Emit::Html),
+            _ => None,
+        }
+    }
+}
+
+// emit runs the pipeline over the source given by the flags as far as stage,
+// writing that stage's output to buf. Html is the output of the backend
+// picked by --target or the --dst extension.
+fn emit<W: Write>(flags: &Flags, stage: Emit, buf: &mut W) -> Result<(), BloggerError> {
+    check_diagnostics_flag(flags)?;
+    let source = source_from_flags(flags)?;
+    match stage {
+        Emit::Tokens => {
+            let tokens = lexer_from_flags(flags, &source.content)?
+                .tokens()
+                .map_err(|e| diagnose(flags, e))?;
+            for token in tokens {
+                writeln!(buf, "{:?}", token.kind)?;
+            }
+        }
+        // `--pretty=ron` dumps the AST as RON, which reads back into a
+        // Program, rather than the Debug form
+        Emit::Ast => {
+            let program = parse_source(flags, &source)?;
+            match flags.get("--pretty").map(String::as_str) {
+                None => writeln!(buf, "{:#?}", program)?,
+                Some("ron") => {
+                    let ron = ron::ser::to_string_pretty(&program, Default::default())
+                        .map_err(|e| BloggerError::CommandError(e.to_string()))?;
+                    writeln!(buf, "{}", ron)?;
+                }
+                Some(other) => {
+                    return Err(BloggerError::CommandError(format!(
+                        "unknown pretty format: {}",
+                        other
+                    )))
+                }
+            }
+        }
+        Emit::Html => {
+            let backend = backend_from_flags(flags, flags.get("--dst").map(Path::new))?;
+            let options = options_from_flags(flags)?;
+            crate::ensure_not_empty(&source.content)?;
+            let mut timings = Timings::default();
+            let program = parse_source_timed(flags, &source, &mut timings)?;
+            lint(flags, &program, &mut io::stderr())?;
+            let (compiled, codegen) = Timings::time(|| backend.compile(program, &options, buf));
+            compiled?;
+            timings.codegen = codegen;
+            report_timings(flags, &timings)?;
+        }
+    }
+    buf.flush()?;
+    Ok(())
+}
+
+fn emit_to_stdout(flags: &Flags, stage: Emit) -> Result<(), BloggerError> {
+    emit(flags, stage, &mut io::stdout().lock())
+}
+
+// lexer_from_flags lexes src with the default specs. `--paragraph-breaks`
+// lets blank lines split a section into paragraphs.
+fn lexer_from_flags<'a>(flags: &Flags, src: &'a str) -> Result<Lexer<'a>, BloggerError> {
+    Ok(Lexer::new(src, token_specs()).with_blank_lines(flags.switch("--paragraph-breaks")?))
+}
+
+fn parse_source(flags: &Flags, source: &Source) -> Result<Program, BloggerError> {
+    let lexer = lexer_from_flags(flags, &source.content)?;
+    let mut parser = Parser::new(lexer, &source.content, &source.base_dir);
+    parse_reporting_errors(flags, &mut parser)
+}
+
+// parse_reporting_errors parses with recovery, printing every error but the
+// last as it goes and returning the last. `--max-errors` caps how many are
+// collected.
+fn parse_reporting_errors(flags: &Flags, parser: &mut Parser) -> Result<Program, BloggerError> {
+    let max_errors = match flags.get("--max-errors") {
+        Some(max) => max
+            .parse::<usize>()
+            .ok()
+            .filter(|max| *max > 0)
+            .ok_or_else(|| BloggerError::CommandError(format!("invalid max errors: {}", max)))?,
+        None => DEFAULT_MAX_ERRORS,
+    };
+    let (program, mut errors) = parser.parse_recover(max_errors);
+    if let Some(program) = program {
+        return Ok(program);
+    }
+    let last = errors.pop().expect("a failed parse has an error");
+    for err in errors {
+        eprintln!("{}", diagnose(flags, err));
+    }
+    Err(diagnose(flags, last))
+}
+
+// parse_source_timed is parse_source with the source lexed up front, so
+// lexing and parsing are timed separately
+fn parse_source_timed(
+    flags: &Flags,
+    source: &Source,
+    timings: &mut Timings,
+) -> Result<Program, BloggerError> {
+    let lexer = lexer_from_flags(flags, &source.content)?;
+    let mut parser = Parser::new(lexer, &source.content, &source.base_dir);
+    ((), timings.lex) = Timings::time(|| parser.lex_ahead());
+    let (program, parse) = Timings::time(|| parse_reporting_errors(flags, &mut parser));
+    timings.parse = parse;
+    program
+}
+
+// report_timings prints how long each phase took to stderr under `--time`,
+// leaving the output itself untouched
+fn report_timings(flags: &Flags, timings: &Timings) -> Result<(), BloggerError> {
+    if flags.switch("--time")? {
+        eprint!("{}", timings);
+    }
+    Ok(())
+}
+
+// lint writes the warnings for program to out (stderr), one JSON object per
+// line under `--format=json`. With `--strict` any warning fails the command.
+// `--quiet` leaves the warnings out, but not the failure.
+fn lint<W: Write>(flags: &Flags, program: &Program, out: &mut W) -> Result<(), BloggerError> {
+    let json = match flags.get("--format").map(String::as_str) {
+        None | Some("human") => false,
+        Some("json") => true,
+        Some(other) => {
             return Err(BloggerError::CommandError(format!(
-                "unknown command: {}",
-                command
+                "unknown warning format: {}",
+                other
             )))
         }
     };
+    let quiet = flags.switch("--quiet")?;
+    let strict = flags.switch("--strict")?;
+    let warnings = program.lint();
+    for warning in warnings.iter().filter(|_| !quiet) {
+        if json {
+            writeln!(out, "{}", warning.to_json())?;
+        } else {
+            writeln!(out, "{}", warning)?;
+        }
+    }
+    if strict && !warnings.is_empty() {
+        return Err(BloggerError::CommandError(format!(
+            "{} warnings, failing because of --strict",
+            warnings.len()
+        )));
+    }
+    Ok(())
+}
+
+// Source is the program a command runs on, along with the directory its
+// imports are resolved against
+struct Source {
+    content: String,
+    base_dir: PathBuf,
+}
+
+// source_from_flags reads the program from the --src path, or takes it
+// inline from --source. Exactly one of the two must be given.
+fn source_from_flags(flags: &Flags) -> Result<Source, BloggerError> {
+    match (flags.get("--src"), flags.contains("--source")) {
+        (Some(src_location), false) => {
+            let src_path = Path::new(src_location);
+            Ok(Source {
+                content: fs::read_file_to_string(src_path)?,
+                base_dir: src_path.parent().unwrap_or(Path::new("")).to_path_buf(),
+            })
+        }
+        (None, true) => Ok(Source {
+            content: flags.get("--source").cloned().unwrap_or_default(),
+            base_dir: PathBuf::new(),
+        }),
+        _ => Err(BloggerError::CommandError(
+            "expected exactly one of --src or --source".to_string(),
+        )),
+    }
+}
+
+// backend_from_flags picks the backend named by --target, otherwise the one
+// implied by the extension of dst, falling back to JSX.
+fn backend_from_flags(flags: &Flags, dst: Option<&Path>) -> Result<Backend, BloggerError> {
+    match flags.get("--target") {
+        Some(target) => Backend::from_name(target)
+            .ok_or_else(|| BloggerError::CommandError(format!("unknown target: {}", target))),
+        None => Ok(dst
+            .and_then(|dst| dst.extension())
+            .and_then(|ext| ext.to_str())
+            .and_then(Backend::from_extension)
+            .unwrap_or(Backend::Jsx)),
+    }
+}
+
+fn options_from_flags(flags: &Flags) -> Result<Options, BloggerError> {
+    let theme = match flags.get("--theme") {
+        Some(theme_location) => {
+            let theme_src = fs::read_file_to_string(Path::new(theme_location))?;
+            Theme::from_json(&theme_src).map_err(|e| {
+                BloggerError::CommandError(format!("invalid theme {}: {}", theme_location, e))
+            })?
+        }
+        None => Theme::default(),
+    };
+    let wrap_width =
+        match flags.get("--wrap") {
+            Some(width) => Some(width.parse::<usize>().map_err(|_| {
+                BloggerError::CommandError(format!("invalid wrap width: {}", width))
+            })?),
+            None => None,
+        };
+    Ok(Options {
+        theme,
+        minify: flags.switch("--minify")?,
+        indent: flags.switch("--indent")?,
+        wrap_width,
+        // left to the backend unless given explicitly
+        collapse_whitespace: flags.boolean("--collapse-whitespace")?,
+        xhtml: flags.boolean("--xhtml")?,
+        decode_entities: flags.switch("--decode-entities")?,
+        dedent_code: flags.switch("--dedent-code")?,
+    })
+}
+
+// BuildSummary is the outcome of a directory build. Errors are collected in
+// failures, a build that fails fast stops after the first one.
+#[derive(Debug, Default)]
+struct BuildSummary {
+    // files compiled by this build
+    built: usize,
+    // files skipped because their source is unchanged since the last build
+    cached: usize,
+    failures: Vec<(PathBuf, BloggerError)>,
+}
+
+// build_dir compiles every .blog file under src_dir into the same relative
+// location under dst_dir. Sources whose hash matches the manifest left by the
+// previous build, and whose output still exists, are skipped. Changes to
+// imported files aren't tracked. Each file is lexed and linted under the
+// same flags compile honours.
+fn build_dir(
+    flags: &Flags,
+    src_dir: &Path,
+    dst_dir: &Path,
+    backend: Backend,
+    options: &Options,
+    fail_fast: bool,
+) -> Result<BuildSummary, BloggerError> {
+    let mut summary = BuildSummary::default();
+    let mut cache = BuildCache::load(dst_dir)?;
+    // a file is only skipped if it would lex and lint the same way again
+    let context = format!(
+        "{:?} {:?} paragraph-breaks={} strict={}",
+        backend,
+        options,
+        flags.switch("--paragraph-breaks")?,
+        flags.switch("--strict")?
+    );
+    for src_path in fs::find_files(src_dir, "blog")? {
+        let relative = src_path.strip_prefix(src_dir).unwrap_or(&src_path);
+        let key = relative.to_string_lossy();
+        let dst_path = dst_dir.join(relative).with_extension(backend.extension());
+        let src_content = match fs::read_file_to_string(&src_path) {
+            Ok(src_content) => src_content,
+            Err(err) => {
+                summary.failures.push((src_path, err.into()));
+                if fail_fast {
+                    break;
+                }
+                continue;
+            }
+        };
+        let hash = hash_source(&src_content, &context);
+        if cache.is_fresh(&key, hash) && dst_path.exists() {
+            summary.cached += 1;
+            continue;
+        }
+        match compile_file(flags, &src_path, &src_content, &dst_path, backend, options) {
+            Ok(()) => {
+                cache.record(&key, hash);
+                summary.built += 1;
+            }
+            Err(err) => {
+                cache.remove(&key);
+                summary.failures.push((src_path, err));
+                if fail_fast {
+                    break;
+                }
+            }
+        }
+    }
+    std::fs::create_dir_all(dst_dir)?;
+    cache.save()?;
+    Ok(summary)
+}
+
+fn compile_file(
+    flags: &Flags,
+    src_path: &Path,
+    src_content: &str,
+    dst_path: &Path,
+    backend: Backend,
+    options: &Options,
+) -> Result<(), BloggerError> {
+    crate::ensure_not_empty(src_content)?;
+    let lexer = lexer_from_flags(flags, src_content)?;
+    let base_dir = src_path.parent().unwrap_or(Path::new(""));
+    let mut parser = Parser::new(lexer, src_content, base_dir);
+    let program = parse_reporting_errors(flags, &mut parser)?;
+    let mut warnings = Vec::new();
+    let linted = lint(flags, &program, &mut warnings);
+    // human readable warnings name their file, as build failures do
+    let json = flags.get("--format").map(String::as_str) == Some("json");
+    for line in String::from_utf8_lossy(&warnings).lines() {
+        if json {
+            eprintln!("{}", line);
+        } else {
+            eprintln!("{}: {}", src_path.display(), line);
+        }
+    }
+    linted?;
+    if let Some(parent) = dst_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut dst_buf = fs::create_write_buffer(dst_path)?;
+    backend.compile(program, options, &mut dst_buf)?;
+    Ok(())
+}
 
+// short aliases, mapped to the long flag they stand for
+const SHORT_FLAGS: &[(&str, &str)] = &[
+    ("-s", "--src"),
+    ("-d", "--dst"),
+    ("-t", "--target"),
+    ("-w", "--watch"),
+];
+
+// parse_flags reads every argument starting with `-` as a flag, `--name`
+// or a short alias, optionally followed by `=` and a value. The value is
+// taken as it is, so it may hold spaces, `:` or program text. Any other
+// argument starting with `-` is an error.
+fn parse_flags(args: &[String]) -> Result<Flags, BloggerError> {
+    let shapes: Vec<Matcher> = [r"(-.-).([a-z]|-)+", r"-.[a-z]"]
+        .iter()
+        .map(|pattern| Matcher::new(pattern).unwrap())
+        .collect();
+    let mut f = Flags::new();
+    for a in args.iter().filter(|a| a.starts_with('-')) {
+        // only the first = separates the name, the value may contain more
+        let (name, value) = a.split_once('=').unwrap_or((a, ""));
+        if !shapes.iter().any(|m| m.matches(name)) {
+            return Err(BloggerError::CommandError(format!("invalid flag: {}", a)));
+        }
+        let name = SHORT_FLAGS
+            .iter()
+            .find(|(alias, _)| *alias == name)
+            .map_or(name, |(_, long)| *long);
+        f.insert(name.to_string(), Some(value.to_string()));
+    }
+    Ok(f)
+}
+
+// check_flags rejects any flag the command doesn't read, so a misspelt
+// flag isn't silently ignored
+fn check_flags(command: &dyn Command, flags: &Flags) -> Result<(), BloggerError> {
+    let mut unknown: Vec<&String> = flags
+        .inner
+        .keys()
+        .filter(|flag| !command.accepts(flag))
+        .collect();
+    unknown.sort();
+    match unknown.first() {
+        Some(flag) => Err(BloggerError::CommandError(format!(
+            "unknown flag for {}: {}",
+            command.name(),
+            flag
+        ))),
+        None => Ok(()),
+    }
+}
+
+// version is the tool's build version, as printed by `blogger version`
+fn version() -> String {
+    format!("blogger {}", env!("CARGO_PKG_VERSION"))
+}
+
+pub fn run() -> Result<(), BloggerError> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let flags = parse_flags(&args)?;
+    let name = if flags.contains("--version") {
+        "version"
+    } else {
+        args.first().map_or("help", String::as_str)
+    };
+    let command: &dyn Command = name.try_into()?;
+    check_flags(command, &flags)?;
     command.run(&args, &flags)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{
+        backend_from_flags, build_dir, check_flags, emit, lint, parse_flags, parse_source,
+        registry, source_from_flags, usage, version, Command, Emit,
+    };
+    use crate::{
+        backend::{Backend, Options},
+        parser::parser::Program,
+    };
+
+    fn args(raw: &[&str]) -> Vec<String> {
+        raw.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn test_flag_value_with_equals() {
+        let flags = parse_flags(&args(&["compile", "--title=a=b", "--src=x.blog"])).unwrap();
+        assert_eq!(flags.get("--title"), Some(&"a=b".to_string()));
+        assert_eq!(flags.get("--src"), Some(&"x.blog".to_string()));
+    }
+
+    #[test]
+    fn test_flag_values_are_kept_whole() {
+        let flags = parse_flags(&args(&[
+            "compile",
+            "--src=My Posts/first post.blog",
+            "--dst=C:/out/post.html",
+        ]))
+        .unwrap();
+        assert_eq!(
+            flags.get("--src"),
+            Some(&"My Posts/first post.blog".to_string())
+        );
+        assert_eq!(flags.get("--dst"), Some(&"C:/out/post.html".to_string()));
+    }
+
+    #[test]
+    fn test_malformed_and_unknown_flags() {
+        for raw in ["--", "--Src=x", "--src x", "-", "-ss=x", "--9=x"] {
+            let err = parse_flags(&args(&["compile", raw])).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("Blogger Error: invalid flag: {}", raw)
+            );
+        }
+
+        let compile = registry().get("compile").unwrap().as_ref();
+        let flags = parse_flags(&args(&["compile", "--src=x", "--dts=y", "--minfy"])).unwrap();
+        let err = check_flags(compile, &flags).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Blogger Error: unknown flag for compile: --dts"
+        );
+        let flags =
+            parse_flags(&args(&["compile", "-s=x", "-d=y", "--feed=z", "--minify"])).unwrap();
+        assert!(check_flags(compile, &flags).is_ok());
+
+        // a flag is only known to the commands that read it
+        let lex = registry().get("lex").unwrap().as_ref();
+        let flags = parse_flags(&args(&["lex", "--src=x", "--dst=y"])).unwrap();
+        let err = check_flags(lex, &flags).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Blogger Error: unknown flag for lex: --dst"
+        );
+    }
+
+    #[test]
+    fn test_short_and_valueless_flags() {
+        let flags = parse_flags(&args(&["compile", "-w", "--src=x", "--minify"])).unwrap();
+        assert!(flags.contains("--watch"));
+        assert_eq!(flags.get("--watch"), None);
+        assert_eq!(flags.get("--src"), Some(&"x".to_string()));
+        assert!(flags.switch("--minify").unwrap());
+        assert!(!flags.switch("--indent").unwrap());
+
+        let flags = parse_flags(&args(&["compile", "-s=in.blog", "-d=out2.html"])).unwrap();
+        assert_eq!(flags.get("--src"), Some(&"in.blog".to_string()));
+        assert_eq!(flags.get("--dst"), Some(&"out2.html".to_string()));
+    }
+
+    #[test]
+    fn test_required_flag_without_value() {
+        for raw in [
+            &["compile", "--src=x", "--dst"][..],
+            &["build", "--src", "--dst=out"][..],
+        ] {
+            let raw = args(raw);
+            let flags = parse_flags(&raw).unwrap();
+            let command = registry().get(raw[0].as_str()).unwrap();
+            let err = command.run(&raw, &flags).unwrap_err();
+            assert!(
+                err.to_string().contains("expected a value for flag"),
+                "{}",
+                err
+            );
+        }
+        let flags = parse_flags(&args(&["build", "--src=in"])).unwrap();
+        assert_eq!(
+            flags.must(&["--src", "--dst"]).unwrap_err().to_string(),
+            "Blogger Error: expected flag --dst"
+        );
+    }
+
+    #[test]
+    fn test_boolean_flags() {
+        let flags = parse_flags(&args(&[
+            "build",
+            "--fail-fast=false",
+            "--xhtml",
+            "--minify=true",
+        ]))
+        .unwrap();
+        assert_eq!(flags.boolean("--fail-fast").unwrap(), Some(false));
+        assert_eq!(flags.boolean("--xhtml").unwrap(), Some(true));
+        assert_eq!(flags.boolean("--minify").unwrap(), Some(true));
+        assert_eq!(flags.boolean("--indent").unwrap(), None);
+
+        for value in ["yes", "1", "False"] {
+            let raw = args(&[
+                "build",
+                "--src=in",
+                "--dst=out",
+                &format!("--fail-fast={}", value),
+            ]);
+            let flags = parse_flags(&raw).unwrap();
+            let err = registry()
+                .get("build")
+                .unwrap()
+                .run(&raw, &flags)
+                .unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "Blogger Error: expected true or false for flag --fail-fast, found {}",
+                    value
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_switches_reject_other_values() {
+        let source = "--source=article a { s } section s { paragraph { `x` } } section t { }";
+        for switch in [
+            "--minify",
+            "--indent",
+            "--decode-entities",
+            "--dedent-code",
+            "--quiet",
+            "--strict",
+            "--time",
+            "--paragraph-breaks",
+        ] {
+            let raw = args(&["emit", "--emit=html", source, &format!("{}=yes", switch)]);
+            let flags = parse_flags(&raw).unwrap();
+            let err = emit(&flags, Emit::Html, &mut Vec::new()).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!(
+                    "Blogger Error: expected true or false for flag {}, found yes",
+                    switch
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn test_feed_date() {
+        let dir = std::env::temp_dir();
+        let dst = dir.join(format!("blogger-feed-{}.html", std::process::id()));
+        let feed = dir.join(format!("blogger-feed-{}.xml", std::process::id()));
+        let raw = args(&[
+            "compile",
+            "--source=article a { s } section s { paragraph { `x` } }",
+            &format!("--dst={}", dst.display()),
+            &format!("--feed={}", feed.display()),
+            "--feed-date=2026-10-16",
+        ]);
+        let flags = parse_flags(&raw).unwrap();
+        registry()
+            .get("compile")
+            .unwrap()
+            .run(&raw, &flags)
+            .unwrap();
+        let xml = std::fs::read_to_string(&feed).unwrap();
+        assert_eq!(
+            xml.matches("<updated>2026-10-16T00:00:00Z</updated>")
+                .count(),
+            2
+        );
+        std::fs::remove_file(&dst).unwrap();
+        std::fs::remove_file(&feed).unwrap();
+
+        let raw = args(&[
+            "compile",
+            "--source=article a { }",
+            &format!("--dst={}", dst.display()),
+            "--feed",
+        ]);
+        let flags = parse_flags(&raw).unwrap();
+        let err = registry()
+            .get("compile")
+            .unwrap()
+            .run(&raw, &flags)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Blogger Error: expected a value for flag --feed"
+        );
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn test_bare_section_is_rejected() {
+        let dst = std::env::temp_dir().join(format!("blogger-section-{}.html", std::process::id()));
+        let raw = args(&[
+            "compile",
+            "--source=article a { s } section s { paragraph { `x` } }",
+            &format!("--dst={}", dst.display()),
+            "--section",
+        ]);
+        let flags = parse_flags(&raw).unwrap();
+        let err = registry()
+            .get("compile")
+            .unwrap()
+            .run(&raw, &flags)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Blogger Error: expected a value for flag --section"
+        );
+        assert!(!dst.exists());
+    }
+
+    #[test]
+    fn test_version() {
+        assert_eq!(version(), format!("blogger {}", env!("CARGO_PKG_VERSION")));
+        assert!(parse_flags(&args(&["--version"]))
+            .unwrap()
+            .contains("--version"));
+    }
+
+    #[test]
+    fn test_build_dir() {
+        let root = std::env::temp_dir().join(format!("blogger-build-{}", std::process::id()));
+        let src = root.join("src");
+        let dst = root.join("dst");
+        std::fs::create_dir_all(src.join("posts")).unwrap();
+        std::fs::write(
+            src.join("index.blog"),
+            "article home { s } section s { paragraph { `home` } }",
+        )
+        .unwrap();
+        std::fs::write(
+            src.join("posts").join("first.blog"),
+            "article first { intro } section intro { paragraph { `hello` } }",
+        )
+        .unwrap();
+        std::fs::write(src.join("broken.blog"), "article broken {").unwrap();
+        std::fs::write(src.join("notes.txt"), "not a blog").unwrap();
+
+        let flags = parse_flags(&args(&["build"])).unwrap();
+        let summary = build_dir(
+            &flags,
+            &src,
+            &dst,
+            Backend::Html,
+            &Options::default(),
+            false,
+        )
+        .unwrap();
+        assert_eq!(summary.built, 2);
+        assert_eq!(summary.failures.len(), 1);
+        assert_eq!(summary.failures[0].0, src.join("broken.blog"));
+        assert!(Path::new(&dst.join("index.html")).exists());
+        let first = std::fs::read_to_string(dst.join("posts").join("first.html")).unwrap();
+        assert!(first.contains("hello"));
+        assert!(!dst.join("notes.html").exists());
+
+        // failing fast stops at broken.blog, the first file in order
+        std::fs::remove_dir_all(&dst).unwrap();
+        let summary =
+            build_dir(&flags, &src, &dst, Backend::Html, &Options::default(), true).unwrap();
+        assert_eq!(summary.built, 0);
+        assert_eq!(summary.failures.len(), 1);
+        assert!(!dst.join("index.html").exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_build_honours_compile_flags() {
+        let root = std::env::temp_dir().join(format!("blogger-build-flags-{}", std::process::id()));
+        let src = root.join("src");
+        let dst = root.join("dst");
+        std::fs::create_dir_all(&src).unwrap();
+        // paragraphs split on a blank line, and an unused section to warn about
+        std::fs::write(
+            src.join("post.blog"),
+            "article a { s } section s {\n    `one`\n\n    `two`\n}\nsection u { }",
+        )
+        .unwrap();
+        let build = |extra: &[&str]| {
+            let mut raw = vec!["build"];
+            raw.extend_from_slice(extra);
+            let flags = parse_flags(&args(&raw)).unwrap();
+            build_dir(&flags, &src, &dst, Backend::Html, &Options::default(), true).unwrap()
+        };
+
+        assert_eq!(build(&[]).failures.len(), 1);
+        let summary = build(&["--paragraph-breaks"]);
+        assert_eq!(summary.built, 1);
+        let out = std::fs::read_to_string(dst.join("post.html")).unwrap();
+        assert!(
+            out.contains("<br>\n<p>one</p>\n<br>\n<p>two</p>"),
+            "{}",
+            out
+        );
+
+        let summary = build(&["--paragraph-breaks", "--strict", "--quiet"]);
+        assert_eq!(summary.built, 0);
+        assert!(summary.failures[0]
+            .1
+            .to_string()
+            .ends_with("warnings, failing because of --strict"));
+
+        // parse errors are capped by --max-errors, as compile caps them
+        std::fs::write(
+            src.join("post.blog"),
+            "article a { } section { } section { }",
+        )
+        .unwrap();
+        let summary = build(&["--max-errors=1"]);
+        let err = summary.failures[0].1.to_string();
+        assert!(err.contains("Too many errors, stopped after 1"), "{}", err);
+
+        let raw = args(&[
+            "build",
+            &format!("--src={}", src.display()),
+            &format!("--dst={}", dst.display()),
+            "--diagnostics=xml",
+        ]);
+        let flags = parse_flags(&raw).unwrap();
+        let err = registry()
+            .get("build")
+            .unwrap()
+            .run(&raw, &flags)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Blogger Error: unknown diagnostics format: xml"
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_backend_inferred_from_dst() {
+        let flags = parse_flags(&args(&["compile"])).unwrap();
+        let infer = |dst: &str| backend_from_flags(&flags, Some(Path::new(dst))).unwrap();
+        assert_eq!(infer("post.txt"), Backend::PlainText);
+        assert_eq!(infer("post.jsx"), Backend::Jsx);
+        assert_eq!(infer("post.tsx"), Backend::Jsx);
+        assert_eq!(infer("post.html"), Backend::Html);
+        assert_eq!(infer("post.md"), Backend::Markdown);
+        assert_eq!(infer("post.unknown"), Backend::Jsx);
+        assert_eq!(infer("post"), Backend::Jsx);
+
+        let flags = parse_flags(&args(&["compile", "--target=jsx"])).unwrap();
+        let backend = backend_from_flags(&flags, Some(Path::new("post.txt"))).unwrap();
+        assert_eq!(backend, Backend::Jsx);
+
+        // every extension a backend writes infers that backend back
+        for backend in [
+            Backend::Jsx,
+            Backend::Html,
+            Backend::PlainText,
+            Backend::Markdown,
+        ] {
+            let dst = format!("post.{}", backend.extension());
+            assert_eq!(infer(&dst), backend);
+        }
+    }
+
+    #[test]
+    fn test_unchanged_build_is_cached() {
+        let root = std::env::temp_dir().join(format!("blogger-cache-{}", std::process::id()));
+        let src = root.join("src");
+        let dst = root.join("dst");
+        std::fs::create_dir_all(&src).unwrap();
+        std::fs::write(
+            src.join("one.blog"),
+            "article one { s } section s { paragraph { `one` } }",
+        )
+        .unwrap();
+        std::fs::write(
+            src.join("two.blog"),
+            "article two { s } section s { paragraph { `two` } }",
+        )
+        .unwrap();
+        let options = Options::default();
+        let flags = parse_flags(&args(&["build"])).unwrap();
+
+        let first = build_dir(&flags, &src, &dst, Backend::Jsx, &options, true).unwrap();
+        assert_eq!((first.built, first.cached), (2, 0));
+        assert!(dst.join(".blogger-cache").exists());
+
+        let second = build_dir(&flags, &src, &dst, Backend::Jsx, &options, true).unwrap();
+        assert_eq!((second.built, second.cached), (0, 2));
+
+        std::fs::write(
+            src.join("two.blog"),
+            "article two { s } section s { paragraph { `changed` } }",
+        )
+        .unwrap();
+        let third = build_dir(&flags, &src, &dst, Backend::Jsx, &options, true).unwrap();
+        assert_eq!((third.built, third.cached), (1, 1));
+
+        // a different backend doesn't reuse the jsx entries
+        let plain = build_dir(&flags, &src, &dst, Backend::PlainText, &options, true).unwrap();
+        assert_eq!((plain.built, plain.cached), (2, 0));
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_command_registry() {
+        for name in [
+            "lex", "parse", "stats", "compile", "build", "version", "help",
+        ] {
+            let command = registry().get(name).expect(name);
+            assert_eq!(command.name(), name);
+            assert!(!command.description().is_empty());
+            assert!(usage().contains(&format!("  {:<10}{}", name, command.description())));
+        }
+        assert_eq!(registry().len(), 8);
+
+        let command: &dyn Command = "compile".try_into().unwrap();
+        assert_eq!(command.name(), "compile");
+        let err = <&dyn Command>::try_from("publish").err().unwrap();
+        assert_eq!(err.to_string(), "Blogger Error: unknown command: publish");
+    }
+
+    #[test]
+    fn test_inline_source() {
+        let dst = std::env::temp_dir().join(format!("blogger-inline-{}.html", std::process::id()));
+        let raw = args(&[
+            "compile",
+            "--source=article a { s } section s { paragraph { `inline <b>` } }",
+            &format!("--dst={}", dst.display()),
+        ]);
+        let flags = parse_flags(&raw).unwrap();
+        let compile: &dyn Command = "compile".try_into().unwrap();
+        compile.run(&raw, &flags).unwrap();
+        let out = std::fs::read_to_string(&dst).unwrap();
+        assert!(out.contains("<p>inline &lt;b&gt;</p>"));
+        std::fs::remove_file(&dst).unwrap();
+
+        let both = parse_flags(&args(&["lex", "--src=a.blog", "--source=article a { }"])).unwrap();
+        assert!(source_from_flags(&both).is_err());
+        let neither = parse_flags(&args(&["lex"])).unwrap();
+        assert!(source_from_flags(&neither).is_err());
+    }
+
+    #[test]
+    fn test_emit_stages() {
+        let flags = parse_flags(&args(&[
+            "emit",
+            "--source=article a { s } section s { paragraph { `hi` } }",
+        ]))
+        .unwrap();
+        let run = |stage| {
+            let mut buf = Vec::new();
+            emit(&flags, stage, &mut buf).unwrap();
+            String::from_utf8(buf).unwrap()
+        };
+
+        let tokens = run(Emit::Tokens);
+        assert_eq!(tokens.lines().count(), 13);
+        assert!(tokens.starts_with("Article\nIdent(\"a\")\nLBrace\n"));
+
+        let ast = run(Emit::Ast);
+        assert!(ast.starts_with("Program {"));
+        assert!(ast.contains("TextBlock(\n"));
+
+        let html = run(Emit::Html);
+        assert!(html.starts_with("<h1 className='text-4xl font-bold'>a</h1>\n<section id='s'>"));
+        assert!(html.contains("<p>hi</p>"));
+    }
+
+    #[test]
+    fn test_time_flag_leaves_output_unchanged() {
+        let compile_to = |name: &str, extra: &[&str]| {
+            let dst = std::env::temp_dir().join(format!(
+                "blogger-time-{}-{}.html",
+                name,
+                std::process::id()
+            ));
+            let mut raw = vec![
+                "compile".to_string(),
+                "--source=article a { s } section s { paragraph { h1 { Hi } `text` } }".to_string(),
+                format!("--dst={}", dst.display()),
+            ];
+            raw.extend(extra.iter().map(|arg| arg.to_string()));
+            let flags = parse_flags(&raw).unwrap();
+            let compile: &dyn Command = "compile".try_into().unwrap();
+            compile.run(&raw, &flags).unwrap();
+            let out = std::fs::read_to_string(&dst).unwrap();
+            std::fs::remove_file(&dst).unwrap();
+            out
+        };
+        let plain = compile_to("plain", &[]);
+        assert!(plain.contains("<p>text</p>"));
+        assert_eq!(compile_to("timed", &["--time"]), plain);
+        assert_eq!(
+            compile_to("timed-section", &["--time", "--section=s"]),
+            compile_to("section", &["--section=s"])
+        );
+    }
+
+    #[test]
+    fn test_pretty_ron_round_trips() {
+        let src = "article a { s } section s[author=me] { paragraph { h1 { Hi } `text` footnote{n} footnote{n}{`note`} ul { li { x } } } }";
+        let flags = parse_flags(&args(&[
+            "parse",
+            &format!("--source={}", src),
+            "--pretty=ron",
+        ]))
+        .unwrap();
+        let mut buf = Vec::new();
+        emit(&flags, Emit::Ast, &mut buf).unwrap();
+        let ron = String::from_utf8(buf).unwrap();
+        let program: Program = ron::from_str(&ron).unwrap();
+        let source = source_from_flags(&flags).unwrap();
+        let parsed = parse_source(&flags, &source).unwrap();
+        assert_eq!(program, parsed);
+        // equality ignores spans, so check they survive too
+        assert_eq!(program.sections()["s"].span, parsed.sections()["s"].span);
+
+        let flags = parse_flags(&args(&[
+            "parse",
+            &format!("--source={}", src),
+            "--pretty=yaml",
+        ]))
+        .unwrap();
+        assert!(emit(&flags, Emit::Ast, &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_quiet_suppresses_warnings() {
+        // the unused section and the empty paragraph are both warned about
+        let source = "--source=article a { s } section s { paragraph { } } section t { }";
+        let run = |extra: &[&str]| {
+            let mut raw = vec!["compile", source];
+            raw.extend_from_slice(extra);
+            let flags = parse_flags(&args(&raw)).unwrap();
+            let program = parse_source(&flags, &source_from_flags(&flags).unwrap()).unwrap();
+            let mut out = Vec::new();
+            let result = lint(&flags, &program, &mut out);
+            (result.is_ok(), String::from_utf8(out).unwrap())
+        };
+
+        let (ok, warnings) = run(&[]);
+        assert!(ok);
+        assert!(warnings.contains("Warning[unused-section]"));
+        assert_eq!(run(&["--quiet"]), (true, String::new()));
+
+        // --strict still fails, just without the listing
+        let (ok, warnings) = run(&["--strict"]);
+        assert!(!ok);
+        assert!(!warnings.is_empty());
+        assert_eq!(run(&["--strict", "--quiet"]), (false, String::new()));
+    }
+}
@@ -1,4 +1,6 @@
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct Position {
     offset: usize,
     line: usize,
@@ -13,14 +15,25 @@ impl Position {
             column: 0,
         }
     }
+    // advance moves past ch. A '\r' takes no column, so the "\r\n" of CRLF
+    // files counts as the single line break its '\n' makes.
     pub fn advance(&self, ch: char) -> Self {
         Self {
             offset: self.offset + ch.len_utf8(),
             line: if ch == '\n' { self.line + 1 } else { self.line },
-            column: if ch == '\n' { 0 } else { self.column + 1 },
+            column: match ch {
+                '\n' => 0,
+                '\r' => self.column,
+                _ => self.column + 1,
+            },
         }
     }
 
+    // advance_str moves past every char of s in one pass
+    pub fn advance_str(&self, s: &str) -> Self {
+        s.chars().fold(*self, |pos, ch| pos.advance(ch))
+    }
+
     pub fn offset(&self) -> usize {
         self.offset
     }
@@ -32,9 +45,27 @@ impl Position {
     pub fn column(&self) -> usize {
         self.column
     }
+
+    // line_number and column_number count from 1, as editors show them, for
+    // messages meant for people. line and column, like the JSON forms, count
+    // from 0.
+    pub fn line_number(&self) -> usize {
+        self.line + 1
+    }
+
+    pub fn column_number(&self) -> usize {
+        self.column + 1
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"line":{},"column":{},"offset":{}}}"#,
+            self.line, self.column, self.offset
+        )
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
 pub struct Span {
     start: Position,
     end: Position,
@@ -44,20 +75,309 @@ impl Span {
     pub fn new(start: Position, end: Position) -> Self {
         Self { start, end }
     }
+
+    pub fn start(&self) -> Position {
+        self.start
+    }
+
+    pub fn end(&self) -> Position {
+        self.end
+    }
+
+    // merge returns the smallest span covering both self and other
+    pub fn merge(&self, other: &Span) -> Span {
+        let start = if other.start.offset < self.start.offset {
+            other.start
+        } else {
+            self.start
+        };
+        let end = if other.end.offset > self.end.offset {
+            other.end
+        } else {
+            self.end
+        };
+        Span::new(start, end)
+    }
+
+    // contains reports whether pos falls inside the span, end exclusive
+    pub fn contains(&self, pos: Position) -> bool {
+        self.start.offset <= pos.offset && pos.offset < self.end.offset
+    }
+
+    // snippet renders the span's first line with a caret underline, expanding
+    // tabs to DEFAULT_TAB_WIDTH columns as most editors show them
     pub fn snippet(&self, src: &str) -> String {
-        let line = src.lines().nth(self.start.line).unwrap_or("").trim_start();
-        let underline: String = (0..line.len())
-            .map(|i| {
-                if i >= self.start.column && i <= self.end.column {
-                    '^'
-                } else {
-                    '-'
-                }
-            })
+        self.snippet_with_tab_width(src, DEFAULT_TAB_WIDTH)
+    }
+
+    // snippet_with_tab_width is snippet with tab stops every tab_width
+    // columns. Only the rendering changes, offsets and columns still count
+    // a tab as one char.
+    pub fn snippet_with_tab_width(&self, src: &str, tab_width: usize) -> String {
+        let tab_width = tab_width.max(1);
+        let line = src.lines().nth(self.start.line).unwrap_or("");
+        let expanded = expand_tabs(line, tab_width);
+        let trimmed = expanded.trim_start();
+        let indent = expanded.chars().count() - trimmed.chars().count();
+        let start = display_column(line, self.start.column, tab_width);
+        let end = display_column(line, self.end.column, tab_width);
+        let underline: String = (indent..indent + trimmed.chars().count())
+            .map(|i| if i >= start && i <= end { '^' } else { '-' })
             .collect();
         format!(
             "\nLine: {}, Column: {}\n>> '{}'\n   {}",
-            self.start.line, self.start.column, line, underline
+            self.start.line_number(),
+            self.start.column_number(),
+            trimmed,
+            underline
         )
     }
 }
+
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
+// display_column is where the char at column (as counted by Position) is
+// drawn, with a tab advancing to the next multiple of tab_width
+fn display_column(line: &str, column: usize, tab_width: usize) -> usize {
+    line.chars()
+        .filter(|&ch| ch != '\r')
+        .take(column)
+        .fold(0, |col, ch| match ch {
+            '\t' => col + tab_width - col % tab_width,
+            _ => col + 1,
+        })
+}
+
+// expand_tabs replaces each tab with spaces up to the next tab stop
+fn expand_tabs(line: &str, tab_width: usize) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut col = 0;
+    for ch in line.chars().filter(|&ch| ch != '\r') {
+        if ch == '\t' {
+            let width = tab_width - col % tab_width;
+            out.extend(std::iter::repeat_n(' ', width));
+            col += width;
+        } else {
+            out.push(ch);
+            col += 1;
+        }
+    }
+    out
+}
+
+// Spanned pairs a node with the span of source it was parsed from. It derefs
+// to the node so it can mostly be used as one. Equality only compares the
+// nodes, so the same content written with different layout compares equal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+impl<T> Spanned<T> {
+    pub fn new(node: T, span: Span) -> Self {
+        Self { node, span }
+    }
+}
+
+impl<T> std::ops::Deref for Spanned<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.node
+    }
+}
+
+impl<T> std::ops::DerefMut for Spanned<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.node
+    }
+}
+
+impl<T: PartialEq> PartialEq for Spanned<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.node == other.node
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+// Diagnostic is the machine readable form of an error or warning, for
+// editor tooling that can't consume the human oriented snippets.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+    pub severity: Severity,
+}
+
+impl Diagnostic {
+    pub fn error<M: Into<String>>(message: M, span: Span) -> Self {
+        Self {
+            message: message.into(),
+            span,
+            severity: Severity::Error,
+        }
+    }
+
+    // to_json gives the line and column counting from 0, as editor tooling
+    // such as the language server protocol expects
+    pub fn to_json(&self) -> String {
+        let start = self.span.start();
+        format!(
+            r#"{{"message":"{}","line":{},"column":{},"offset":{},"severity":"{}"}}"#,
+            escape_json(&self.message),
+            start.line(),
+            start.column(),
+            start.offset(),
+            self.severity.as_str()
+        )
+    }
+}
+
+// escape_json escapes s for use inside a JSON string literal
+pub fn escape_json(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for ch in s.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Position, Span, Spanned};
+
+    fn pos_at(src: &str, offset: usize) -> Position {
+        src[..offset]
+            .chars()
+            .fold(Position::new(), |p, c| p.advance(c))
+    }
+
+    #[test]
+    fn test_advance_str_matches_advance() {
+        let block = "first line\n\tsecond é line\nthird";
+        let start = Position::new().advance('`');
+        let folded = block.chars().fold(start, |p, c| p.advance(c));
+        let jumped = start.advance_str(block);
+        assert_eq!(jumped, folded);
+        assert_eq!((jumped.line(), jumped.column()), (2, 5));
+        assert_eq!(jumped.offset(), 1 + block.len());
+    }
+
+    fn span(src: &str, start: usize, end: usize) -> Span {
+        Span::new(pos_at(src, start), pos_at(src, end))
+    }
+
+    #[test]
+    fn test_merge_adjacent_spans() {
+        let src = "h1 { title }";
+        let merged = span(src, 0, 2).merge(&span(src, 2, 4));
+        assert_eq!(merged, span(src, 0, 4));
+        // order of the operands doesn't matter
+        assert_eq!(span(src, 2, 4).merge(&span(src, 0, 2)), merged);
+    }
+
+    #[test]
+    fn test_merge_overlapping_spans() {
+        let src = "section\nintro { }";
+        let merged = span(src, 0, 10).merge(&span(src, 5, 16));
+        assert_eq!(merged, span(src, 0, 16));
+        assert_eq!(merged.end().line(), 1);
+        assert_eq!(span(src, 0, 16).merge(&span(src, 3, 5)), span(src, 0, 16));
+    }
+
+    #[test]
+    fn test_contains() {
+        let src = "h1 { title }";
+        let s = span(src, 3, 7);
+        assert!(!s.contains(pos_at(src, 2)));
+        assert!(s.contains(pos_at(src, 3)));
+        assert!(s.contains(pos_at(src, 6)));
+        assert!(!s.contains(pos_at(src, 7)));
+    }
+
+    #[test]
+    fn test_spanned() {
+        let src = "h1 { title }";
+        let title = Spanned::new("title".to_string(), span(src, 5, 10));
+        // derefs to the node
+        assert_eq!(title.len(), 5);
+        assert_eq!(title.as_str(), "title");
+        assert_eq!(
+            &src[title.span.start().offset()..title.span.end().offset()],
+            "title"
+        );
+
+        // equality ignores the span
+        let moved = Spanned::new("title".to_string(), span(src, 0, 2));
+        assert_eq!(title, moved);
+        assert_ne!(title, Spanned::new("other".to_string(), title.span));
+    }
+
+    // source_and_underline picks the quoted line and its underline out of a
+    // snippet
+    fn source_and_underline(snippet: &str) -> (&str, &str) {
+        let mut lines = snippet.lines().skip(2);
+        let source = lines.next().unwrap();
+        let underline = lines.next().unwrap();
+        (&source[4..source.len() - 1], &underline[3..])
+    }
+
+    #[test]
+    fn test_snippet_expands_tabs() {
+        let src = "section s {\n\tparagraph\th1 { x } }\n}";
+        let start = src.find("h1").unwrap();
+        let s = span(src, start, start + 2);
+        assert_eq!(s.start().column(), 11);
+
+        let snippet = s.snippet(src);
+        assert!(!snippet.contains('\t'));
+        let (source, underline) = source_and_underline(&snippet);
+        assert_eq!(source, "paragraph   h1 { x } }");
+        assert_eq!(underline.find('^'), source.find("h1"));
+        assert_eq!(underline.len(), source.len());
+
+        let snippet = s.snippet_with_tab_width(src, 8);
+        let (source, underline) = source_and_underline(&snippet);
+        assert_eq!(source, "paragraph       h1 { x } }");
+        assert_eq!(underline.find('^'), source.find("h1"));
+    }
+
+    #[test]
+    fn test_snippet_counts_from_one() {
+        let src = "h1 { title }
+h2 { x }";
+        let start = src.find("h2").unwrap();
+        let s = span(src, start, start + 2);
+        assert_eq!((s.start().line(), s.start().column()), (1, 0));
+        assert!(s.snippet(src).starts_with("\nLine: 2, Column: 1\n"));
+
+        let first = span(src, 5, 10);
+        assert!(first.snippet(src).starts_with("\nLine: 1, Column: 6\n"));
+        // the JSON form counts from 0
+        let json = super::Diagnostic::error("m", first).to_json();
+        assert!(json.contains(r#""line":0,"column":5"#), "{}", json);
+    }
+}
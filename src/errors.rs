@@ -1,3 +1,7 @@
+use std::path::PathBuf;
+
+use crate::fs::InvalidUtf8;
+
 #[derive(Debug)]
 pub enum BloggerError {
     IOError(std::io::Error),
@@ -6,6 +10,25 @@ pub enum BloggerError {
     RegexError(String),
     LexerError(String),
     CommandError(String),
+    EmptySource,
+    // a source file that isn't UTF-8
+    EncodingError(PathBuf),
+}
+
+impl BloggerError {
+    // exit_code is the process exit status for this error, so scripts can
+    // tell failures apart: 2 lexer, 3 parse (including an empty source),
+    // 4 codegen, 5 command, 6 IO and 1 for anything else.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            BloggerError::LexerError(_) => 2,
+            BloggerError::ParseError(_) | BloggerError::EmptySource => 3,
+            BloggerError::CodegenError(_) => 4,
+            BloggerError::CommandError(_) => 5,
+            BloggerError::IOError(_) | BloggerError::EncodingError(_) => 6,
+            BloggerError::RegexError(_) => 1,
+        }
+    }
 }
 
 impl std::fmt::Display for BloggerError {
@@ -19,6 +42,10 @@ impl std::fmt::Display for BloggerError {
             BloggerError::RegexError(s) => write!(f, "Blogger Error: {}", s),
             BloggerError::LexerError(s) => write!(f, "Blogger Error: {}", s),
             BloggerError::CommandError(s) => write!(f, "Blogger Error: {}", s),
+            BloggerError::EmptySource => write!(f, "Blogger Error: source is empty"),
+            BloggerError::EncodingError(path) => {
+                write!(f, "Blogger Error: {}", InvalidUtf8(path.clone()))
+            }
         }
     }
 }
@@ -34,6 +61,53 @@ impl std::error::Error for BloggerError {
 
 impl From<std::io::Error> for BloggerError {
     fn from(e: std::io::Error) -> Self {
-        BloggerError::IOError(e)
+        match e
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<InvalidUtf8>())
+        {
+            Some(InvalidUtf8(path)) => BloggerError::EncodingError(path.clone()),
+            None => BloggerError::IOError(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::BloggerError;
+    use crate::fs;
+
+    #[test]
+    fn test_exit_codes() {
+        let cases = [
+            (BloggerError::RegexError(String::new()), 1),
+            (BloggerError::LexerError(String::new()), 2),
+            (BloggerError::ParseError(String::new()), 3),
+            (BloggerError::EmptySource, 3),
+            (BloggerError::CodegenError(String::new()), 4),
+            (BloggerError::CommandError(String::new()), 5),
+            (BloggerError::IOError(std::io::Error::other("disk")), 6),
+            (BloggerError::EncodingError(PathBuf::new()), 6),
+        ];
+        for (err, code) in cases {
+            assert_eq!(err.exit_code(), code, "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_invalid_utf8_names_the_file() {
+        let path = std::env::temp_dir().join(format!("blogger-latin1-{}.blog", std::process::id()));
+        std::fs::write(&path, b"article caf\xe9 { }").unwrap();
+        let err = BloggerError::from(fs::read_file_to_string(&path).unwrap_err());
+        assert!(matches!(&err, BloggerError::EncodingError(p) if *p == path));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Blogger Error: {} is not valid UTF-8, re-save it with UTF-8 encoding",
+                path.display()
+            )
+        );
+        std::fs::remove_file(&path).unwrap();
     }
 }
@@ -0,0 +1,70 @@
+use std::{
+    collections::BTreeMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use super::{create_write_buffer, read_file_to_string};
+
+pub const MANIFEST_NAME: &str = ".blogger-cache";
+
+// BuildCache records the hash of each source compiled by a directory build,
+// so a later build can skip the files that haven't changed. It is stored as
+// a `.blogger-cache` manifest in the output directory, one `hash path` line
+// per file.
+#[derive(Debug, Default)]
+pub struct BuildCache {
+    path: PathBuf,
+    entries: BTreeMap<String, u64>,
+}
+
+impl BuildCache {
+    // load reads the manifest in dir, a missing manifest is an empty cache
+    pub fn load<P: AsRef<Path>>(dir: P) -> io::Result<Self> {
+        let path = dir.as_ref().join(MANIFEST_NAME);
+        let contents = match read_file_to_string(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(err) => return Err(err),
+        };
+        let entries = contents
+            .lines()
+            .filter_map(|line| {
+                let (hash, key) = line.split_once(' ')?;
+                Some((key.to_string(), hash.parse().ok()?))
+            })
+            .collect();
+        Ok(Self { path, entries })
+    }
+
+    // is_fresh reports whether key was last compiled from a source with this hash
+    pub fn is_fresh(&self, key: &str, hash: u64) -> bool {
+        self.entries.get(key) == Some(&hash)
+    }
+
+    pub fn record(&mut self, key: &str, hash: u64) {
+        self.entries.insert(key.to_string(), hash);
+    }
+
+    pub fn remove(&mut self, key: &str) {
+        self.entries.remove(key);
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let mut buf = create_write_buffer(&self.path)?;
+        for (key, hash) in &self.entries {
+            writeln!(buf, "{} {}", hash, key)?;
+        }
+        buf.flush()
+    }
+}
+
+// hash_source hashes a source along with anything else its output depends
+// on, such as the backend and options it is compiled with
+pub fn hash_source<T: Hash>(src: &str, context: T) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    src.hash(&mut hasher);
+    context.hash(&mut hasher);
+    hasher.finish()
+}
@@ -1,16 +1,58 @@
+pub mod cache;
+
+use std::fmt;
 use std::fs::File;
 use std::io::{self, BufReader, BufWriter, Read};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
+// read_file_to_string reads a UTF-8 file. A file in any other encoding is
+// an InvalidData error wrapping InvalidUtf8, which names the file.
 pub fn read_file_to_string<P: AsRef<Path>>(path: P) -> io::Result<String> {
-    let file = File::open(path)?;
+    let file = File::open(&path)?;
     let mut reader = BufReader::new(file);
-    let mut contents = String::new();
-    reader.read_to_string(&mut contents)?;
-    Ok(contents)
+    let mut contents = Vec::new();
+    reader.read_to_end(&mut contents)?;
+    String::from_utf8(contents).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            InvalidUtf8(path.as_ref().to_path_buf()),
+        )
+    })
+}
+
+// InvalidUtf8 is the path of a file that isn't valid UTF-8
+#[derive(Debug)]
+pub struct InvalidUtf8(pub PathBuf);
+
+impl fmt::Display for InvalidUtf8 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is not valid UTF-8, re-save it with UTF-8 encoding",
+            self.0.display()
+        )
+    }
 }
 
+impl std::error::Error for InvalidUtf8 {}
+
 pub fn create_write_buffer<P: AsRef<Path>>(path: P) -> io::Result<BufWriter<File>> {
     let file = File::create(path)?;
     Ok(BufWriter::new(file))
 }
+
+// find_files walks dir recursively and returns every file with the given
+// extension, sorted so builds are deterministic.
+pub fn find_files<P: AsRef<Path>>(dir: P, ext: &str) -> io::Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            found.extend(find_files(&path, ext)?);
+        } else if path.extension().is_some_and(|e| e == ext) {
+            found.push(path);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
@@ -1,11 +1,15 @@
 use std::fmt;
 
-use crate::{diag::Span, errors::BloggerError};
+use crate::{
+    diag::{Diagnostic, Span},
+    errors::BloggerError,
+};
 
 #[derive(Debug, Clone)]
 pub enum LexerErrorKind {
     UnexpectedChar(char),
     UnterminatedBlock,
+    UnterminatedComment,
     UnexpectedEOF,
 }
 
@@ -32,6 +36,7 @@ impl LexerError {
                 format!("Unexpected character '{}' at: {}", c, snippet)
             }
             LexerErrorKind::UnterminatedBlock => format!("Unterminated block\n{}", snippet),
+            LexerErrorKind::UnterminatedComment => format!("Unterminated comment\n{}", snippet),
             LexerErrorKind::UnexpectedEOF => "Unexpected EOF".to_string(),
         }
     }
@@ -39,6 +44,22 @@ impl LexerError {
     pub fn span(&self) -> Span {
         self.span
     }
+
+    // message describes the error without the source snippet
+    pub fn message(&self) -> String {
+        match &self.kind {
+            LexerErrorKind::UnexpectedChar(c) => format!("Unexpected character '{}'", c),
+            LexerErrorKind::UnterminatedBlock => "Unterminated block".to_string(),
+            LexerErrorKind::UnterminatedComment => "Unterminated comment".to_string(),
+            LexerErrorKind::UnexpectedEOF => "Unexpected EOF".to_string(),
+        }
+    }
+}
+
+impl From<&LexerError> for Diagnostic {
+    fn from(value: &LexerError) -> Self {
+        Diagnostic::error(value.message(), value.span)
+    }
 }
 
 impl fmt::Display for LexerError {
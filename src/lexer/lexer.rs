@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use crate::diag::{Position, Span};
 
 use super::error::LexerError;
@@ -6,43 +8,154 @@ use super::{
     tokens::{Token, TokenKind, TokenSpec},
 };
 
+// Mode is what the lexer expects to read next. It moves between modes on
+// its own, starting_in_block is the only other place lexing can start.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Mode {
+    // tokens separated by insignificant whitespace
     Normal,
+    // the body of a text block, up to and excluding its closing backtick
     Block,
+    // after a `code` or `embed` keyword, lexing normally until its
+    // opening brace
+    RawBrace,
+    // inside the braces of a code or embed block without backticks
+    Raw,
 }
 
 pub struct Lexer<'a> {
     input: &'a str,
     position: Position,
-    specs: Vec<TokenSpec>,
+    specs: Arc<Vec<TokenSpec>>,
     mode: Mode,
+    // emit Comment tokens rather than skipping comments
+    keep_comments: bool,
+    // emit a BlankLine token for whitespace holding an empty line
+    blank_lines: bool,
 }
 
 impl<'a> Lexer<'a> {
-    pub fn new(input: &'a str, specs: Vec<TokenSpec>) -> Self {
+    // new accepts either an owned spec list or a shared one such as the
+    // cached defaults from token_specs
+    pub fn new(input: &'a str, specs: impl Into<Arc<Vec<TokenSpec>>>) -> Self {
         Self {
             input,
             position: Position::new(),
-            specs,
+            specs: specs.into(),
             mode: Mode::Normal,
+            keep_comments: false,
+            blank_lines: false,
         }
     }
 
+    // starting_in_block lexes input that begins partway through a text
+    // block, such as a snippet cut from a larger one: everything up to the
+    // first backtick is one text block, then lexing carries on normally.
+    pub fn starting_in_block(input: &'a str, specs: impl Into<Arc<Vec<TokenSpec>>>) -> Self {
+        Self {
+            mode: Mode::Block,
+            ..Self::new(input, specs)
+        }
+    }
+
+    // builder style toggle for emitting comments as tokens, for tools
+    // that need to preserve them. Comments are skipped by default.
+    pub fn with_keep_comments(mut self, keep_comments: bool) -> Self {
+        self.keep_comments = keep_comments;
+        self
+    }
+
+    // builder style toggle for emitting a BlankLine token wherever an empty
+    // line separates two tokens, so the parser can split paragraphs on them
+    // the way Markdown does. Off by default.
+    pub fn with_blank_lines(mut self, blank_lines: bool) -> Self {
+        self.blank_lines = blank_lines;
+        self
+    }
+
+    pub fn blank_lines(&self) -> bool {
+        self.blank_lines
+    }
+
+    // end_of_input is the position just after the last non-whitespace
+    // character, where errors about running out of input should point
+    pub fn end_of_input(&self) -> Position {
+        Position::new().advance_str(self.input.trim_end())
+    }
+
+    // tokens drains the lexer, returning every token or the first error
+    pub fn tokens(self) -> Result<Vec<Token>, LexerError> {
+        self.collect()
+    }
+
+    // tokens_with_errors drains the lexer past any errors, returning the
+    // tokens it could lex along with every error. After an unexpected char
+    // lexing resumes at the char that follows it.
+    pub fn tokens_with_errors(self) -> (Vec<Token>, Vec<LexerError>) {
+        let mut tokens = Vec::new();
+        let mut errors = Vec::new();
+        for result in self {
+            match result {
+                Ok(token) => tokens.push(token),
+                Err(err) => errors.push(err),
+            }
+        }
+        (tokens, errors)
+    }
+
     fn next_token(&mut self) -> Option<Result<Token, LexerError>> {
-        // Whitespace won't be skipped in TextBlocks
-        // because a backtick short circuits normal
-        // lexing flow
-        self.skip_whitespace();
+        // a backtick keeps the text block form of a code or embed block
+        if self.mode == Mode::Raw
+            && self.input[self.position.offset()..]
+                .trim_start()
+                .starts_with('`')
+        {
+            self.mode = Mode::Normal;
+        }
+        let normal = matches!(self.mode, Mode::Normal | Mode::RawBrace);
+        loop {
+            // Whitespace is only insignificant between tokens. Inside a text
+            // block it's content, so it's never skipped in block mode.
+            let start = self.position;
+            if normal {
+                self.skip_whitespace();
+            }
+
+            // End of input
+            if self.position.offset() >= self.input.len() {
+                return None;
+            }
 
-        // End of input
-        if self.position.offset() >= self.input.len() {
-            return None;
+            let skipped = &self.input[start.offset()..self.position.offset()];
+            if self.blank_lines && self.mode == Mode::Normal && skipped.matches('\n').count() > 1 {
+                return Some(Ok(self.make_token(
+                    TokenKind::BlankLine,
+                    start,
+                    self.position,
+                )));
+            }
+
+            if normal && self.input[self.position.offset()..].starts_with("/*") {
+                let comment = self.lex_comment();
+                if self.keep_comments || comment.is_err() {
+                    return Some(comment);
+                }
+                continue;
+            }
+            if normal && self.input[self.position.offset()..].starts_with("//") {
+                let comment = self.lex_line_comment();
+                if self.keep_comments {
+                    return Some(Ok(comment));
+                }
+                continue;
+            }
+            break;
         }
 
         Some(match self.mode {
-            Mode::Normal => self.lex_normal(),
+            Mode::Normal | Mode::RawBrace => self.lex_normal(),
             Mode::Block => self.lex_block(),
+            Mode::Raw => self.lex_raw(),
         })
     }
 
@@ -56,12 +169,22 @@ impl<'a> Lexer<'a> {
             if let TokenKind::TextBlock(s) = &kind {
                 if s == "`" {
                     self.mode = Mode::Block;
-                    return self.lex_block();
+                    // widen the span to include the opening backtick
+                    return self
+                        .lex_block()
+                        .map(|token| self.make_token(token.kind, start, token.span.end()));
                 }
             }
+            self.mode = match (&kind, self.mode) {
+                (TokenKind::Code | TokenKind::Embed, _) => Mode::RawBrace,
+                (TokenKind::LBrace, Mode::RawBrace) => Mode::Raw,
+                _ => Mode::Normal,
+            };
             Ok(self.make_token(kind, start, self.position))
         } else {
+            // step over the bad char so the next call makes progress
             let ch = self.peek_char().unwrap();
+            self.advance_char();
             Err(LexerError::new(
                 LexerErrorKind::UnexpectedChar(ch),
                 Span::new(start, self.position),
@@ -71,61 +194,120 @@ impl<'a> Lexer<'a> {
     }
 
     // tokenises a text block, omitting the wrapping backticks
-    // and absorbing the internal text.
+    // and absorbing the internal text. The text is kept verbatim: a
+    // leading newline, indentation and trailing whitespace all survive,
+    // which is what code blocks rely on.
     fn lex_block(&mut self) -> Result<Token, LexerError> {
         let start = self.position;
         let remaining = &self.input[self.position.offset()..];
         if let Some(rel_end) = remaining.find('`') {
             let text = &remaining[..rel_end];
-            // Advance over the block text.
-            for _ in 0..text.len() {
-                self.advance_char();
-            }
-            // Consume the backtick.
-            self.advance_char();
+            // Jump over the block text and the closing backtick.
+            self.position = self.position.advance_str(&remaining[..=rel_end]);
             self.mode = Mode::Normal;
             Ok(self.make_token(TokenKind::TextBlock(text.to_string()), start, self.position))
         } else {
+            // the block runs to the end of input, there is nothing left to lex
+            self.position = start.advance_str(remaining);
+            self.mode = Mode::Normal;
             Err(LexerError::new(
                 LexerErrorKind::UnterminatedBlock,
-                Span::new(start, self.position),
+                Span::new(start, start),
                 self.input,
             ))
         }
     }
 
-    // Expands the current window until no more matches are found,
-    // returning the last match it encountered.
-    //
-    // Runs in linear time but may be suboptimal in the way the input is handled
-    // but source code management in this project is generally quite hacky.
-    //
-    // TODO: make faster and cleaner?
+    // tokenises the body of a `code { ... }` or `embed { ... }` block written
+    // without backticks as a text block, up to the brace that closes it.
+    // Nested braces are balanced so most code can be written as is. Blank
+    // lines and whitespace around the text are dropped, indentation is kept.
+    fn lex_raw(&mut self) -> Result<Token, LexerError> {
+        let start = self.position;
+        let remaining = &self.input[self.position.offset()..];
+        let mut depth = 0;
+        let end = remaining.char_indices().find_map(|(i, ch)| {
+            match ch {
+                '{' => depth += 1,
+                '}' if depth == 0 => return Some(i),
+                '}' => depth -= 1,
+                _ => {}
+            }
+            None
+        });
+        self.mode = Mode::Normal;
+        match end {
+            Some(end) => {
+                let raw = &remaining[..end];
+                self.position = self.position.advance_str(raw);
+                let leading = raw.len() - raw.trim_start().len();
+                let text = match raw[..leading].rfind('\n') {
+                    Some(newline) => &raw[newline + 1..],
+                    None => &raw[leading..],
+                };
+                Ok(self.make_token(
+                    TokenKind::TextBlock(text.trim_end().to_string()),
+                    start,
+                    self.position,
+                ))
+            }
+            None => {
+                self.position = start.advance_str(remaining);
+                Err(LexerError::new(
+                    LexerErrorKind::UnterminatedBlock,
+                    Span::new(start, start),
+                    self.input,
+                ))
+            }
+        }
+    }
+
+    // tokenises a `/* ... */` comment, keeping the text between the markers
+    fn lex_comment(&mut self) -> Result<Token, LexerError> {
+        let start = self.position;
+        let body = &self.input[self.position.offset() + 2..];
+        if let Some(rel_end) = body.find("*/") {
+            let text = body[..rel_end].to_string();
+            self.position = self.position.advance_str(&format!("/*{}*/", text));
+            Ok(self.make_token(TokenKind::Comment(text), start, self.position))
+        } else {
+            self.position = start.advance_str(&self.input[start.offset()..]);
+            Err(LexerError::new(
+                LexerErrorKind::UnterminatedComment,
+                Span::new(start, start),
+                self.input,
+            ))
+        }
+    }
+
+    // tokenises a `// ...` comment, keeping the text after the marker. The
+    // newline ending it is left to be skipped as whitespace, so line
+    // tracking and blank lines work as they would without the comment.
+    fn lex_line_comment(&mut self) -> Token {
+        let start = self.position;
+        let rest = &self.input[self.position.offset()..];
+        let line = &rest[..rest.find('\n').unwrap_or(rest.len())];
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        self.position = self.position.advance_str(line);
+        self.make_token(
+            TokenKind::Comment(line[2..].to_string()),
+            start,
+            self.position,
+        )
+    }
+
+    // Finds the longest token starting at the cursor (maximal munch). Each
+    // spec reports its longest match in one pass; on a tie the spec listed
+    // first wins, which is how keywords take priority over identifiers.
     fn best_match(&mut self) -> Option<(TokenKind, usize)> {
-        let mut candidate = String::new();
+        let remaining = &self.input[self.position.offset()..];
         let mut last_match: Option<(TokenKind, usize)> = None;
-        let mut chars = self.input[self.position.offset()..].chars().peekable();
-        let mut char_count = 0;
-
-        // Keep adding one character at a time until no match is found
-        while let Some(&ch) = chars.peek() {
-            // Add the next character to our candidate string
-            candidate.push(ch);
-            char_count += 1;
-            chars.next();
-
-            let mut matched = false;
-            for spec in &self.specs {
-                if let Some(kind) = spec.try_match(&candidate) {
-                    last_match = Some((kind.clone(), char_count));
-                    matched = true;
-                    break;
+        for spec in self.specs.iter() {
+            if let Some((kind, len)) = spec.match_prefix(remaining) {
+                if last_match.as_ref().is_none_or(|(_, best)| len > *best) {
+                    last_match = Some((kind, len));
                 }
             }
-
-            if !matched {
-                break;
-            }
         }
 
         // Apply the match if we found one
@@ -177,3 +359,248 @@ impl<'a> Iterator for Lexer<'a> {
         self.next_token()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Lexer;
+    use crate::lexer::tokens::{token_specs, TokenKind, TokenSpec};
+
+    fn kinds(lexer: Lexer) -> Vec<TokenKind> {
+        lexer.map(|token| token.unwrap().kind).collect()
+    }
+
+    #[test]
+    fn test_comments_skipped_by_default() {
+        let src = "/* intro */ section /* multi\nline */ s";
+        assert_eq!(
+            kinds(Lexer::new(src, token_specs())),
+            vec![TokenKind::Section, TokenKind::Ident("s".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_keep_comments() {
+        let src = "/* intro */ section /* multi\nline */ s";
+        assert_eq!(
+            kinds(Lexer::new(src, token_specs()).with_keep_comments(true)),
+            vec![
+                TokenKind::Comment(" intro ".to_string()),
+                TokenKind::Section,
+                TokenKind::Comment(" multi\nline ".to_string()),
+                TokenKind::Ident("s".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_line_comments() {
+        let src = "section s // the intro\r\n{ // opens\n// a whole line\n} // last";
+        assert_eq!(
+            kinds(Lexer::new(src, token_specs())),
+            vec![
+                TokenKind::Section,
+                TokenKind::Ident("s".to_string()),
+                TokenKind::LBrace,
+                TokenKind::RBrace,
+            ]
+        );
+        let starts: Vec<_> = Lexer::new(src, token_specs())
+            .tokens()
+            .unwrap()
+            .iter()
+            .map(|token| {
+                let start = token.span.start();
+                (start.line(), start.column(), start.offset())
+            })
+            .collect();
+        assert_eq!(starts, vec![(0, 0, 0), (0, 8, 8), (1, 0, 24), (3, 0, 51)]);
+
+        let kept = kinds(Lexer::new(src, token_specs()).with_keep_comments(true));
+        assert_eq!(kept[2], TokenKind::Comment(" the intro".to_string()));
+        assert_eq!(kept[5], TokenKind::Comment(" a whole line".to_string()));
+
+        // a comment line doesn't stop blank lines being seen around it
+        let src = "a\n// note\n\nb";
+        let spaced = kinds(Lexer::new(src, token_specs()).with_blank_lines(true));
+        assert_eq!(spaced[1], TokenKind::BlankLine);
+    }
+
+    #[test]
+    fn test_unterminated_comment() {
+        let mut lexer = Lexer::new("section /* oops", token_specs());
+        assert!(lexer.next().unwrap().is_ok());
+        assert_eq!(
+            lexer.next().unwrap().unwrap_err().message(),
+            "Unterminated comment"
+        );
+    }
+
+    #[test]
+    fn test_multiline_block_span() {
+        let src = "`one\ntwö\nthree` section";
+        let tokens: Vec<_> = Lexer::new(src, token_specs())
+            .map(|token| token.unwrap())
+            .collect();
+        let end = tokens[0].span.end();
+        assert_eq!((end.line(), end.column(), end.offset()), (2, 6, 16));
+        assert_eq!(tokens[1].kind, TokenKind::Section);
+        assert_eq!(tokens[1].span.start().offset(), 17);
+    }
+
+    #[test]
+    fn test_tokens() {
+        let tokens = Lexer::new("section s { }", token_specs()).tokens().unwrap();
+        let kinds: Vec<TokenKind> = tokens.into_iter().map(|token| token.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                TokenKind::Section,
+                TokenKind::Ident("s".to_string()),
+                TokenKind::LBrace,
+                TokenKind::RBrace,
+            ]
+        );
+
+        let err = Lexer::new("section ! s ?", token_specs())
+            .tokens()
+            .unwrap_err();
+        assert_eq!(err.message(), "Unexpected character '!'");
+    }
+
+    #[test]
+    fn test_block_whitespace_is_verbatim() {
+        let src = "code {`\n    fn main() {\n\t\tindented();\n    }\n`}";
+        let kinds = kinds(Lexer::new(src, token_specs()));
+        assert_eq!(
+            kinds[2],
+            TokenKind::TextBlock("\n    fn main() {\n\t\tindented();\n    }\n".to_string())
+        );
+    }
+
+    #[test]
+    fn test_heading_levels() {
+        assert_eq!(
+            kinds(Lexer::new("h1 h4 h6 h7", token_specs())),
+            vec![
+                TokenKind::Heading("h1".to_string()),
+                TokenKind::Heading("h4".to_string()),
+                TokenKind::Heading("h6".to_string()),
+                TokenKind::Ident("h7".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unicode_identifier() {
+        assert_eq!(
+            kinds(Lexer::new("section café { }", token_specs())),
+            vec![
+                TokenKind::Section,
+                TokenKind::Ident("café".to_string()),
+                TokenKind::LBrace,
+                TokenKind::RBrace,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_crlf_span() {
+        let src = "section s {\r\n  paragraph\r\n}";
+        let tokens = Lexer::new(src, token_specs()).tokens().unwrap();
+        assert_eq!(tokens[3].kind, TokenKind::Paragraph);
+        let (start, end) = (tokens[3].span.start(), tokens[3].span.end());
+        assert_eq!((start.line(), start.column(), start.offset()), (1, 2, 15));
+        assert_eq!((end.line(), end.column()), (1, 11));
+        assert_eq!(tokens[4].span.start().line(), 2);
+        assert_eq!(tokens[4].span.start().column(), 0);
+    }
+
+    #[test]
+    fn test_resync_after_unexpected_char() {
+        let mut lexer = Lexer::new("section ! s ? }", token_specs());
+        assert_eq!(lexer.next().unwrap().unwrap().kind, TokenKind::Section);
+        let first = lexer.next().unwrap().unwrap_err();
+        assert_eq!(first.message(), "Unexpected character '!'");
+        assert_eq!(
+            lexer.next().unwrap().unwrap().kind,
+            TokenKind::Ident("s".to_string())
+        );
+        let second = lexer.next().unwrap().unwrap_err();
+        assert_eq!(second.message(), "Unexpected character '?'");
+        assert_eq!(lexer.next().unwrap().unwrap().kind, TokenKind::RBrace);
+        assert!(lexer.next().is_none());
+
+        let (tokens, errors) = Lexer::new("! section `open", token_specs()).tokens_with_errors();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn test_blank_lines() {
+        let src = "`a`\n`b`\n  \r\n\n`c`";
+        assert!(!kinds(Lexer::new(src, token_specs())).contains(&TokenKind::BlankLine));
+        assert_eq!(
+            kinds(Lexer::new(src, token_specs()).with_blank_lines(true)),
+            vec![
+                TokenKind::TextBlock("a".to_string()),
+                TokenKind::TextBlock("b".to_string()),
+                TokenKind::BlankLine,
+                TokenKind::TextBlock("c".to_string()),
+            ]
+        );
+    }
+
+    // the search best_match replaced: every prefix is tried against every
+    // spec from scratch, a longer match wins and ties go to the earlier spec
+    fn exhaustive_match(specs: &[TokenSpec], input: &str) -> Option<TokenKind> {
+        let mut best = None;
+        let mut candidate = String::new();
+        for ch in input.chars().take(64) {
+            candidate.push(ch);
+            if let Some(kind) = specs.iter().find_map(|spec| spec.try_match(&candidate)) {
+                best = Some(kind);
+            }
+        }
+        best
+    }
+
+    #[test]
+    fn test_best_match_agrees_with_exhaustive_search() {
+        let src = include_str!("../../blog/article.blog");
+        let specs = token_specs();
+        let tokens = Lexer::new(src, specs.clone()).tokens().unwrap();
+        assert!(tokens.len() > 100);
+        for token in tokens {
+            if matches!(token.kind, TokenKind::TextBlock(_)) {
+                continue;
+            }
+            let rest = &src[token.span.start().offset()..];
+            assert_eq!(Some(token.kind), exhaustive_match(&specs, rest));
+        }
+    }
+
+    #[test]
+    fn test_start_in_block_mode() {
+        let src = "  rest of a block\n` section s";
+        let lexer = Lexer::starting_in_block(src, token_specs());
+        let tokens = lexer.tokens().unwrap();
+        assert_eq!(
+            tokens.iter().map(|t| t.kind.clone()).collect::<Vec<_>>(),
+            vec![
+                TokenKind::TextBlock("  rest of a block\n".to_string()),
+                TokenKind::Section,
+                TokenKind::Ident("s".to_string()),
+            ]
+        );
+        // the block's span runs from the start of input through its
+        // closing backtick
+        assert_eq!(tokens[0].span.start().offset(), 0);
+        assert_eq!(tokens[0].span.end().offset(), 19);
+        assert_eq!(tokens[1].span.start().offset(), 20);
+
+        let err = Lexer::starting_in_block("never closed", token_specs())
+            .tokens()
+            .unwrap_err();
+        assert!(err.to_string().contains("Unterminated block"));
+    }
+}
@@ -1,3 +1,4 @@
 pub mod error;
+#[allow(clippy::module_inception)]
 pub mod lexer;
 pub mod tokens;
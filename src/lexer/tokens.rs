@@ -1,4 +1,12 @@
-use crate::{diag::Span, regex::matcher::Matcher};
+use std::{
+    fmt,
+    sync::{Arc, OnceLock},
+};
+
+use crate::{
+    diag::{escape_json, Span},
+    regex::matcher::Matcher,
+};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum TokenKind {
@@ -9,14 +17,79 @@ pub enum TokenKind {
     RBrace,
     LParen,
     RParen,
+    LBracket,
+    RBracket,
+    Equals,
+    Comma,
     Heading(String),
     Aside,
     OList,
     UList,
+    CList,
     LItem,
     Code,
+    Html,
+    Math,
+    Footnote,
+    Columns,
+    Embed,
+    Anchor,
+    Import,
+    Preamble,
+    Footer,
+    // a double quoted string, holding the text between the quotes
+    Str(String),
     TextBlock(String),
     Ident(String),
+    // a `/* ... */` or `// ...` comment, only emitted when the lexer keeps
+    // comments
+    Comment(String),
+    // whitespace holding an empty line, only emitted when the lexer marks
+    // paragraph breaks
+    BlankLine,
+    // produced by specs registered outside this crate, see TokenSpecRegistry
+    Custom(String),
+}
+
+// Display names tokens the way a user would describe them, for error messages.
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenKind::Section => write!(f, "keyword 'section'"),
+            TokenKind::Article => write!(f, "keyword 'article'"),
+            TokenKind::Paragraph => write!(f, "keyword 'paragraph'"),
+            TokenKind::LBrace => write!(f, "opening brace"),
+            TokenKind::RBrace => write!(f, "closing brace"),
+            TokenKind::LParen => write!(f, "opening parenthesis"),
+            TokenKind::RParen => write!(f, "closing parenthesis"),
+            TokenKind::LBracket => write!(f, "opening bracket"),
+            TokenKind::RBracket => write!(f, "closing bracket"),
+            TokenKind::Equals => write!(f, "'='"),
+            TokenKind::Comma => write!(f, "','"),
+            TokenKind::Heading(level) => write!(f, "heading level '{}'", level),
+            TokenKind::Aside => write!(f, "keyword 'aside'"),
+            TokenKind::OList => write!(f, "keyword 'ol'"),
+            TokenKind::UList => write!(f, "keyword 'ul'"),
+            TokenKind::CList => write!(f, "keyword 'cl'"),
+            TokenKind::LItem => write!(f, "keyword 'li'"),
+            TokenKind::Code => write!(f, "keyword 'code'"),
+            TokenKind::Html => write!(f, "keyword 'html'"),
+            TokenKind::Math => write!(f, "keyword 'math'"),
+            TokenKind::Footnote => write!(f, "keyword 'footnote'"),
+            TokenKind::Columns => write!(f, "keyword 'columns'"),
+            TokenKind::Embed => write!(f, "keyword 'embed'"),
+            TokenKind::Anchor => write!(f, "keyword 'anchor'"),
+            TokenKind::Import => write!(f, "keyword 'import'"),
+            TokenKind::Preamble => write!(f, "keyword 'preamble'"),
+            TokenKind::Footer => write!(f, "keyword 'footer'"),
+            TokenKind::Str(s) => write!(f, "string \"{}\"", s),
+            TokenKind::TextBlock(_) => write!(f, "text block"),
+            TokenKind::Ident(s) => write!(f, "identifier '{}'", s),
+            TokenKind::Comment(_) => write!(f, "comment"),
+            TokenKind::BlankLine => write!(f, "blank line"),
+            TokenKind::Custom(s) => write!(f, "'{}'", s),
+        }
+    }
 }
 
 /// A Token containing its TokenKind plus a Span.
@@ -26,6 +99,19 @@ pub struct Token {
     pub span: Span,
 }
 
+impl Token {
+    // to_json renders the token as a single line JSON object, the kind in
+    // the same Debug form `emit tokens` prints
+    pub fn to_json(&self) -> String {
+        format!(
+            r#"{{"kind":"{}","start":{},"end":{}}}"#,
+            escape_json(&format!("{:?}", self.kind)),
+            self.span.start().to_json(),
+            self.span.end().to_json()
+        )
+    }
+}
+
 pub struct TokenSpec {
     matcher: Matcher,
     to_kind: fn(&str) -> TokenKind,
@@ -36,6 +122,17 @@ impl TokenSpec {
         Self { matcher, to_kind }
     }
 
+    // match_prefix matches the longest non-empty prefix of `input`,
+    // returning its kind and length in chars.
+    pub fn match_prefix(&self, input: &str) -> Option<(TokenKind, usize)> {
+        let len = self.matcher.longest_match(input).filter(|&len| len > 0)?;
+        let end = input
+            .char_indices()
+            .nth(len)
+            .map_or(input.len(), |(idx, _)| idx);
+        Some(((self.to_kind)(&input[..end]), len))
+    }
+
     pub fn try_match(&self, input: &str) -> Option<TokenKind> {
         if self.matcher.matches(input) {
             Some((self.to_kind)(input))
@@ -45,34 +142,153 @@ impl TokenSpec {
     }
 }
 
-pub fn token_specs() -> Vec<TokenSpec> {
+// TokenSpecRegistry assembles the spec list handed to the Lexer, so callers
+// can add their own tokens without forking token_specs. Order is priority:
+// when two specs match the same length, the earlier one wins.
+pub struct TokenSpecRegistry {
+    specs: Vec<TokenSpec>,
+}
+
+impl TokenSpecRegistry {
+    pub fn new() -> Self {
+        Self { specs: Vec::new() }
+    }
+
+    // with_defaults seeds the registry with the built-in specs
+    pub fn with_defaults() -> Self {
+        Self {
+            specs: default_specs(),
+        }
+    }
+
+    // add registers a spec with the lowest priority
+    pub fn add(mut self, matcher: Matcher, to_kind: fn(&str) -> TokenKind) -> Self {
+        self.specs.push(TokenSpec::new(matcher, to_kind));
+        self
+    }
+
+    // add_first registers a spec with the highest priority, which is what a
+    // new keyword needs to win over identifiers
+    pub fn add_first(mut self, matcher: Matcher, to_kind: fn(&str) -> TokenKind) -> Self {
+        self.specs.insert(0, TokenSpec::new(matcher, to_kind));
+        self
+    }
+
+    pub fn build(self) -> Vec<TokenSpec> {
+        self.specs
+    }
+}
+
+impl Default for TokenSpecRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// token_specs returns the built-in specs. Compiling the matchers is
+// expensive, so they're built once and shared by every lexer.
+pub fn token_specs() -> Arc<Vec<TokenSpec>> {
+    static SPECS: OnceLock<Arc<Vec<TokenSpec>>> = OnceLock::new();
+    SPECS.get_or_init(|| Arc::new(default_specs())).clone()
+}
+
+fn default_specs() -> Vec<TokenSpec> {
     vec![
         TokenSpec::new(Matcher::new("\\{").unwrap(), |_| TokenKind::LBrace),
         TokenSpec::new(Matcher::new("\\}").unwrap(), |_| TokenKind::RBrace),
         TokenSpec::new(Matcher::new("\\(").unwrap(), |_| TokenKind::LParen),
         TokenSpec::new(Matcher::new("\\)").unwrap(), |_| TokenKind::RParen),
-        TokenSpec::new(Matcher::new("(s.e.c.t.i.o.n)").unwrap(), |_| {
-            TokenKind::Section
-        }),
-        TokenSpec::new(Matcher::new("(a.r.t.i.c.l.e)").unwrap(), |_| {
-            TokenKind::Article
-        }),
-        TokenSpec::new(Matcher::new("(p.a.r.a.g.r.a.p.h)").unwrap(), |_| {
+        TokenSpec::new(Matcher::new("\\[").unwrap(), |_| TokenKind::LBracket),
+        TokenSpec::new(Matcher::new("\\]").unwrap(), |_| TokenKind::RBracket),
+        TokenSpec::new(Matcher::new("=").unwrap(), |_| TokenKind::Equals),
+        TokenSpec::new(Matcher::new(",").unwrap(), |_| TokenKind::Comma),
+        TokenSpec::new(Matcher::new("\"section\"").unwrap(), |_| TokenKind::Section),
+        TokenSpec::new(Matcher::new("\"article\"").unwrap(), |_| TokenKind::Article),
+        TokenSpec::new(Matcher::new("\"paragraph\"").unwrap(), |_| {
             TokenKind::Paragraph
         }),
-        TokenSpec::new(Matcher::new("(h.[1-3])").unwrap(), |s| {
+        TokenSpec::new(Matcher::new("(h.[1-6])").unwrap(), |s| {
             TokenKind::Heading(s.to_string())
         }),
-        TokenSpec::new(Matcher::new("(a.s.i.d.e)").unwrap(), |_| TokenKind::Aside),
-        TokenSpec::new(Matcher::new("(o.l)").unwrap(), |_| TokenKind::OList),
-        TokenSpec::new(Matcher::new("(u.l)").unwrap(), |_| TokenKind::UList),
-        TokenSpec::new(Matcher::new("(l.i)").unwrap(), |_| TokenKind::LItem),
-        TokenSpec::new(Matcher::new("(c.o.d.e)").unwrap(), |_| TokenKind::Code),
+        TokenSpec::new(Matcher::new("\"aside\"").unwrap(), |_| TokenKind::Aside),
+        TokenSpec::new(Matcher::new("\"ol\"").unwrap(), |_| TokenKind::OList),
+        TokenSpec::new(Matcher::new("\"ul\"").unwrap(), |_| TokenKind::UList),
+        TokenSpec::new(Matcher::new("\"cl\"").unwrap(), |_| TokenKind::CList),
+        TokenSpec::new(Matcher::new("\"li\"").unwrap(), |_| TokenKind::LItem),
+        TokenSpec::new(Matcher::new("\"code\"").unwrap(), |_| TokenKind::Code),
+        TokenSpec::new(Matcher::new("\"html\"").unwrap(), |_| TokenKind::Html),
+        TokenSpec::new(Matcher::new("\"math\"").unwrap(), |_| TokenKind::Math),
+        TokenSpec::new(Matcher::new("\"footnote\"").unwrap(), |_| {
+            TokenKind::Footnote
+        }),
+        TokenSpec::new(Matcher::new("\"columns\"").unwrap(), |_| TokenKind::Columns),
+        TokenSpec::new(Matcher::new("\"embed\"").unwrap(), |_| TokenKind::Embed),
+        TokenSpec::new(Matcher::new("\"anchor\"").unwrap(), |_| TokenKind::Anchor),
+        TokenSpec::new(Matcher::new("\"import\"").unwrap(), |_| TokenKind::Import),
+        TokenSpec::new(Matcher::new("\"preamble\"").unwrap(), |_| {
+            TokenKind::Preamble
+        }),
+        TokenSpec::new(Matcher::new("\"footer\"").unwrap(), |_| TokenKind::Footer),
+        TokenSpec::new(
+            Matcher::new("\\\".(([a-z]|[A-Z]|[0-9]|/|\\.|_|-)*).\\\"").unwrap(),
+            |s| TokenKind::Str(s[1..s.len() - 1].to_string()),
+        ),
         TokenSpec::new(Matcher::new("(`)").unwrap(), |s| {
             TokenKind::TextBlock(s.to_string())
         }),
-        TokenSpec::new(Matcher::new("(([a-z]|[A-Z]|[0-9])*)").unwrap(), |s| {
+        // identifiers may contain hyphens after the first char, for ids
+        // such as intro-section
+        TokenSpec::new(Matcher::new("(\\w.(\\w|-)*)").unwrap(), |s| {
             TokenKind::Ident(s.to_string())
         }),
     ]
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::{token_specs, TokenKind, TokenSpecRegistry};
+    use crate::{lexer::lexer::Lexer, regex::matcher::Matcher};
+
+    fn kinds(src: &str, registry: TokenSpecRegistry) -> Vec<TokenKind> {
+        Lexer::new(src, registry.build())
+            .map(|token| token.unwrap().kind)
+            .collect()
+    }
+
+    #[test]
+    fn test_custom_keyword() {
+        let todo = || Matcher::new("(T.O.D.O)").unwrap();
+        let registry = TokenSpecRegistry::with_defaults()
+            .add_first(todo(), |s| TokenKind::Custom(s.to_string()));
+        assert_eq!(
+            kinds("section TODO TODOs", registry),
+            vec![
+                TokenKind::Section,
+                TokenKind::Custom("TODO".to_string()),
+                TokenKind::Ident("TODOs".to_string()),
+            ]
+        );
+
+        // registered last, the identifier spec wins the tie
+        let registry =
+            TokenSpecRegistry::with_defaults().add(todo(), |s| TokenKind::Custom(s.to_string()));
+        assert_eq!(
+            kinds("TODO", registry),
+            vec![TokenKind::Ident("TODO".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_default_specs_are_shared() {
+        let first = token_specs();
+        let handles: Vec<_> = (0..4).map(|_| std::thread::spawn(token_specs)).collect();
+        for handle in handles {
+            assert!(Arc::ptr_eq(&first, &handle.join().unwrap()));
+        }
+        let lexer = Lexer::new("section", token_specs());
+        assert_eq!(lexer.count(), 1);
+        assert!(Arc::ptr_eq(&first, &token_specs()));
+    }
+}
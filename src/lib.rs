@@ -1,4 +1,12 @@
-use backend::codegen::Generator;
+use std::{
+    fmt,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use backend::{codegen::Generator, theme::Theme};
+use diag::escape_json;
+use errors::BloggerError;
 use lexer::{lexer::Lexer, tokens::token_specs};
 use parser::parser::Parser;
 use wasm_bindgen::prelude::wasm_bindgen;
@@ -11,20 +19,173 @@ pub mod fs;
 pub mod lexer;
 pub mod parser;
 pub mod regex;
+pub mod util;
 
 // Allows compilation to run through web assembly bindings
 #[wasm_bindgen]
 pub fn compile_source(src: &str) -> String {
+    compile_str(src).map_err(|e| e.to_string()).unwrap()
+}
+
+// Lexes src for a playground showing live tokenization, see lex_tokens_json
+#[wasm_bindgen]
+pub fn lex_source_tokens(src: &str) -> String {
+    lex_tokens_json(src)
+}
+
+// lex_tokens_json lexes src into a JSON array of tokens. Lexing stops at the
+// first error, which becomes a final element with an `error` field in
+// place of the kind.
+pub fn lex_tokens_json(src: &str) -> String {
+    let mut elements = Vec::new();
+    for result in Lexer::new(src, token_specs()) {
+        match result {
+            Ok(token) => elements.push(token.to_json()),
+            Err(err) => {
+                elements.push(format!(
+                    r#"{{"error":"{}","start":{},"end":{}}}"#,
+                    escape_json(&err.message()),
+                    err.span().start().to_json(),
+                    err.span().end().to_json()
+                ));
+                break;
+            }
+        }
+    }
+    format!("[{}]", elements.join(","))
+}
+
+// compile_str runs the whole pipeline over src using the default backend.
+pub fn compile_str(src: &str) -> Result<String, BloggerError> {
+    compile_str_timed(src).map(|(out, _)| out)
+}
+
+// compile_str_timed is compile_str, also reporting how long each phase took
+pub fn compile_str_timed(src: &str) -> Result<(String, Timings), BloggerError> {
+    ensure_not_empty(src)?;
     let src_content = src.to_string();
-    let mut dst_buf = Vec::new();
     let lexer = Lexer::new(&src_content, token_specs());
-    let mut parser = Parser::new(lexer, &src_content);
-    let program = parser.parse().map_err(|e| e.to_string()).unwrap();
-    let mut compiler = Generator::new(program);
-    println!("sigma");
-    compiler
-        .compile(&mut dst_buf)
-        .map_err(|e| e.to_string())
-        .unwrap();
-    String::from_utf8(dst_buf).unwrap()
+    let mut parser = Parser::new(lexer, &src_content, Path::new(""));
+    let ((), lex) = Timings::time(|| parser.lex_ahead());
+    let (program, parse) = Timings::time(|| parser.parse());
+    let mut compiler = Generator::new(program?, Theme::default());
+    let (out, codegen) = Timings::time(|| compiler.compile_to_string());
+    let timings = Timings {
+        lex,
+        parse,
+        codegen,
+    };
+    Ok((out?, timings))
+}
+
+// Timings records how long each phase of a compile took, as reported by
+// `compile --time`
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Timings {
+    pub lex: Duration,
+    pub parse: Duration,
+    pub codegen: Duration,
+}
+
+impl Timings {
+    // time runs f, returning its result along with how long it took
+    pub fn time<T>(f: impl FnOnce() -> T) -> (T, Duration) {
+        let start = Instant::now();
+        let out = f();
+        (out, start.elapsed())
+    }
+
+    pub fn total(&self) -> Duration {
+        self.lex + self.parse + self.codegen
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (phase, duration) in [
+            ("lex", self.lex),
+            ("parse", self.parse),
+            ("codegen", self.codegen),
+            ("total", self.total()),
+        ] {
+            writeln!(
+                f,
+                "{:<16}{:>10.3}ms",
+                phase,
+                duration.as_secs_f64() * 1000.0
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// ensure_not_empty rejects sources with nothing but whitespace up front,
+// rather than letting the parser report a missing article at 0:0.
+pub fn ensure_not_empty(src: &str) -> Result<(), BloggerError> {
+    if src.trim().is_empty() {
+        return Err(BloggerError::EmptySource);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compile_str, compile_str_timed, lex_tokens_json};
+    use crate::errors::BloggerError;
+
+    #[test]
+    fn test_empty_source() {
+        assert!(matches!(compile_str(""), Err(BloggerError::EmptySource)));
+    }
+
+    #[test]
+    fn test_whitespace_only_source() {
+        assert!(matches!(
+            compile_str("  \n\t \r\n"),
+            Err(BloggerError::EmptySource)
+        ));
+        assert_eq!(
+            compile_str("\n").unwrap_err().to_string(),
+            "Blogger Error: source is empty"
+        );
+    }
+
+    #[test]
+    fn test_compile_str_timed() {
+        let src = "article a { s } section s { paragraph { `timed` } }";
+        let (out, timings) = compile_str_timed(src).unwrap();
+        assert_eq!(out, compile_str(src).unwrap());
+        assert_eq!(
+            timings.total(),
+            timings.lex + timings.parse + timings.codegen
+        );
+        assert_eq!(timings.to_string().lines().count(), 4);
+        assert!(timings.to_string().starts_with("lex "));
+    }
+
+    #[test]
+    fn test_section_defined_after_call() {
+        let called_first =
+            compile_str("article a { s } section s { paragraph { `body` } }").unwrap();
+        let called_last =
+            compile_str("section s { paragraph { `body` } } article a { s }").unwrap();
+        assert!(called_first.contains("<section id='s'>\n<br/>\n<p>body</p>\n</section>"));
+        assert_eq!(called_first, called_last);
+    }
+
+    #[test]
+    fn test_lex_tokens_json() {
+        assert_eq!(
+            lex_tokens_json("h1 {x}"),
+            r#"[{"kind":"Heading(\"h1\")","start":{"line":0,"column":0,"offset":0},"end":{"line":0,"column":2,"offset":2}},{"kind":"LBrace","start":{"line":0,"column":3,"offset":3},"end":{"line":0,"column":4,"offset":4}},{"kind":"Ident(\"x\")","start":{"line":0,"column":4,"offset":4},"end":{"line":0,"column":5,"offset":5}},{"kind":"RBrace","start":{"line":0,"column":5,"offset":5},"end":{"line":0,"column":6,"offset":6}}]"#
+        );
+
+        // stops at the first error
+        let json = lex_tokens_json("{ ; }");
+        assert!(json.starts_with(r#"[{"kind":"LBrace""#));
+        assert!(json.ends_with(
+            r#"{"error":"Unexpected character ';'","start":{"line":0,"column":2,"offset":2},"end":{"line":0,"column":3,"offset":3}}]"#
+        ));
+        assert!(!json.contains("RBrace"));
+    }
 }
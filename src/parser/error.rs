@@ -1,7 +1,11 @@
 use std::error::Error;
 use std::fmt;
 
-use crate::{diag::Span, errors::BloggerError, lexer::error::LexerError};
+use crate::{
+    diag::{Diagnostic, Span},
+    errors::BloggerError,
+    lexer::error::LexerError,
+};
 
 /// ParserError now owns its source code and can render a snippet.
 #[derive(Debug)]
@@ -73,8 +77,36 @@ impl From<&LexerError> for ParserError {
     }
 }
 
+impl From<&ParserError> for Diagnostic {
+    fn from(value: &ParserError) -> Self {
+        Diagnostic::error(value.msg.clone(), value.span)
+    }
+}
+
 impl From<ParserError> for BloggerError {
     fn from(err: ParserError) -> Self {
         BloggerError::ParseError(err.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        diag::Diagnostic,
+        lexer::{lexer::Lexer, tokens::token_specs},
+        parser::parser::Parser,
+    };
+
+    #[test]
+    fn test_parser_error_json_diagnostic() {
+        let src = "article { s }\nsection { }".to_string();
+        let lexer = Lexer::new(&src, token_specs());
+        let err = Parser::new(lexer, &src, Path::new("")).parse().unwrap_err();
+        assert_eq!(
+            Diagnostic::from(&err).to_json(),
+            r#"{"message":"Expected identifier, found opening brace","line":1,"column":8,"offset":22,"severity":"error"}"#
+        );
+    }
+}
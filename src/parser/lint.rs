@@ -0,0 +1,275 @@
+use std::fmt;
+
+use super::parser::{Program, Statement};
+use crate::diag::{escape_json, Span};
+
+// Warning is a non-fatal problem found in an otherwise valid program. code
+// names the lint that raised it, e.g. "empty-section", so tooling can filter
+// on it.
+#[derive(Debug, Clone)]
+pub struct Warning {
+    pub code: &'static str,
+    pub message: String,
+    pub span: Option<Span>,
+}
+
+impl Warning {
+    pub fn new<M: Into<String>>(code: &'static str, message: M, span: Option<Span>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            span,
+        }
+    }
+
+    // to_json renders the warning as a single line JSON object, the
+    // position is left out when it has no span. As in Diagnostic::to_json
+    // the line and column count from 0.
+    pub fn to_json(&self) -> String {
+        let position = match self.span {
+            Some(span) => format!(
+                r#","line":{},"column":{},"offset":{}"#,
+                span.start().line(),
+                span.start().column(),
+                span.start().offset()
+            ),
+            None => String::new(),
+        };
+        format!(
+            r#"{{"code":"{}","message":"{}"{},"severity":"warning"}}"#,
+            self.code,
+            escape_json(&self.message),
+            position
+        )
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Warning[{}]: {}", self.code, self.message)?;
+        match self.span {
+            Some(span) => write!(
+                f,
+                " at line {}, column {}",
+                span.start().line_number(),
+                span.start().column_number()
+            ),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Program {
+    // lint runs every lint pass, returning their warnings in pass order
+    pub fn lint(&self) -> Vec<Warning> {
+        let mut warnings = self.lint_article();
+        warnings.extend(self.lint_unused());
+        warnings.extend(self.lint_headings());
+        warnings.extend(self.lint_empty());
+        warnings
+    }
+
+    // lint_unused warns about sections the article never calls, which are
+    // parsed but never rendered
+    pub fn lint_unused(&self) -> Vec<Warning> {
+        self.unused_sections()
+            .into_iter()
+            .map(|name| {
+                Warning::new(
+                    "unused-section",
+                    format!("Section {} is never called by the article", name),
+                    Some(self.sections()[name].span),
+                )
+            })
+            .collect()
+    }
+
+    // lint_headings warns when a heading skips a level relative to the
+    // heading before it in the same section, e.g. an h1 followed by an h3
+    pub fn lint_headings(&self) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+        for (name, section) in self.sections().iter() {
+            let mut previous: Option<usize> = None;
+            let headings = section
+                .paragraphs
+                .iter()
+                .flat_map(|paragraph| paragraph.statements_deep())
+                .filter_map(|(statement, span)| match statement {
+                    Statement::Heading(tag, _, _) => heading_level(tag).map(|l| (tag, l, span)),
+                    _ => None,
+                });
+            for (tag, level, span) in headings {
+                if let Some(prev) = previous {
+                    if level > prev + 1 {
+                        warnings.push(Warning::new(
+                            "heading-skip",
+                            format!(
+                                "{} follows h{} in section {}, skipping a heading level",
+                                tag, prev, name
+                            ),
+                            Some(span),
+                        ));
+                    }
+                }
+                previous = Some(level);
+            }
+        }
+        warnings
+    }
+
+    // lint_article warns when the article calls no sections, since it then
+    // renders as nothing but its title
+    pub fn lint_article(&self) -> Vec<Warning> {
+        if !self.article().section_calls.is_empty() {
+            return Vec::new();
+        }
+        let message = match self.article().name.as_str() {
+            "" => "Article calls no sections, so it renders empty".to_string(),
+            name => format!("Article {} calls no sections, so it renders empty", name),
+        };
+        vec![Warning::new(
+            "empty-article",
+            message,
+            Some(self.article().span),
+        )]
+    }
+
+    // lint_empty warns about sections without paragraphs and paragraphs
+    // without statements, which render as empty elements
+    pub fn lint_empty(&self) -> Vec<Warning> {
+        let mut warnings = Vec::new();
+        for (name, section) in self.sections().iter() {
+            if section.paragraphs.is_empty() {
+                warnings.push(Warning::new(
+                    "empty-section",
+                    format!("Empty section {}", name),
+                    Some(section.span),
+                ));
+            }
+            for paragraph in &section.paragraphs {
+                if paragraph.statements.is_empty() {
+                    warnings.push(Warning::new(
+                        "empty-paragraph",
+                        format!("Empty paragraph in section {}", name),
+                        Some(paragraph.span),
+                    ));
+                }
+            }
+        }
+        warnings
+    }
+}
+
+// heading_level extracts N from a heading tag "hN"
+fn heading_level(tag: &str) -> Option<usize> {
+    tag.strip_prefix('h')?
+        .parse()
+        .ok()
+        .filter(|level| (1..=6).contains(level))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Warning;
+    use crate::{
+        lexer::{lexer::Lexer, tokens::token_specs},
+        parser::parser::{Parser, Program},
+    };
+
+    fn parse(src: &str) -> Program {
+        let src = src.to_string();
+        let lexer = Lexer::new(&src, token_specs());
+        Parser::new(lexer, &src, Path::new("")).parse().unwrap()
+    }
+
+    #[test]
+    fn test_skipped_heading_level_warns() {
+        let program =
+            parse("article { a } section a { paragraph { h1 { One } `x` h3 { Three } } }");
+        let warnings = program.lint_headings();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].message,
+            "h3 follows h1 in section a, skipping a heading level"
+        );
+    }
+
+    #[test]
+    fn test_sequential_headings_do_not_warn() {
+        let program = parse(
+            "article { a } section a { paragraph { h1 { One } h2 { Two } h1 { Again } } } section b { paragraph { h3 { Fresh } } }",
+        );
+        assert!(program.lint_headings().is_empty());
+    }
+
+    #[test]
+    fn test_article_without_sections_warns() {
+        let warnings = parse("article foo{}").lint_article();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].message,
+            "Article foo calls no sections, so it renders empty"
+        );
+        let span = warnings[0].span.unwrap();
+        assert_eq!((span.start().offset(), span.end().offset()), (0, 13));
+
+        assert!(parse("article foo { a } section a { }")
+            .lint_article()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_empty_paragraph_warns() {
+        let program = parse("article { a } section a { paragraph { x } paragraph { } }");
+        let warnings = program.lint_empty();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message, "Empty paragraph in section a");
+        assert_eq!(warnings[0].span.unwrap().start().column(), 42);
+    }
+
+    #[test]
+    fn test_empty_section_warns() {
+        let program = parse("article { a b } section a { paragraph { x } }\nsection b { }");
+        let warnings = program.lint_empty();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].message, "Empty section b");
+        let span = warnings[0].span.unwrap();
+        assert_eq!(span.start().line(), 1);
+        assert_eq!(span.end().column(), 13);
+    }
+
+    #[test]
+    fn test_lint_collects_every_pass() {
+        let program = parse(
+            "article { a } section a { paragraph { h1 { One } h3 { Three } } paragraph { } } section b { paragraph { x } }",
+        );
+        let codes: Vec<&str> = program.lint().iter().map(|w| w.code).collect();
+        assert_eq!(
+            codes,
+            vec!["unused-section", "heading-skip", "empty-paragraph"]
+        );
+
+        let warnings = parse("article a { }").lint();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].to_string(),
+            "Warning[empty-article]: Article a calls no sections, so it renders empty at line 1, column 1"
+        );
+    }
+
+    #[test]
+    fn test_warning_json() {
+        let warning = Warning::new("unused-section", "Section \"b\" is unused", None);
+        assert_eq!(
+            warning.to_json(),
+            r#"{"code":"unused-section","message":"Section \"b\" is unused","severity":"warning"}"#
+        );
+        let warnings = parse("article { a } section a { }").lint();
+        assert_eq!(
+            warnings[0].to_json(),
+            r#"{"code":"empty-section","message":"Empty section a","line":0,"column":14,"offset":14,"severity":"warning"}"#
+        );
+    }
+}
@@ -1,2 +1,5 @@
 pub mod error;
+pub mod lint;
+#[allow(clippy::module_inception)]
 pub mod parser;
+pub mod stats;
@@ -1,56 +1,390 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 use super::error::ParserError;
-use crate::diag::Span;
+use crate::diag::{Span, Spanned};
+use crate::fs;
+use crate::lexer::error::LexerError;
 use crate::lexer::lexer::Lexer;
-use crate::lexer::tokens::{Token, TokenKind};
+use crate::lexer::tokens::{token_specs, Token, TokenKind};
+use crate::util::slugify;
 
-// Program is represented as a tree
-#[derive(Debug)]
+// Program is represented as a tree. Its fields are only reachable through
+// accessors, so a section's name always matches the key it is stored under
+// and the footnotes always match the definitions in the sections.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Program {
-    pub article: ArticleDeclaration,
-    pub sections: HashMap<String, SectionDeclaration>,
+    article: Spanned<ArticleDeclaration>,
+    sections: SectionMap,
+    // footnote definitions by id, collected from every section after parsing
+    #[serde(serialize_with = "serialize_sorted")]
+    footnotes: HashMap<String, String>,
+    // optional statements rendered before and after all sections
+    preamble: Option<Spanned<Paragraph>>,
+    footer: Option<Spanned<Paragraph>>,
 }
 
 impl Program {
     // iter_ast returns an iterator that traverses in the order of program declaration
     // i.e: starts at the article, then each section entirely, in the order it is called
     // in the article
-    pub fn iter_ast(&self) -> ASTIterator {
+    pub fn iter_ast(&self) -> ASTIterator<'_> {
         ASTIterator::new(self)
     }
+
+    // sections_in_order yields the sections in the order the article calls
+    // them, skipping calls to sections that don't exist
+    pub fn sections_in_order(&self) -> impl Iterator<Item = &SectionDeclaration> {
+        self.article
+            .section_calls
+            .iter()
+            .filter_map(|name| self.find_section(name))
+    }
+
+    pub fn article(&self) -> &Spanned<ArticleDeclaration> {
+        &self.article
+    }
+
+    pub fn sections(&self) -> &SectionMap {
+        &self.sections
+    }
+
+    pub fn section(&self, name: &str) -> Option<&Spanned<SectionDeclaration>> {
+        self.sections.get(name)
+    }
+
+    pub fn find_section(&self, name: &str) -> Option<&SectionDeclaration> {
+        self.sections.get(name).map(|section| &section.node)
+    }
+
+    pub fn footnotes(&self) -> &HashMap<String, String> {
+        &self.footnotes
+    }
+
+    pub fn footnote(&self, id: &str) -> Option<&str> {
+        self.footnotes.get(id).map(String::as_str)
+    }
+
+    pub fn preamble(&self) -> Option<&Spanned<Paragraph>> {
+        self.preamble.as_ref()
+    }
+
+    pub fn footer(&self) -> Option<&Spanned<Paragraph>> {
+        self.footer.as_ref()
+    }
+
+    // add_section adds a section under its own name. A name that is already
+    // taken, or a footnote the program already defines, is an error and
+    // leaves the program unchanged. source is the text the section was
+    // parsed from, which errors quote.
+    pub fn add_section(
+        &mut self,
+        section: Spanned<SectionDeclaration>,
+        source: &str,
+    ) -> Result<(), ParserError> {
+        if self.sections.contains_key(&section.name) {
+            return Err(ParserError::new_with_source(
+                format!("Duplicate section: {}", section.name),
+                section.span,
+                source,
+            ));
+        }
+        let mut footnotes = HashMap::new();
+        for paragraph in &section.paragraphs {
+            for (statement, span) in paragraph.statements_deep() {
+                if let Statement::FootnoteDef(id, text) = statement {
+                    if self.footnotes.contains_key(id)
+                        || footnotes.insert(id.clone(), text.clone()).is_some()
+                    {
+                        return Err(ParserError::new_with_source(
+                            format!("Duplicate footnote: {}", id),
+                            span,
+                            source,
+                        ));
+                    }
+                }
+            }
+        }
+        self.footnotes.extend(footnotes);
+        self.sections.insert(section);
+        Ok(())
+    }
+
+    // paragraphs_mut yields every paragraph for editing: those of each
+    // section in declaration order, then the preamble and footer. Sections
+    // themselves can't be reached mutably, so their names stay in step with
+    // the map. Crate only, since an edit could break the footnote and anchor
+    // invariants validate checks.
+    pub(crate) fn paragraphs_mut(&mut self) -> impl Iterator<Item = &mut Spanned<Paragraph>> {
+        self.sections
+            .entries
+            .iter_mut()
+            .flat_map(|(_, section)| section.paragraphs.iter_mut())
+            .chain(self.preamble.iter_mut())
+            .chain(self.footer.iter_mut())
+    }
+
+    // call_count is how many times the article calls the named section. A
+    // section called more than once is rendered once per call.
+    pub fn call_count(&self, name: &str) -> usize {
+        self.article
+            .section_calls
+            .iter()
+            .filter(|call| *call == name)
+            .count()
+    }
+
+    // footnote_refs lists each referenced footnote id once, in the order the
+    // references appear in the rendered article
+    pub fn footnote_refs(&self) -> Vec<&str> {
+        let mut refs: Vec<&str> = Vec::new();
+        for node in self.iter_ast() {
+            if let AstNode::Paragraph(paragraph) = node {
+                for (statement, _) in paragraph.statements_deep() {
+                    if let Statement::FootnoteRef(id) = statement {
+                        if !refs.contains(&id.as_str()) {
+                            refs.push(id);
+                        }
+                    }
+                }
+            }
+        }
+        refs
+    }
+
+    // validate checks the references between declarations. It runs once
+    // the whole file is parsed, so anything can be used before the point it
+    // is declared. Section calls are resolved when the AST is walked, calls
+    // to missing sections are skipped there. Anchors may not reuse the id
+    // of a heading or another anchor.
+    pub fn validate(&self, source: &str) -> Result<(), ParserError> {
+        let statements = || {
+            self.sections
+                .iter()
+                .flat_map(|(_, section)| section.paragraphs.iter())
+                .flat_map(|paragraph| paragraph.statements_deep())
+        };
+        // heading ids an anchor could collide with, explicit or slugged
+        let heading_ids: HashSet<String> = statements()
+            .filter_map(|(statement, _)| match statement {
+                Statement::Heading(_, id, text) => {
+                    Some(id.clone().unwrap_or_else(|| slugify(text)))
+                }
+                _ => None,
+            })
+            .collect();
+        let mut anchor_ids = HashSet::new();
+        for (statement, span) in statements() {
+            match statement {
+                Statement::FootnoteRef(id) if !self.footnotes.contains_key(id) => {
+                    return Err(ParserError::new_with_source(
+                        format!("Undefined footnote: {}", id),
+                        span,
+                        source,
+                    ));
+                }
+                Statement::Anchor(id) if heading_ids.contains(id) || !anchor_ids.insert(id) => {
+                    return Err(ParserError::new_with_source(
+                        format!("Duplicate anchor: {}", id),
+                        span,
+                        source,
+                    ));
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // unused_sections lists, in declaration order, the sections the article never calls
+    pub fn unused_sections(&self) -> Vec<&str> {
+        self.sections
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .filter(|name| !self.article.section_calls.iter().any(|c| c == name))
+            .collect()
+    }
+}
+
+// SectionMap is a name-indexed map of sections that remembers declaration
+// order, so anything iterating sections directly sees a reproducible order.
+#[derive(Debug, Clone, Default)]
+pub struct SectionMap {
+    entries: Vec<(String, Spanned<SectionDeclaration>)>,
+    index: HashMap<String, usize>,
+}
+
+impl SectionMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // insert adds a section keyed by its name, replacing (in place) any
+    // existing one with the same name
+    pub fn insert(&mut self, section: Spanned<SectionDeclaration>) {
+        let name = section.name.clone();
+        match self.index.get(&name) {
+            Some(&idx) => self.entries[idx].1 = section,
+            None => {
+                self.index.insert(name.clone(), self.entries.len());
+                self.entries.push((name, section));
+            }
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Spanned<SectionDeclaration>> {
+        self.index.get(name).map(|&idx| &self.entries[idx].1)
+    }
+
+    pub fn contains_key(&self, name: &str) -> bool {
+        self.index.contains_key(name)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    // iter yields sections in declaration order
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Spanned<SectionDeclaration>)> {
+        self.entries.iter().map(|(name, section)| (name, section))
+    }
+}
+
+// Sections are compared by name, so two maps holding the same sections are
+// equal whatever order they were declared in.
+impl PartialEq for SectionMap {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(name, section)| other.get(name) == Some(section))
+    }
+}
+
+// A SectionMap serializes as its entries in declaration order, the index is
+// rebuilt from them. An entry whose key isn't its section's name is rejected.
+impl Serialize for SectionMap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.entries.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for SectionMap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let entries = Vec::<(String, Spanned<SectionDeclaration>)>::deserialize(deserializer)?;
+        let mut sections = SectionMap::new();
+        for (name, section) in entries {
+            if name != section.name {
+                return Err(serde::de::Error::custom(format!(
+                    "section {} is stored under the name {}",
+                    section.name, name
+                )));
+            }
+            sections.insert(section);
+        }
+        Ok(sections)
+    }
+}
+
+// serialize_sorted writes a map in key order, so serialized output is the
+// same from one run to the next
+fn serialize_sorted<S: Serializer>(
+    map: &HashMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    map.iter().collect::<BTreeMap<_, _>>().serialize(serializer)
+}
+
+impl std::ops::Index<&str> for SectionMap {
+    type Output = Spanned<SectionDeclaration>;
+
+    fn index(&self, name: &str) -> &Self::Output {
+        self.get(name).expect("no section with that name")
+    }
 }
 
-#[derive(Debug, Clone)]
+// Each node carries its span through Spanned: a declaration's runs from its
+// keyword to its closing brace, as does a statement's. Spans are ignored by
+// equality, so the same content written with different layout compares equal.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ArticleDeclaration {
     pub name: String,
+    // key/value pairs from `article name[key=value, ...]`, such as the date
+    // and author a feed is written with
+    #[serde(serialize_with = "serialize_sorted")]
+    pub metadata: HashMap<String, String>,
     pub section_calls: Vec<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct SectionDeclaration {
     pub name: String,
-    pub paragraphs: Vec<Paragraph>,
+    // key/value pairs from `section name[key=value, ...]`, not rendered but
+    // available to feeds and tooling
+    #[serde(serialize_with = "serialize_sorted")]
+    pub metadata: HashMap<String, String>,
+    pub paragraphs: Vec<Spanned<Paragraph>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Paragraph {
-    pub statements: Vec<Statement>,
+    pub statements: Vec<Spanned<Statement>>,
 }
 
-#[derive(Debug, Clone)]
+impl Paragraph {
+    // statements_deep yields every statement with its span, descending into
+    // the paragraphs nested in columns
+    pub fn statements_deep(&self) -> Vec<(&Statement, Span)> {
+        let mut out = Vec::new();
+        for statement in &self.statements {
+            out.push((&statement.node, statement.span));
+            if let Statement::Columns(columns) = &statement.node {
+                out.extend(columns.iter().flat_map(|column| column.statements_deep()));
+            }
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Statement {
-    Heading(String, String),
+    // tag, explicit anchor id and text
+    Heading(String, Option<String>, String),
     TextBlock(String),
     CodeBlock(String),
+    // RawHtml is emitted verbatim by the generator without any escaping,
+    // so whatever markup the author writes (scripts included) ends up in
+    // the page. Only use it with trusted sources.
+    RawHtml(String),
+    // Math holds TeX source, typeset client side (e.g. by KaTeX).
+    Math(String),
     Aside(String),
     List(List),
+    // FootnoteRef marks a reference to the footnote with the given id
+    FootnoteRef(String),
+    // FootnoteDef defines a footnote's text. It renders nothing in place;
+    // definitions are gathered into Program::footnotes after parsing.
+    FootnoteDef(String, String),
+    // Embed holds the URL of media, such as a video, to show in place
+    Embed(String),
+    // Columns lays its paragraphs out side by side
+    Columns(Vec<Spanned<Paragraph>>),
+    // Anchor marks a link target mid-paragraph, holding its (slug) id
+    Anchor(String),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum List {
     Ordered(Vec<String>),
     Unordered(Vec<String>),
+    // a task list, each item paired with whether it is done
+    Checklist(Vec<(bool, String)>),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -68,15 +402,17 @@ impl<'a> AstNode<'a> {
             AstNode::Article(article) => article
                 .section_calls
                 .iter()
-                .filter_map(|name| program.sections.get(name).map(AstNode::Section))
+                .filter_map(|name| program.find_section(name).map(AstNode::Section))
+                .collect(),
+            AstNode::Section(section) => section
+                .paragraphs
+                .iter()
+                .map(|paragraph| AstNode::Paragraph(paragraph))
                 .collect(),
-            AstNode::Section(section) => {
-                section.paragraphs.iter().map(AstNode::Paragraph).collect()
-            }
             AstNode::Paragraph(paragraph) => paragraph
                 .statements
                 .iter()
-                .map(AstNode::Statement)
+                .map(|statement| AstNode::Statement(statement))
                 .collect(),
             AstNode::Statement(stmt) => match stmt {
                 Statement::List(list) => vec![AstNode::List(list)],
@@ -104,38 +440,248 @@ impl<'a> ASTIterator<'a> {
 impl<'a> Iterator for ASTIterator<'a> {
     type Item = AstNode<'a>;
     fn next(&mut self) -> Option<Self::Item> {
-        self.stack.pop().map(|node| {
+        self.stack.pop().inspect(|node| {
             node.children(self.program)
                 .into_iter()
                 .rev()
                 .for_each(|child| self.stack.push(child));
-            node
         })
     }
 }
 
+// `for node in &program` walks the AST the same way as iter_ast
+impl<'a> IntoIterator for &'a Program {
+    type Item = AstNode<'a>;
+    type IntoIter = ASTIterator<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_ast()
+    }
+}
+
+// how many errors parse_recover collects unless told otherwise
+pub const DEFAULT_MAX_ERRORS: usize = 20;
+
+// Declarations are the top level items of one file, before they're checked
+// and assembled into a Program
+#[derive(Default)]
+struct Declarations {
+    article: Option<Spanned<ArticleDeclaration>>,
+    sections: SectionMap,
+    preamble: Option<Spanned<Paragraph>>,
+    footer: Option<Spanned<Paragraph>>,
+}
+
+// TokenStream is where the parser pulls tokens from, normally the lexer
+// itself so lexing happens as the parse goes
+enum TokenStream<'a> {
+    Lexing(Lexer<'a>),
+    Lexed(std::vec::IntoIter<Result<Token, LexerError>>),
+}
+
+impl Iterator for TokenStream<'_> {
+    type Item = Result<Token, LexerError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            TokenStream::Lexing(lexer) => lexer.next(),
+            TokenStream::Lexed(tokens) => tokens.next(),
+        }
+    }
+}
+
 /// Parser consumes tokens produced by the Lexer (each Token holds a TokenKind and its Span)
 /// and holds a reference to the full source for error rendering.
 pub struct Parser<'a> {
-    tokens: std::iter::Peekable<Lexer<'a>>,
-    source: &'a String,
+    tokens: std::iter::Peekable<TokenStream<'a>>,
+    source: &'a str,
+    // span of the most recently consumed token
+    prev_span: Span,
+    // directory that import paths are resolved against
+    base_dir: &'a Path,
+    // files currently being imported, used to reject import cycles
+    importing: Vec<PathBuf>,
+    // how many tokens have been consumed, so error recovery can tell
+    // whether a failed parse made progress
+    consumed: usize,
+    // where the input runs out, for end of input errors
+    eof: Span,
+    // spans of the opening braces not yet closed, innermost last
+    open_braces: Vec<Span>,
+    // whether the lexer marks blank lines, letting them split paragraphs
+    paragraph_breaks: bool,
+    // brace depth of the section body being parsed, the only place a blank
+    // line is significant
+    section_depth: Option<usize>,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(lexer: Lexer<'a>, source: &'a String) -> Self {
+    pub fn new(lexer: Lexer<'a>, source: &'a str, base_dir: &'a Path) -> Self {
+        let end = lexer.end_of_input();
+        let paragraph_breaks = lexer.blank_lines();
         Self {
-            tokens: lexer.peekable(),
+            eof: Span::new(end, end),
+            tokens: TokenStream::Lexing(lexer).peekable(),
             source,
+            prev_span: Span::default(),
+            base_dir,
+            importing: Vec::new(),
+            consumed: 0,
+            open_braces: Vec::new(),
+            paragraph_breaks,
+            section_depth: None,
         }
     }
 
+    // lex_ahead drains the lexer before parsing starts rather than as the
+    // parse goes, so the two phases can be timed apart. The parse is the
+    // same either way.
+    pub fn lex_ahead(&mut self) {
+        let tokens: Vec<_> = self.tokens.by_ref().collect();
+        self.tokens = TokenStream::Lexed(tokens.into_iter()).peekable();
+    }
+
     pub fn parse(&mut self) -> Result<Program, ParserError> {
-        let mut article_opt: Option<ArticleDeclaration> = None;
-        let mut sections = HashMap::new();
+        let declarations = self.parse_declarations()?;
+        self.assemble(declarations)
+    }
 
-        while let Some(token) = self.peek_token()? {
+    // parse_recover parses like parse, but carries on after an error by
+    // skipping to the next top level declaration, so one run reports every
+    // broken declaration rather than only the first. Collection stops at
+    // max_errors, with a final note when declarations were left unparsed. The
+    // program is only returned when there were no errors.
+    pub fn parse_recover(&mut self, max_errors: usize) -> (Option<Program>, Vec<ParserError>) {
+        let mut declarations = Declarations::default();
+        let mut errors = Vec::new();
+        loop {
+            let consumed = self.consumed;
+            let result = match self.peek_token() {
+                Ok(None) => break,
+                Ok(Some(_)) => self.parse_declaration(&mut declarations),
+                Err(err) => {
+                    // step over the token the lexer failed on
+                    self.tokens.next();
+                    Err(err)
+                }
+            };
+            let Err(err) = result else {
+                continue;
+            };
+            errors.push(err);
+            self.synchronize(consumed);
+            if errors.len() >= max_errors {
+                // only worth a note when there are declarations left
+                if let Some(next) = self.tokens.peek() {
+                    let span = next.as_ref().map_or(self.prev_span, |token| token.span);
+                    errors.push(ParserError::new_with_source(
+                        format!("Too many errors, stopped after {}", max_errors),
+                        span,
+                        self.source,
+                    ));
+                }
+                return (None, errors);
+            }
+        }
+        if !errors.is_empty() {
+            return (None, errors);
+        }
+        match self.assemble(declarations) {
+            Ok(program) => (Some(program), errors),
+            Err(err) => (None, vec![err]),
+        }
+    }
+
+    // synchronize skips to the next top level keyword after an error,
+    // stepping over at least one token so a declaration that failed on its
+    // first token isn't retried forever
+    fn synchronize(&mut self, consumed_before: usize) {
+        if self.consumed == consumed_before {
+            self.tokens.next();
+        }
+        while let Some(result) = self.tokens.peek() {
+            if let Ok(token) = result {
+                if matches!(
+                    token.kind,
+                    TokenKind::Article
+                        | TokenKind::Section
+                        | TokenKind::Preamble
+                        | TokenKind::Footer
+                        | TokenKind::Import
+                ) {
+                    break;
+                }
+            }
+            self.tokens.next();
+        }
+        self.open_braces.clear();
+        self.section_depth = None;
+    }
+
+    // assemble checks the declarations of a whole file and builds the
+    // Program from them
+    fn assemble(&self, declarations: Declarations) -> Result<Program, ParserError> {
+        let Declarations {
+            article: article_opt,
+            sections,
+            preamble,
+            footer,
+        } = declarations;
+        let article = article_opt.ok_or_else(|| {
+            ParserError::new_with_source(
+                "Missing article declaration",
+                Span::new(Default::default(), Default::default()),
+                self.source,
+            )
+        })?;
+        let footnotes = self.collect_footnotes(&sections)?;
+        let program = Program {
+            article,
+            sections,
+            footnotes,
+            preamble,
+            footer,
+        };
+        program.validate(self.source)?;
+        Ok(program)
+    }
+
+    // parses the top level article, section, preamble, footer and import
+    // declarations
+    fn parse_declarations(&mut self) -> Result<Declarations, ParserError> {
+        let mut declarations = Declarations::default();
+        while self.peek_token()?.is_some() {
+            self.parse_declaration(&mut declarations)?;
+        }
+        Ok(declarations)
+    }
+
+    // parses the declaration at the current token into declarations
+    fn parse_declaration(&mut self, declarations: &mut Declarations) -> Result<(), ParserError> {
+        let Declarations {
+            article: article_opt,
+            sections,
+            preamble,
+            footer,
+        } = declarations;
+        if let Some(token) = self.peek_token()? {
             let t = token.clone();
             match t.kind {
+                TokenKind::Preamble | TokenKind::Footer => {
+                    let (slot, keyword) = if t.kind == TokenKind::Preamble {
+                        (preamble, "preamble")
+                    } else {
+                        (footer, "footer")
+                    };
+                    if slot.is_some() {
+                        return Err(ParserError::new_with_source(
+                            format!("Multiple {} declarations found", keyword),
+                            t.span,
+                            self.source,
+                        ));
+                    }
+                    *slot = Some(self.parse_statement_block(t.kind.clone())?);
+                }
                 TokenKind::Article => {
                     if article_opt.is_some() {
                         return Err(ParserError::new_with_source(
@@ -144,7 +690,7 @@ impl<'a> Parser<'a> {
                             self.source,
                         ));
                     }
-                    article_opt = Some(self.parse_article_declaration()?);
+                    *article_opt = Some(self.parse_article_declaration()?);
                 }
                 TokenKind::Section => {
                     let sec = self.parse_section_declaration()?;
@@ -155,59 +701,262 @@ impl<'a> Parser<'a> {
                             self.source,
                         ));
                     }
-                    sections.insert(sec.name.clone(), sec);
+                    sections.insert(sec);
+                }
+                TokenKind::Import => {
+                    let (path, imported) = self.parse_import()?;
+                    for (name, sec) in imported.entries {
+                        if sections.contains_key(&name) {
+                            return Err(ParserError::new_with_source(
+                                format!("Duplicate section: {} (imported from {})", name, path),
+                                t.span,
+                                self.source,
+                            ));
+                        }
+                        sections.insert(sec);
+                    }
                 }
                 _ => {
                     return Err(ParserError::new_with_source(
-                        format!("Unexpected token at program level: {:?}", token),
+                        format!("Unexpected {} at program level", t.kind),
                         t.span,
                         self.source,
                     ))
                 }
             }
         }
+        Ok(())
+    }
 
-        let article = article_opt.ok_or_else(|| {
+    // parse_import handles `import "path.blog"`. The path is resolved
+    // against the base directory and the file is parsed on its own, returning
+    // its sections for the caller to merge.
+    fn parse_import(&mut self) -> Result<(String, SectionMap), ParserError> {
+        self.expect_token(TokenKind::Import)?;
+        let token = self.next_token()?;
+        let TokenKind::Str(relative) = token.kind else {
+            return Err(ParserError::new_with_source(
+                format!("Expected import path, found {}", token.kind),
+                token.span,
+                self.source,
+            ));
+        };
+        let path = self.base_dir.join(&relative);
+        if self.importing.contains(&path) {
+            return Err(ParserError::new_with_source(
+                format!("Import cycle through {}", relative),
+                token.span,
+                self.source,
+            ));
+        }
+        let src = fs::read_file_to_string(&path).map_err(|e| {
             ParserError::new_with_source(
-                "Missing article declaration",
-                Span::new(Default::default(), Default::default()),
+                format!("Cannot import {}: {}", relative, e),
+                token.span,
                 self.source,
             )
         })?;
-        Ok(Program { article, sections })
+
+        let base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let lexer = Lexer::new(&src, token_specs()).with_blank_lines(self.paragraph_breaks);
+        let mut parser = Parser::new(lexer, &src, &base_dir);
+        parser.importing = self.importing.clone();
+        parser.importing.push(path.clone());
+        let imported = parser.parse_declarations()?;
+        let declared = [
+            ("an article", imported.article.is_some()),
+            ("a preamble", imported.preamble.is_some()),
+            ("a footer", imported.footer.is_some()),
+        ];
+        if let Some((what, _)) = declared.iter().find(|(_, present)| *present) {
+            return Err(ParserError::new_with_source(
+                format!("Imported file {} must not declare {}", relative, what),
+                token.span,
+                self.source,
+            ));
+        }
+        Ok((relative, imported.sections))
     }
 
-    fn parse_article_declaration(&mut self) -> Result<ArticleDeclaration, ParserError> {
+    // gathers every footnote definition, checking ids are unique
+    fn collect_footnotes(
+        &self,
+        sections: &SectionMap,
+    ) -> Result<HashMap<String, String>, ParserError> {
+        let statements = || {
+            sections.iter().flat_map(|(_, section)| {
+                section
+                    .paragraphs
+                    .iter()
+                    .flat_map(|paragraph| paragraph.statements_deep())
+            })
+        };
+
+        let mut footnotes = HashMap::new();
+        for (statement, span) in statements() {
+            if let Statement::FootnoteDef(id, text) = statement {
+                if footnotes.insert(id.clone(), text.clone()).is_some() {
+                    return Err(ParserError::new_with_source(
+                        format!("Duplicate footnote: {}", id),
+                        span,
+                        self.source,
+                    ));
+                }
+            }
+        }
+        Ok(footnotes)
+    }
+
+    fn parse_article_declaration(&mut self) -> Result<Spanned<ArticleDeclaration>, ParserError> {
         self.expect_token(TokenKind::Article)?;
+        let start = self.prev_span;
         // Allow an optional article name.
         let name = match self.peek_token()? {
-            Some(token) if token.kind == TokenKind::LBrace => String::new(),
+            Some(token) if matches!(token.kind, TokenKind::LBrace | TokenKind::LBracket) => {
+                String::new()
+            }
             _ => self.expect_ident()?,
         };
+        let metadata = match self.peek_token()? {
+            Some(token) if token.kind == TokenKind::LBracket => self.parse_metadata()?,
+            _ => HashMap::new(),
+        };
         self.expect_token(TokenKind::LBrace)?;
         let section_calls = self.parse_until(TokenKind::RBrace, Self::expect_ident_dynamic)?;
         self.expect_token(TokenKind::RBrace)?;
-        Ok(ArticleDeclaration {
+        let article = ArticleDeclaration {
             name,
+            metadata,
             section_calls,
-        })
+        };
+        Ok(Spanned::new(article, start.merge(&self.prev_span)))
     }
 
-    fn parse_section_declaration(&mut self) -> Result<SectionDeclaration, ParserError> {
+    fn parse_section_declaration(&mut self) -> Result<Spanned<SectionDeclaration>, ParserError> {
         self.expect_token(TokenKind::Section)?;
+        let start = self.prev_span;
         let name = self.expect_ident()?;
+        let metadata = match self.peek_token()? {
+            Some(token) if token.kind == TokenKind::LBracket => self.parse_metadata()?,
+            _ => HashMap::new(),
+        };
         self.expect_token(TokenKind::LBrace)?;
-        let paragraphs = self.parse_until(TokenKind::RBrace, Self::parse_paragraph)?;
+        let paragraphs = if self.paragraph_breaks {
+            self.section_depth = Some(self.open_braces.len());
+            let paragraphs = self.parse_section_body();
+            self.section_depth = None;
+            paragraphs?
+        } else {
+            self.parse_until(TokenKind::RBrace, Self::parse_paragraph)?
+        };
         self.expect_token(TokenKind::RBrace)?;
-        Ok(SectionDeclaration { name, paragraphs })
+        let section = SectionDeclaration {
+            name,
+            metadata,
+            paragraphs,
+        };
+        Ok(Spanned::new(section, start.merge(&self.prev_span)))
+    }
+
+    // parses `[key=value, ...]`. Keys are identifiers, values identifiers or
+    // strings, and each key may appear once.
+    fn parse_metadata(&mut self) -> Result<HashMap<String, String>, ParserError> {
+        self.expect_token(TokenKind::LBracket)?;
+        let mut metadata = HashMap::new();
+        while let Some(token) = self.peek_token()? {
+            if token.kind == TokenKind::RBracket {
+                break;
+            }
+            let key = self.expect_ident()?;
+            let key_span = self.prev_span;
+            self.expect_token(TokenKind::Equals)?;
+            let token = self.next_token()?;
+            let value = match token.kind {
+                TokenKind::Ident(value) | TokenKind::Str(value) => value,
+                other => {
+                    return Err(ParserError::new_with_source(
+                        format!("Expected a value for {}, found {}", key, other),
+                        token.span,
+                        self.source,
+                    ))
+                }
+            };
+            if metadata.insert(key.clone(), value).is_some() {
+                return Err(ParserError::new_with_source(
+                    format!("Duplicate metadata key: {}", key),
+                    key_span,
+                    self.source,
+                ));
+            }
+            match self.peek_token()? {
+                Some(token) if token.kind == TokenKind::Comma => {
+                    self.expect_token(TokenKind::Comma)?
+                }
+                _ => break,
+            }
+        }
+        self.expect_token(TokenKind::RBracket)?;
+        Ok(metadata)
     }
 
-    fn parse_paragraph(&mut self) -> Result<Paragraph, ParserError> {
-        self.expect_token(TokenKind::Paragraph)?;
+    // parse_section_body parses a section's paragraphs when blank lines split
+    // them. Statements outside a `paragraph` block run up to the next blank
+    // line, paragraph or the end of the section and form a paragraph of their
+    // own, so explicit and implicit paragraphs can be mixed.
+    fn parse_section_body(&mut self) -> Result<Vec<Spanned<Paragraph>>, ParserError> {
+        let mut paragraphs = Vec::new();
+        while let Some(token) = self.peek_token()? {
+            match token.kind {
+                TokenKind::RBrace => break,
+                TokenKind::BlankLine => {
+                    self.next_token()?;
+                }
+                TokenKind::Paragraph => paragraphs.push(self.parse_paragraph()?),
+                _ => {
+                    let start = token.span;
+                    let mut statements = Vec::new();
+                    while let Some(token) = self.peek_token()? {
+                        if matches!(
+                            token.kind,
+                            TokenKind::RBrace | TokenKind::BlankLine | TokenKind::Paragraph
+                        ) {
+                            break;
+                        }
+                        statements.push(self.parse_spanned_statement()?);
+                    }
+                    let paragraph = Paragraph { statements };
+                    paragraphs.push(Spanned::new(paragraph, start.merge(&self.prev_span)));
+                }
+            }
+        }
+        Ok(paragraphs)
+    }
+
+    fn parse_paragraph(&mut self) -> Result<Spanned<Paragraph>, ParserError> {
+        self.parse_statement_block(TokenKind::Paragraph)
+    }
+
+    // parses `keyword { statements }`, the shape shared by paragraphs, the
+    // preamble and the footer
+    fn parse_statement_block(
+        &mut self,
+        keyword: TokenKind,
+    ) -> Result<Spanned<Paragraph>, ParserError> {
+        self.expect_token(keyword)?;
+        let start = self.prev_span;
         self.expect_token(TokenKind::LBrace)?;
-        let statements = self.parse_until(TokenKind::RBrace, Self::parse_statement)?;
+        let statements = self.parse_until(TokenKind::RBrace, Self::parse_spanned_statement)?;
         self.expect_token(TokenKind::RBrace)?;
-        Ok(Paragraph { statements })
+        Ok(Spanned::new(
+            Paragraph { statements },
+            start.merge(&self.prev_span),
+        ))
+    }
+
+    fn parse_spanned_statement(&mut self) -> Result<Spanned<Statement>, ParserError> {
+        let start = self.peek_token()?.map(|t| t.span).unwrap_or_default();
+        let statement = self.parse_statement()?;
+        Ok(Spanned::new(statement, start.merge(&self.prev_span)))
     }
 
     fn parse_statement(&mut self) -> Result<Statement, ParserError> {
@@ -223,10 +972,11 @@ impl<'a> Parser<'a> {
                 } else {
                     unreachable!()
                 };
+                let id = self.parse_heading_id()?;
                 self.expect_token(TokenKind::LBrace)?;
-                let content = self.parse_heading_content()?;
+                let content = self.parse_inline_text("heading")?;
                 self.expect_token(TokenKind::RBrace)?;
-                Ok(Statement::Heading(heading_type, content))
+                Ok(Statement::Heading(heading_type, id, content))
             }
             Some(token) if matches!(token.kind, TokenKind::TextBlock(_)) => {
                 let tb_token = self.next_token()?;
@@ -240,67 +990,202 @@ impl<'a> Parser<'a> {
                     unreachable!()
                 }
             }
+            Some(token) if matches!(token.kind, TokenKind::Ident(_)) => {
+                Ok(Statement::TextBlock(self.parse_bare_text()?))
+            }
             Some(token) if token.kind == TokenKind::Code => {
-                let code_token = self.next_token()?;
-                if code_token.kind != TokenKind::Code {
-                    unreachable!()
-                }
-                self.expect_token(TokenKind::LBrace)?;
-                let tb_token = self.next_token()?;
-                self.expect_token(TokenKind::RBrace)?;
-                match tb_token.kind {
-                    TokenKind::TextBlock(code_text) => Ok(Statement::CodeBlock(code_text)),
-                    _ => Err(ParserError::new_with_source(
-                        "Expected text block inside code block",
-                        tb_token.span,
-                        self.source,
-                    )),
-                }
+                self.expect_token(TokenKind::Code)?;
+                let code_text = self.parse_braced_text_block("code")?;
+                Ok(Statement::CodeBlock(code_text))
+            }
+            Some(token) if token.kind == TokenKind::Html => {
+                self.expect_token(TokenKind::Html)?;
+                let html = self.parse_braced_text_block("html")?;
+                Ok(Statement::RawHtml(html))
+            }
+            Some(token) if token.kind == TokenKind::Math => {
+                self.expect_token(TokenKind::Math)?;
+                let tex = self.parse_braced_text_block("math")?;
+                Ok(Statement::Math(tex))
             }
             Some(token) if token.kind == TokenKind::Aside => self.parse_aside(),
-            Some(token) if matches!(token.kind, TokenKind::OList | TokenKind::UList) => {
+            Some(token) if token.kind == TokenKind::Footnote => self.parse_footnote(),
+            Some(token) if token.kind == TokenKind::Embed => {
+                self.expect_token(TokenKind::Embed)?;
+                let url = self.parse_braced_text_block("embed")?;
+                Ok(Statement::Embed(url))
+            }
+            Some(token) if token.kind == TokenKind::Columns => self.parse_columns(),
+            Some(token) if token.kind == TokenKind::Anchor => self.parse_anchor(),
+            Some(token)
+                if matches!(
+                    token.kind,
+                    TokenKind::OList | TokenKind::UList | TokenKind::CList
+                ) =>
+            {
                 let list = self.parse_list()?;
                 Ok(Statement::List(list))
             }
-            Some(token) => Err(ParserError::new_with_source(
-                format!("Unexpected token in statement: {:?}", token),
+            Some(token) if token.kind == TokenKind::LItem => Err(ParserError::new_with_source(
+                "List items must appear inside an ol, ul or cl block",
                 token.span,
                 self.source,
             )),
-            None => Err(ParserError::new_with_source(
-                "Unexpected end of input while parsing statement",
-                Span::new(Default::default(), Default::default()),
+            Some(token) => Err(ParserError::new_with_source(
+                format!("Unexpected {} in statement", token.kind),
+                token.span,
                 self.source,
             )),
+            None => Err(self.end_of_input("Unexpected end of input while parsing statement")),
         }
     }
 
-    fn parse_heading_content(&mut self) -> Result<String, ParserError> {
-        let token = self.next_token()?;
-        match token.kind {
-            TokenKind::Ident(text) | TokenKind::TextBlock(text) => Ok(text),
-            other => Err(ParserError::new_with_source(
-                format!("Expected heading content, found {:?}", other),
-                token.span,
+    // parses the optional `(id)` pinning a heading's anchor, as in
+    // `h2(intro-section) { Title }`
+    fn parse_heading_id(&mut self) -> Result<Option<String>, ParserError> {
+        match self.peek_token()? {
+            Some(token) if token.kind == TokenKind::LParen => {
+                self.expect_token(TokenKind::LParen)?;
+                let id = self.expect_ident()?;
+                self.expect_token(TokenKind::RParen)?;
+                Ok(Some(id))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    // parses the text of an aside, heading or list item up to (but not
+    // including) the closing brace. Words and text blocks are joined with
+    // spaces, anything structural is rejected. `context` names the enclosing
+    // construct for error messages.
+    fn parse_inline_text(&mut self, context: &str) -> Result<String, ParserError> {
+        let mut parts = Vec::new();
+        while let Some(token) = self.peek_token()? {
+            if token.kind == TokenKind::RBrace {
+                break;
+            }
+            let token = self.next_token()?;
+            match token.kind {
+                TokenKind::Ident(text) | TokenKind::TextBlock(text) => parts.push(text),
+                other => {
+                    return Err(ParserError::new_with_source(
+                        format!("Only text is allowed in {}, found {}", context, other),
+                        token.span,
+                        self.source,
+                    ))
+                }
+            }
+        }
+        if parts.is_empty() {
+            let eof = self.eof;
+            let span = self.peek_token()?.map_or(eof, |t| t.span);
+            return Err(ParserError::new_with_source(
+                format!("Expected text in {}", context),
+                span,
+                self.source,
+            ));
+        }
+        Ok(parts.join(" "))
+    }
+
+    // bare words written directly in a paragraph are coalesced into a single
+    // text block, stopping at the first token that isn't an identifier. The
+    // words are joined by the source between them, so spacing is kept as
+    // written, unless a comment sits between them, which becomes a space.
+    fn parse_bare_text(&mut self) -> Result<String, ParserError> {
+        let mut text = String::new();
+        let mut prev_end: Option<usize> = None;
+        while let Some(Token {
+            kind: TokenKind::Ident(_),
+            ..
+        }) = self.peek_token()?
+        {
+            let word = self.expect_ident()?;
+            if let Some(prev_end) = prev_end {
+                let gap = &self.source[prev_end..self.prev_span.start().offset()];
+                text.push_str(if gap.trim().is_empty() { gap } else { " " });
+            }
+            text.push_str(&word);
+            prev_end = Some(self.prev_span.end().offset());
+        }
+        Ok(text)
+    }
+
+    // parses a `{ `...` }` group, returning the contents of the text block.
+    // `context` names the enclosing construct for error messages.
+    fn parse_braced_text_block(&mut self, context: &str) -> Result<String, ParserError> {
+        self.expect_token(TokenKind::LBrace)?;
+        let tb_token = self.next_token()?;
+        self.expect_token(TokenKind::RBrace)?;
+        match tb_token.kind {
+            TokenKind::TextBlock(text) => Ok(text),
+            _ => Err(ParserError::new_with_source(
+                format!("Expected text block inside {} block", context),
+                tb_token.span,
                 self.source,
             )),
         }
     }
 
+    fn parse_columns(&mut self) -> Result<Statement, ParserError> {
+        self.expect_token(TokenKind::Columns)?;
+        self.expect_token(TokenKind::LBrace)?;
+        let columns = self.parse_until(TokenKind::RBrace, Self::parse_paragraph)?;
+        self.expect_token(TokenKind::RBrace)?;
+        Ok(Statement::Columns(columns))
+    }
+
+    // `footnote{id}` references a footnote, `footnote{id}{text}` defines it
+    fn parse_footnote(&mut self) -> Result<Statement, ParserError> {
+        self.expect_token(TokenKind::Footnote)?;
+        self.expect_token(TokenKind::LBrace)?;
+        let id = self.expect_ident()?;
+        self.expect_token(TokenKind::RBrace)?;
+        match self.peek_token()? {
+            Some(token) if token.kind == TokenKind::LBrace => {
+                self.expect_token(TokenKind::LBrace)?;
+                let token = self.next_token()?;
+                let text = match token.kind {
+                    TokenKind::TextBlock(text) | TokenKind::Ident(text) => text,
+                    other => {
+                        return Err(ParserError::new_with_source(
+                            format!("Expected text in footnote, found {}", other),
+                            token.span,
+                            self.source,
+                        ))
+                    }
+                };
+                self.expect_token(TokenKind::RBrace)?;
+                Ok(Statement::FootnoteDef(id, text))
+            }
+            _ => Ok(Statement::FootnoteRef(id)),
+        }
+    }
+
+    // parses `anchor{id}`, where the id has to already be a slug so the
+    // target is exactly what links refer to
+    fn parse_anchor(&mut self) -> Result<Statement, ParserError> {
+        self.expect_token(TokenKind::Anchor)?;
+        self.expect_token(TokenKind::LBrace)?;
+        let id = self.expect_ident()?;
+        if slugify(&id) != id {
+            return Err(ParserError::new_with_source(
+                format!(
+                    "Invalid anchor id '{}', use lowercase letters, digits and hyphens",
+                    id
+                ),
+                self.prev_span,
+                self.source,
+            ));
+        }
+        self.expect_token(TokenKind::RBrace)?;
+        Ok(Statement::Anchor(id))
+    }
+
     fn parse_aside(&mut self) -> Result<Statement, ParserError> {
         self.expect_token(TokenKind::Aside)?;
         self.expect_token(TokenKind::LBrace)?;
-        let token = self.next_token()?;
-        let content = match token.kind {
-            TokenKind::TextBlock(text) | TokenKind::Ident(text) => text,
-            other => {
-                return Err(ParserError::new_with_source(
-                    format!("Expected TextBlock or Ident in aside, found {:?}", other),
-                    token.span,
-                    self.source,
-                ))
-            }
-        };
+        let content = self.parse_inline_text("aside")?;
         self.expect_token(TokenKind::RBrace)?;
         Ok(Statement::Aside(content))
     }
@@ -310,9 +1195,15 @@ impl<'a> Parser<'a> {
         let is_ordered = match list_token.kind {
             TokenKind::OList => true,
             TokenKind::UList => false,
+            TokenKind::CList => {
+                self.expect_token(TokenKind::LBrace)?;
+                let items = self.parse_until(TokenKind::RBrace, Self::parse_checklist_item)?;
+                self.expect_token(TokenKind::RBrace)?;
+                return Ok(List::Checklist(items));
+            }
             _ => {
                 return Err(ParserError::new_with_source(
-                    format!("Expected OList or UList, found {:?}", list_token),
+                    format!("Expected a list, found {}", list_token.kind),
                     list_token.span,
                     self.source,
                 ))
@@ -331,22 +1222,34 @@ impl<'a> Parser<'a> {
     fn parse_list_item(&mut self) -> Result<String, ParserError> {
         self.expect_token(TokenKind::LItem)?;
         self.expect_token(TokenKind::LBrace)?;
+        let item = self.parse_inline_text("list item")?;
+        self.expect_token(TokenKind::RBrace)?;
+        Ok(item)
+    }
+
+    // parses a checklist item, `li(x) { done }` or `li() { pending }`
+    fn parse_checklist_item(&mut self) -> Result<(bool, String), ParserError> {
+        self.expect_token(TokenKind::LItem)?;
+        self.expect_token(TokenKind::LParen)?;
         let token = self.next_token()?;
-        let item = match token.kind {
-            TokenKind::TextBlock(text) | TokenKind::Ident(text) => text,
+        let done = match token.kind {
+            TokenKind::RParen => false,
+            TokenKind::Ident(ref marker) if marker == "x" => {
+                self.expect_token(TokenKind::RParen)?;
+                true
+            }
             other => {
                 return Err(ParserError::new_with_source(
-                    format!(
-                        "Expected TextBlock or Ident in list item, found {:?}",
-                        other
-                    ),
+                    format!("Expected x or nothing in checklist item, found {}", other),
                     token.span,
                     self.source,
                 ))
             }
         };
+        self.expect_token(TokenKind::LBrace)?;
+        let item = self.parse_inline_text("list item")?;
         self.expect_token(TokenKind::RBrace)?;
-        Ok(item)
+        Ok((done, item))
     }
 
     fn parse_until<F, T>(&mut self, end: TokenKind, f: F) -> Result<Vec<T>, ParserError>
@@ -373,7 +1276,7 @@ impl<'a> Parser<'a> {
             Ok(())
         } else {
             Err(ParserError::new_with_source(
-                format!("Expected {:?} but found {:?}", expected, token.kind),
+                format!("Expected {} but found {}", expected, token.kind),
                 token.span,
                 self.source,
             ))
@@ -385,14 +1288,30 @@ impl<'a> Parser<'a> {
         match token.kind {
             TokenKind::Ident(s) => Ok(s),
             other => Err(ParserError::new_with_source(
-                format!("Expected identifier, found {:?}", other),
+                format!("Expected identifier, found {}", other),
                 token.span,
                 self.source,
             )),
         }
     }
 
+    // comments only reach the parser when the lexer keeps them, and carry
+    // no meaning here
+    fn skip_comments(&mut self) {
+        // blank lines are only significant directly inside a section body
+        let blank_line_significant = self.section_depth == Some(self.open_braces.len());
+        while let Some(Ok(token)) = self.tokens.peek() {
+            match token.kind {
+                TokenKind::Comment(_) => {}
+                TokenKind::BlankLine if !blank_line_significant => {}
+                _ => break,
+            }
+            self.tokens.next();
+        }
+    }
+
     fn peek_token(&mut self) -> Result<Option<&Token>, ParserError> {
+        self.skip_comments();
         match self.tokens.peek() {
             Some(Ok(token)) => Ok(Some(token)),
             Some(Err(e)) => Err(e.clone().into()),
@@ -401,14 +1320,653 @@ impl<'a> Parser<'a> {
     }
 
     fn next_token(&mut self) -> Result<Token, ParserError> {
+        self.skip_comments();
         match self.tokens.next() {
-            Some(Ok(token)) => Ok(token),
+            Some(Ok(token)) => {
+                self.prev_span = token.span;
+                self.consumed += 1;
+                match token.kind {
+                    TokenKind::LBrace => self.open_braces.push(token.span),
+                    TokenKind::RBrace => {
+                        self.open_braces.pop();
+                    }
+                    _ => {}
+                }
+                Ok(token)
+            }
             Some(Err(e)) => Err(e.into()),
-            None => Err(ParserError::new_with_source(
-                "Unexpected end of input",
-                Span::new(Default::default(), Default::default()),
-                self.source,
-            )),
+            None => Err(self.end_of_input("Unexpected end of input")),
+        }
+    }
+
+    // end_of_input reports running out of input. When a brace is still open
+    // that is almost always the cause, so the error points at the opener
+    // instead of the end of the file.
+    fn end_of_input(&self, msg: &str) -> ParserError {
+        match self.open_braces.last() {
+            Some(&span) => {
+                ParserError::new_with_source("Unclosed '{' opened here", span, self.source)
+            }
+            None => ParserError::new_with_source(msg, self.eof, self.source),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{AstNode, List, Parser, Program, Statement};
+    use crate::lexer::{lexer::Lexer, tokens::token_specs};
+    use crate::parser::error::ParserError;
+
+    fn try_parse(src: &str) -> Result<Program, ParserError> {
+        try_parse_in(src, Path::new(""))
+    }
+
+    fn try_parse_in(src: &str, base_dir: &Path) -> Result<Program, ParserError> {
+        let src = src.to_string();
+        let lexer = Lexer::new(&src, token_specs());
+        Parser::new(lexer, &src, base_dir).parse()
+    }
+
+    fn parse(src: &str) -> Program {
+        try_parse(src).unwrap()
+    }
+
+    fn statements(program: &Program, section: &str) -> Vec<Statement> {
+        program.sections[section].paragraphs[0]
+            .statements
+            .iter()
+            .map(|statement| statement.node.clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_bare_text_is_coalesced() {
+        let program = parse("article { s } section s { paragraph { hello there world } }");
+        let stmts = statements(&program, "s");
+        assert_eq!(stmts.len(), 1);
+        assert!(matches!(&stmts[0], Statement::TextBlock(t) if t == "hello there world"));
+    }
+
+    #[test]
+    fn test_bare_text_keeps_spacing() {
+        let program =
+            parse("article { s } section s { paragraph { hello   there\tbig\n  world } }");
+        let stmts = statements(&program, "s");
+        assert!(matches!(&stmts[0], Statement::TextBlock(t) if t == "hello   there\tbig\n  world"));
+
+        // a comment between words isn't part of the text
+        let program = parse("article { s } section s { paragraph { hello /* note */ world } }");
+        let stmts = statements(&program, "s");
+        assert!(matches!(&stmts[0], Statement::TextBlock(t) if t == "hello world"));
+    }
+
+    #[test]
+    fn test_bare_text_mixed_with_statements() {
+        let program = parse(
+            "article { s } section s { paragraph { first words `explicit` more text h1 { Title } last } }",
+        );
+        let stmts = statements(&program, "s");
+        assert_eq!(stmts.len(), 5);
+        assert!(matches!(&stmts[0], Statement::TextBlock(t) if t == "first words"));
+        assert!(matches!(&stmts[1], Statement::TextBlock(t) if t == "explicit"));
+        assert!(matches!(&stmts[2], Statement::TextBlock(t) if t == "more text"));
+        assert!(matches!(&stmts[3], Statement::Heading(_, None, t) if t == "Title"));
+        assert!(matches!(&stmts[4], Statement::TextBlock(t) if t == "last"));
+    }
+
+    #[test]
+    fn test_sections_keep_declaration_order() {
+        let src =
+            "article { c } section e { } section d { } section c { } section b { } section a { }";
+        for _ in 0..10 {
+            let program = parse(src);
+            let names: Vec<&str> = program.sections.iter().map(|(n, _)| n.as_str()).collect();
+            assert_eq!(names, vec!["e", "d", "c", "b", "a"]);
+            assert_eq!(program.unused_sections(), vec!["e", "d", "b", "a"]);
+        }
+    }
+
+    #[test]
+    fn test_duplicate_section_reported_deterministically() {
+        let src = "article { } section b { } section a { } section a { } section b { }";
+        for _ in 0..10 {
+            let err = try_parse(src).unwrap_err();
+            assert_eq!(err.msg, "Duplicate section: a");
+        }
+    }
+
+    #[test]
+    fn test_blank_lines_split_paragraphs() {
+        let split = "article a { s }
+section s {
+    h2 { Intro }
+    `First paragraph.`
+
+    /* a comment */
+    `Second paragraph.`
+    ul {
+        li { a }
+
+        li { b }
+    }
+
+
+    paragraph { `Explicit.` }
+    `Third.`
+}";
+        let lexer = Lexer::new(split, token_specs()).with_blank_lines(true);
+        let program = Parser::new(lexer, split, Path::new("")).parse().unwrap();
+
+        let explicit = parse(
+            "article a { s }
+section s {
+    paragraph { h2 { Intro } `First paragraph.` }
+    paragraph { `Second paragraph.` ul { li { a } li { b } } }
+    paragraph { `Explicit.` }
+    paragraph { `Third.` }
+}",
+        );
+        assert_eq!(program, explicit);
+
+        // without the mode, statements still need a paragraph block
+        assert!(try_parse(split).is_err());
+    }
+
+    #[test]
+    fn test_program_into_iterator() {
+        let program = parse("article a { s t } section s { paragraph { `x` } } section t { }");
+        let mut sections = 0;
+        for node in &program {
+            if let AstNode::Section(_) = node {
+                sections += 1;
+            }
+        }
+        assert_eq!(sections, 2);
+        assert_eq!((&program).into_iter().count(), program.iter_ast().count());
+    }
+
+    #[test]
+    fn test_section_metadata() {
+        let program =
+            parse(r#"article a { s t } section s[author=Sam, tags="rust"] { } section t { }"#);
+        let metadata = &program.sections["s"].metadata;
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata["author"], "Sam");
+        assert_eq!(metadata["tags"], "rust");
+        assert!(program.sections["t"].metadata.is_empty());
+        assert!(program.article().metadata.is_empty());
+
+        // articles take metadata too, with or without a name
+        let program = parse(r#"article a[date="2026-10-16"] { }"#);
+        assert_eq!(program.article().metadata["date"], "2026-10-16");
+        let program = parse("article [author=Sam] { }");
+        assert_eq!(program.article().name, "");
+        assert_eq!(program.article().metadata["author"], "Sam");
+    }
+
+    #[test]
+    fn test_malformed_section_metadata() {
+        let cases = [
+            (
+                "section s[author] { }",
+                "Expected '=' but found closing bracket",
+            ),
+            (
+                "section s[author=] { }",
+                "Expected a value for author, found closing bracket",
+            ),
+            (
+                "section s[a=1 b=2] { }",
+                "Expected closing bracket but found identifier 'b'",
+            ),
+            ("section s[a=1, a=2] { }", "Duplicate metadata key: a"),
+            (
+                "section s[a=1 { }",
+                "Expected closing bracket but found opening brace",
+            ),
+        ];
+        for (src, msg) in cases {
+            let err = try_parse(&format!("article x {{ s }} {}", src)).unwrap_err();
+            assert_eq!(err.msg, msg, "{}", src);
+        }
+    }
+
+    #[test]
+    fn test_find_section_and_call_count() {
+        let program = parse("article { a b a } section a { } section b { } section c { }");
+        assert_eq!(
+            program.find_section("b").map(|s| s.name.as_str()),
+            Some("b")
+        );
+        assert!(program.find_section("missing").is_none());
+        assert_eq!(program.call_count("a"), 2);
+        assert_eq!(program.call_count("b"), 1);
+        assert_eq!(program.call_count("c"), 0);
+        assert_eq!(program.call_count("missing"), 0);
+    }
+
+    #[test]
+    fn test_statement_spans_cover_whole_statement() {
+        let src = "article { s } section s { paragraph { `a` h2 { Title } } }";
+        let program = parse(src);
+        let paragraph = &program.sections["s"].paragraphs[0];
+        let spanned: Vec<&str> = paragraph
+            .statements
+            .iter()
+            .map(|statement| &src[statement.span.start().offset()..statement.span.end().offset()])
+            .collect();
+        assert_eq!(spanned, vec!["`a`", "h2 { Title }"]);
+
+        // declarations carry their spans the same way
+        let section = &program.sections["s"];
+        assert_eq!(
+            &src[section.span.start().offset()..section.span.end().offset()],
+            &src[14..]
+        );
+        assert_eq!(section.name, "s");
+    }
+
+    #[test]
+    fn test_footnotes_collected() {
+        let program = parse(
+            "article { a } section a { paragraph { `x` footnote{one} footnote{one}{`First`} } } section b { paragraph { footnote{two}{`Second`} } }",
+        );
+        assert_eq!(program.footnotes().len(), 2);
+        assert_eq!(program.footnote("one"), Some("First"));
+        assert_eq!(program.footnote("two"), Some("Second"));
+        assert_eq!(program.footnote_refs(), vec!["one"]);
+    }
+
+    #[test]
+    fn test_footnote_errors() {
+        let err = try_parse("article { a } section a { paragraph { footnote{x} } }").unwrap_err();
+        assert_eq!(err.msg, "Undefined footnote: x");
+        let err =
+            try_parse("article { a } section a { paragraph { footnote{x}{a} footnote{x}{b} } }")
+                .unwrap_err();
+        assert_eq!(err.msg, "Duplicate footnote: x");
+    }
+
+    #[test]
+    fn test_stray_list_item() {
+        let src = "article { a } section a { paragraph { li { `x` } } }";
+        let err = try_parse(src).unwrap_err();
+        assert_eq!(
+            err.msg,
+            "List items must appear inside an ol, ul or cl block"
+        );
+        assert_eq!(err.span.start().offset(), src.find("li").unwrap());
+    }
+
+    #[test]
+    fn test_columns() {
+        let program = parse(
+            "article { a } section a { paragraph { columns { paragraph { `left` } paragraph { `right` footnote{n} } } footnote{n}{`note`} } }",
+        );
+        let stmts = statements(&program, "a");
+        match &stmts[0] {
+            Statement::Columns(columns) => {
+                assert_eq!(columns.len(), 2);
+                assert!(
+                    matches!(&columns[0].statements[0].node, Statement::TextBlock(t) if t == "left")
+                );
+                assert!(
+                    matches!(&columns[1].statements[0].node, Statement::TextBlock(t) if t == "right")
+                );
+            }
+            other => panic!("expected columns, found {:?}", other),
+        }
+        assert_eq!(program.footnote_refs(), vec!["n"]);
+
+        let program = parse("article { a } section a { paragraph { columns { } } }");
+        assert!(matches!(&statements(&program, "a")[0], Statement::Columns(c) if c.is_empty()));
+    }
+
+    #[test]
+    fn test_parse_recover_reports_each_declaration() {
+        let src = "article a { s } section { } section s { paragraph { x } } section t { paragraph { h1 } } footer { `ok` }".to_string();
+        let lexer = Lexer::new(&src, token_specs());
+        let (program, errors) = Parser::new(lexer, &src, Path::new("")).parse_recover(20);
+        assert!(program.is_none());
+        let messages: Vec<&str> = errors.iter().map(|e| e.msg.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Expected identifier, found opening brace",
+                "Expected opening brace but found closing brace"
+            ]
+        );
+
+        let src = "article a { s } section s { paragraph { x } }".to_string();
+        let lexer = Lexer::new(&src, token_specs());
+        let (program, errors) = Parser::new(lexer, &src, Path::new("")).parse_recover(20);
+        assert!(errors.is_empty());
+        assert_eq!(program.unwrap(), parse(&src));
+    }
+
+    #[test]
+    fn test_parse_recover_caps_errors() {
+        let src = format!("article a {{ }} {}", "section { } ".repeat(50));
+        let lexer = Lexer::new(&src, token_specs());
+        let (_, errors) = Parser::new(lexer, &src, Path::new("")).parse_recover(5);
+        assert_eq!(errors.len(), 6);
+        assert!(errors[..5]
+            .iter()
+            .all(|e| e.msg == "Expected identifier, found opening brace"));
+        assert_eq!(errors[5].msg, "Too many errors, stopped after 5");
+
+        // no note when the cap is hit by the last error in the file
+        let src = format!("article a {{ }} {}", "section { } ".repeat(5));
+        let lexer = Lexer::new(&src, token_specs());
+        let (_, errors) = Parser::new(lexer, &src, Path::new("")).parse_recover(5);
+        assert_eq!(errors.len(), 5);
+    }
+
+    #[test]
+    fn test_forward_references() {
+        // the article comes last, after everything it uses, and the first
+        // section references a footnote defined in the one after it
+        let src = "section intro { paragraph { `see` footnote{n} } } section notes { paragraph { footnote{n}{`later`} } } article a { intro notes }";
+        let program = parse(src);
+        let names: Vec<&str> = program
+            .sections_in_order()
+            .map(|s| s.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["intro", "notes"]);
+        assert_eq!(program.footnote("n"), Some("later"));
+        assert!(program.validate(src).is_ok());
+        assert_eq!(
+            parse("article a { late } section late { paragraph { `x` } }"),
+            parse("section late { paragraph { `x` } } article a { late }")
+        );
+
+        // without the later section the reference has nothing to resolve to
+        let src = "section intro { paragraph { `see` footnote{n} } } article a { intro }";
+        assert_eq!(try_parse(src).unwrap_err().msg, "Undefined footnote: n");
+    }
+
+    #[test]
+    fn test_sections_in_order() {
+        let program = parse(
+            "article { two missing three one } section one { } section two { } section three { }",
+        );
+        let names: Vec<&str> = program
+            .sections_in_order()
+            .map(|s| s.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["two", "three", "one"]);
+    }
+
+    #[test]
+    fn test_import_merges_sections() {
+        let dir = std::env::temp_dir().join(format!("blogger-import-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("shared")).unwrap();
+        std::fs::write(
+            dir.join("shared").join("bio.blog"),
+            "section bio { paragraph { `about me` } }",
+        )
+        .unwrap();
+
+        let program = try_parse_in(
+            "import \"shared/bio.blog\" article a { intro bio } section intro { paragraph { hi } }",
+            &dir,
+        )
+        .unwrap();
+        let names: Vec<&String> = program.sections.iter().map(|(name, _)| name).collect();
+        assert_eq!(names, ["bio", "intro"]);
+        assert!(
+            matches!(&statements(&program, "bio")[0], Statement::TextBlock(t) if t == "about me")
+        );
+
+        let err = try_parse_in(
+            "article a { bio } section bio { paragraph { x } } import \"shared/bio.blog\"",
+            &dir,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.msg,
+            "Duplicate section: bio (imported from shared/bio.blog)"
+        );
+
+        let err = try_parse_in("article a { } import \"missing.blog\"", &dir).unwrap_err();
+        assert!(err.msg.starts_with("Cannot import missing.blog"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_kept_comments_are_ignored() {
+        let src = "/* top */ article a { s } section s { /* x */ paragraph { hi } }".to_string();
+        let lexer = Lexer::new(&src, token_specs()).with_keep_comments(true);
+        let program = Parser::new(lexer, &src, Path::new("")).parse().unwrap();
+        assert_eq!(statements(&program, "s").len(), 1);
+    }
+
+    #[test]
+    fn test_end_of_input_points_at_last_line() {
+        let err =
+            try_parse("article a { s }\nsection s {\n  paragraph { `x` } }\nsection").unwrap_err();
+        assert_eq!(err.msg, "Unexpected end of input");
+        assert_eq!(err.span.start().line(), 3);
+        assert!(err.to_string().contains("section"));
+    }
+
+    #[test]
+    fn test_code_block_forms() {
+        let program = parse(
+            "article a { s } section s { paragraph { code { `let x = 1;` } code { let x = 1; } } }",
+        );
+        assert_eq!(
+            statements(&program, "s"),
+            vec![
+                Statement::CodeBlock("let x = 1;".to_string()),
+                Statement::CodeBlock("let x = 1;".to_string()),
+            ]
+        );
+
+        // nested braces are balanced and indentation is kept
+        let program = parse(
+            "article a { s } section s { paragraph {\n  code {\n    fn main() {\n        run();\n    }\n  }\n} }",
+        );
+        assert_eq!(
+            statements(&program, "s"),
+            vec![Statement::CodeBlock(
+                "    fn main() {\n        run();\n    }".to_string()
+            )]
+        );
+
+        let err = try_parse("article a { s } section s { paragraph { code { if x {").unwrap_err();
+        assert!(err.msg.contains("Unterminated block"));
+    }
+
+    #[test]
+    fn test_unclosed_brace_points_at_opener() {
+        let err = try_parse("article a { s }\nsection s {\n  paragraph { `x` }\n").unwrap_err();
+        assert_eq!(err.msg, "Unclosed '{' opened here");
+        assert_eq!(err.span.start().line(), 1);
+        assert_eq!(err.span.start().column(), 10);
+        assert!(err.to_string().contains("section s {"));
+
+        // the innermost unclosed brace is reported
+        let err = try_parse("article a { s }\nsection s {\n  paragraph { `x`\n").unwrap_err();
+        assert_eq!(err.span.start().line(), 2);
+    }
+
+    #[test]
+    fn test_ast_equality() {
+        let src = "article a { s t } section s { paragraph { h1 { Hi } `x` } } section t { paragraph { ul { li { y } } } }";
+        assert_eq!(parse(src), parse(src));
+
+        // layout and section order don't matter
+        let reordered = "article a { s t }\nsection t {\n  paragraph { ul { li { y } } }\n}\nsection s {\n  paragraph { h1 { Hi } `x` }\n}";
+        assert_eq!(parse(src), parse(reordered));
+
+        let changed = "article a { s t } section s { paragraph { h1 { Hi } `z` } } section t { paragraph { ul { li { y } } } }";
+        assert_ne!(parse(src), parse(changed));
+    }
+
+    #[test]
+    fn test_multi_token_inline_text() {
+        let program = parse(
+            "article a { s } section s { paragraph { aside { hello world } h2 { A `quoted` title } ul { li { two words } } } }",
+        );
+        let stmts = statements(&program, "s");
+        assert!(matches!(&stmts[0], Statement::Aside(t) if t == "hello world"));
+        assert!(
+            matches!(&stmts[1], Statement::Heading(tag, _, t) if tag == "h2" && t == "A quoted title")
+        );
+        assert!(
+            matches!(&stmts[2], Statement::List(List::Unordered(items)) if items == &["two words"])
+        );
+    }
+
+    #[test]
+    fn test_inline_text_rejects_structure() {
+        let err =
+            try_parse("article a { s } section s { paragraph { aside { note code { `x` } } } }")
+                .unwrap_err();
+        assert_eq!(
+            err.msg,
+            "Only text is allowed in aside, found keyword 'code'"
+        );
+
+        let err = try_parse("article a { s } section s { paragraph { h1 { } } }").unwrap_err();
+        assert_eq!(err.msg, "Expected text in heading");
+    }
+
+    #[test]
+    fn test_preamble_and_footer() {
+        let program = parse(
+            "preamble { `Draft` } article a { s } section s { paragraph { x } } footer { `Thanks` aside { bye } }",
+        );
+        let preamble = program.preamble().unwrap();
+        assert_eq!(preamble.statements.len(), 1);
+        assert!(matches!(&preamble.statements[0].node, Statement::TextBlock(t) if t == "Draft"));
+        let footer = program.footer().unwrap();
+        assert_eq!(footer.statements.len(), 2);
+
+        let err = try_parse("article a { } footer { x } footer { y }").unwrap_err();
+        assert_eq!(err.msg, "Multiple footer declarations found");
+    }
+
+    #[test]
+    fn test_unexpected_token_message() {
+        let err = try_parse("article a { s } section s { paragraph { x } } }").unwrap_err();
+        assert_eq!(err.msg, "Unexpected closing brace at program level");
+
+        let err = try_parse("article a { s } section { }").unwrap_err();
+        assert_eq!(err.msg, "Expected identifier, found opening brace");
+    }
+
+    #[test]
+    fn test_heading_id() {
+        let program = parse(
+            "article a { s } section s { paragraph { h2(intro-section) { Title } h2 { Auto } } }",
+        );
+        let stmts = statements(&program, "s");
+        assert!(
+            matches!(&stmts[0], Statement::Heading(tag, Some(id), t) if tag == "h2" && id == "intro-section" && t == "Title")
+        );
+        assert!(matches!(&stmts[1], Statement::Heading(_, None, t) if t == "Auto"));
+
+        let err =
+            try_parse("article a { s } section s { paragraph { h2() { Title } } }").unwrap_err();
+        assert_eq!(err.msg, "Expected identifier, found closing parenthesis");
+    }
+
+    #[test]
+    fn test_add_section_keeps_names_in_sync() {
+        let mut program = parse("article a { s u } section s { paragraph { footnote{n}{`one`} } }");
+        let other_src = "article b { } section u { paragraph { `x` footnote{m}{`two`} } } section s { paragraph { footnote{n}{`three`} } }";
+        let other = parse(other_src);
+
+        program
+            .add_section(other.section("u").unwrap().clone(), other_src)
+            .unwrap();
+        assert_eq!(program.section("u").unwrap().name, "u");
+        assert_eq!(program.footnote("m"), Some("two"));
+        assert!(program
+            .sections()
+            .iter()
+            .all(|(name, section)| *name == section.name));
+
+        // a taken name or footnote id leaves the program as it was
+        let before = program.clone();
+        let err = program
+            .add_section(other.section("s").unwrap().clone(), other_src)
+            .unwrap_err();
+        assert_eq!(err.msg, "Duplicate section: s");
+        // the error quotes the source the section came from
+        assert!(err.render().contains("section s"), "{}", err.render());
+        let mut renamed = other.section("s").unwrap().clone();
+        renamed.name = "t".to_string();
+        let err = program.add_section(renamed, other_src).unwrap_err();
+        assert_eq!(err.msg, "Duplicate footnote: n");
+        assert!(
+            err.render().contains("footnote{n}{`three`}"),
+            "{}",
+            err.render()
+        );
+        assert_eq!(program, before);
+        assert_eq!(program.sections().len(), 2);
+
+        // nor can a deserialized program store a section under another name
+        let ron = ron::to_string(&program).unwrap();
+        assert!(ron::from_str::<Program>(&ron).is_ok());
+        let mismatched = ron.replacen("(\"u\",", "(\"v\",", 1);
+        assert_ne!(mismatched, ron);
+        assert!(ron::from_str::<Program>(&mismatched).is_err());
+    }
+
+    #[test]
+    fn test_anchor() {
+        let program =
+            parse("article a { s } section s { paragraph { `x` anchor{my-target} `y` } }");
+        assert_eq!(
+            statements(&program, "s")[1],
+            Statement::Anchor("my-target".to_string())
+        );
+
+        let err =
+            try_parse("article a { s } section s { paragraph { anchor{MyTarget} } }").unwrap_err();
+        assert_eq!(
+            err.msg,
+            "Invalid anchor id 'MyTarget', use lowercase letters, digits and hyphens"
+        );
+
+        // collides with the auto id of a heading, an explicit one, or another anchor
+        for src in [
+            "article a { s } section s { paragraph { h2 { Getting Started } anchor{getting-started} } }",
+            "article a { s } section s { paragraph { anchor{intro} } } section t { paragraph { h2(intro) { x } } }",
+            "article a { s } section s { paragraph { anchor{x} columns { paragraph { anchor{x} } } } }",
+        ] {
+            let err = try_parse(src).unwrap_err();
+            assert!(err.msg.starts_with("Duplicate anchor: "), "{}", err.msg);
         }
     }
+
+    #[test]
+    fn test_checklist() {
+        let program = parse(
+            "article a { s } section s { paragraph { cl { li(x) { done item } li() { pending } } } }",
+        );
+        let stmts = statements(&program, "s");
+        assert_eq!(
+            stmts[0],
+            Statement::List(List::Checklist(vec![
+                (true, "done item".to_string()),
+                (false, "pending".to_string()),
+            ]))
+        );
+
+        let err = try_parse("article a { s } section s { paragraph { cl { li(y) { z } } } }")
+            .unwrap_err();
+        assert_eq!(
+            err.msg,
+            "Expected x or nothing in checklist item, found identifier 'y'"
+        );
+    }
 }
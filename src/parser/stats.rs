@@ -0,0 +1,141 @@
+use std::{collections::BTreeMap, fmt};
+
+use super::parser::{AstNode, List, Program, Statement};
+
+// Stats summarises the structure of the parts of a program reachable from its
+// article, as reported by the `stats` command.
+#[derive(Debug, Default, PartialEq)]
+pub struct Stats {
+    pub sections: usize,
+    pub paragraphs: usize,
+    pub lists: usize,
+    pub words: usize,
+    // statement counts keyed by kind, e.g. "text" or "code"
+    pub statements: BTreeMap<&'static str, usize>,
+    // heading counts keyed by tag, e.g. "h2"
+    pub headings: BTreeMap<String, usize>,
+}
+
+impl Program {
+    // stats walks the AST counting sections, paragraphs and statements.
+    // Statements nested in columns are counted along with their paragraph.
+    pub fn stats(&self) -> Stats {
+        let mut stats = Stats::default();
+        for node in self.iter_ast() {
+            match node {
+                AstNode::Section(_) => stats.sections += 1,
+                AstNode::Paragraph(paragraph) => {
+                    stats.paragraphs += 1;
+                    for (statement, _) in paragraph.statements_deep() {
+                        stats.record(statement);
+                    }
+                }
+                _ => {}
+            }
+        }
+        stats
+    }
+}
+
+impl Stats {
+    fn record(&mut self, statement: &Statement) {
+        *self.statements.entry(kind(statement)).or_default() += 1;
+        match statement {
+            Statement::Heading(tag, _, text) => {
+                *self.headings.entry(tag.clone()).or_default() += 1;
+                self.words += words(text);
+            }
+            Statement::TextBlock(text)
+            | Statement::Aside(text)
+            | Statement::FootnoteDef(_, text) => self.words += words(text),
+            Statement::List(List::Ordered(items) | List::Unordered(items)) => {
+                self.lists += 1;
+                self.words += items.iter().map(|item| words(item)).sum::<usize>();
+            }
+            Statement::List(List::Checklist(items)) => {
+                self.lists += 1;
+                self.words += items.iter().map(|(_, item)| words(item)).sum::<usize>();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<16}{:>6}", "sections", self.sections)?;
+        writeln!(f, "{:<16}{:>6}", "paragraphs", self.paragraphs)?;
+        writeln!(f, "{:<16}{:>6}", "lists", self.lists)?;
+        writeln!(f, "{:<16}{:>6}", "words", self.words)?;
+        writeln!(f, "statements")?;
+        for (kind, count) in &self.statements {
+            writeln!(f, "  {:<14}{:>6}", kind, count)?;
+        }
+        writeln!(f, "headings")?;
+        for (tag, count) in &self.headings {
+            writeln!(f, "  {:<14}{:>6}", tag, count)?;
+        }
+        Ok(())
+    }
+}
+
+fn kind(statement: &Statement) -> &'static str {
+    match statement {
+        Statement::Heading(..) => "heading",
+        Statement::TextBlock(_) => "text",
+        Statement::CodeBlock(_) => "code",
+        Statement::RawHtml(_) => "html",
+        Statement::Math(_) => "math",
+        Statement::Embed(_) => "embed",
+        Statement::Aside(_) => "aside",
+        Statement::List(_) => "list",
+        Statement::FootnoteRef(_) => "footnote ref",
+        Statement::FootnoteDef(..) => "footnote",
+        Statement::Columns(_) => "columns",
+        Statement::Anchor(_) => "anchor",
+    }
+}
+
+fn words(text: &str) -> usize {
+    text.split_whitespace().count()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        lexer::{lexer::Lexer, tokens::token_specs},
+        parser::parser::Parser,
+    };
+
+    #[test]
+    fn test_stats() {
+        let src = r#"article a { intro body }
+section intro {
+    paragraph { h1 { Hello world } `One two three.` }
+}
+section body {
+    paragraph { h2 { More } ul { li { a b } li { c } } code { `x y` } }
+    paragraph { aside { just a note } h2 { End } }
+}
+section unused { paragraph { `not counted` } }"#
+            .to_string();
+        let lexer = Lexer::new(&src, token_specs());
+        let stats = Parser::new(lexer, &src, Path::new(""))
+            .parse()
+            .unwrap()
+            .stats();
+
+        assert_eq!(stats.sections, 2);
+        assert_eq!(stats.paragraphs, 3);
+        assert_eq!(stats.lists, 1);
+        assert_eq!(stats.words, 2 + 3 + 1 + 3 + 3 + 1);
+        assert_eq!(stats.statements["heading"], 3);
+        assert_eq!(stats.statements["text"], 1);
+        assert_eq!(stats.statements["code"], 1);
+        assert_eq!(stats.headings["h1"], 1);
+        assert_eq!(stats.headings["h2"], 2);
+        assert!(stats.to_string().starts_with("sections             2\n"));
+    }
+}
@@ -1,4 +1,4 @@
-#[derive(Debug, Eq, Clone, Copy)]
+#[derive(Debug, Eq, Clone)]
 enum Token {
     Star,
     Opt,
@@ -8,30 +8,40 @@ enum Token {
     OpenParenthesis,
     ClosedParenthesis,
     Lit(char),
+    // `"abc"`, a run of literal chars
+    Str(String),
     CharRange(char, char),
+    Alphanumeric,
+    // `{n}`, `{n,}` or `{n,m}`, expanded into copies of its operand
+    Repeat(usize, Option<usize>),
 }
 
 impl Token {
     fn precedence(&self) -> u8 {
         match self {
-            Token::Star | Token::Plus | Token::Opt => 3,
+            Token::Star | Token::Plus | Token::Opt | Token::Repeat(..) => 3,
             Token::Concat => 2,
             Token::Alt => 1,
             _ => 0,
         }
     }
     fn is_op(&self) -> bool {
-        !matches!(self, Token::Lit(_) | Token::CharRange(_, _))
+        !matches!(
+            self,
+            Token::Lit(_) | Token::Str(_) | Token::CharRange(_, _) | Token::Alphanumeric
+        )
     }
-    fn to_expr(&self) -> Option<Expr> {
+    fn into_expr(self) -> Option<Expr> {
         match self {
             Token::Star => Some(Expr::Star),
             Token::Opt => Some(Expr::Opt),
             Token::Plus => Some(Expr::Plus),
             Token::Concat => Some(Expr::Concat),
             Token::Alt => Some(Expr::Alt),
-            Token::Lit(c) => Some(Expr::Literal(*c)),
-            Token::CharRange(a, b) => Some(Expr::CharRange(*a, *b)),
+            Token::Lit(c) => Some(Expr::Literal(c)),
+            Token::Str(s) => Some(Expr::StringLiteral(s)),
+            Token::CharRange(a, b) => Some(Expr::CharRange(a, b)),
+            Token::Alphanumeric => Some(Expr::Alphanumeric),
             _ => None,
         }
     }
@@ -45,7 +55,7 @@ impl PartialEq for Token {
 
 impl PartialOrd for Token {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
-        Some(self.precedence().cmp(&other.precedence()))
+        Some(self.cmp(other))
     }
 }
 
@@ -55,15 +65,22 @@ impl Ord for Token {
     }
 }
 
-#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Expr {
     Literal(char),
+    // the chars of a quoted string in sequence, as their concatenation
+    // would match
+    StringLiteral(String),
     Concat,
     Alt,
     Star,
     Opt,
     Plus,
     CharRange(char, char),
+    // `\w`, any alphanumeric char in any script
+    Alphanumeric,
+    // marks its operand as capture group n, only emitted by build_with_groups
+    Group(usize),
 }
 
 impl Expr {
@@ -78,28 +95,157 @@ impl Expr {
         }
     }
 
+    // validates a `l-r` range body (the text between the brackets) up front
+    // so a bad pattern is reported with the offending range
     fn process_range_token(s: &str) -> Result<Token, String> {
-        s.split_once('-')
+        let (l, r) = s
+            .split_once('-')
             .and_then(|(l, r)| Some((l.chars().next()?, r.chars().next()?)))
-            .and_then(|(l, r)| Some(Token::CharRange(l, r)))
-            .ok_or_else(|| "Invalid range".into())
+            .ok_or_else(|| format!("Invalid range '[{}]'", s))?;
+        if l > r {
+            return Err(format!(
+                "Invalid range '[{}]': ranges must be specified in ascending order",
+                s
+            ));
+        }
+        Ok(Token::CharRange(l, r))
+    }
+
+    // parses a `{n}`, `{n,}` or `{n,m}` quantifier body, anything else is an
+    // error since a literal brace has to be escaped
+    fn process_repeat_token(s: &str) -> Result<Token, String> {
+        let invalid = || {
+            format!(
+                "Invalid quantifier '{{{}}}', write '\\{{' to match a literal brace",
+                s
+            )
+        };
+        let parse = |n: &str| n.parse::<usize>().map_err(|_| invalid());
+        let (min, max) = match s.split_once(',') {
+            Some((min, "")) => (parse(min)?, None),
+            Some((min, max)) => (parse(min)?, Some(parse(max)?)),
+            None => (parse(s)?, Some(parse(s)?)),
+        };
+        match max {
+            Some(0) => Err(format!(
+                "Invalid quantifier '{{{}}}': it repeats nothing",
+                s
+            )),
+            Some(max) if min > max => Err(format!(
+                "Invalid quantifier '{{{}}}': the minimum is larger than the maximum",
+                s
+            )),
+            _ => Ok(Token::Repeat(min, max)),
+        }
+    }
+
+    // arity is how many operands an operator takes off the postfix stack
+    fn arity(&self) -> usize {
+        match self {
+            Expr::Concat | Expr::Alt => 2,
+            Expr::Star | Expr::Opt | Expr::Plus | Expr::Group(_) => 1,
+            _ => 0,
+        }
+    }
+
+    // repeat replaces the operand at the end of out, a complete postfix
+    // expression, with min concatenated copies of it followed by either a
+    // starred copy or up to max - min optional ones
+    fn repeat(out: &mut Vec<Expr>, min: usize, max: Option<usize>) -> Result<(), String> {
+        let mut needed = 1;
+        let start = out
+            .iter()
+            .rposition(|e| {
+                needed = needed - 1 + e.arity();
+                needed == 0
+            })
+            .ok_or("Missing fragment for quantifier")?;
+        let operand = out.split_off(start);
+        let copies = (0..min)
+            .map(|_| None)
+            .chain(match max {
+                Some(max) => vec![Some(Expr::Opt); max - min],
+                None => vec![Some(Expr::Star)],
+            })
+            .enumerate();
+        for (i, quantifier) in copies {
+            out.extend_from_slice(&operand);
+            out.extend(quantifier);
+            if i > 0 {
+                out.push(Expr::Concat);
+            }
+        }
+        Ok(())
+    }
+
+    // push_op moves an operator from the stack onto the postfix output
+    fn push_op(out: &mut Vec<Expr>, op: Token) -> Result<(), String> {
+        match op {
+            Token::Repeat(min, max) => Self::repeat(out, min, max),
+            op => {
+                out.push(op.into_expr().ok_or("Invalid token")?);
+                Ok(())
+            }
+        }
+    }
+
+    // unquotes the body of a `"..."` literal, where a backslash makes the
+    // char after it literal
+    fn process_string_token(s: &str) -> Result<Token, String> {
+        let mut out = String::new();
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => out.extend(chars.next()),
+                c => out.push(c),
+            }
+        }
+        if out.is_empty() {
+            return Err("Empty string literal '\"\"'".into());
+        }
+        Ok(Token::Str(out))
+    }
+
+    // a quote is escaped when an odd number of backslashes come before it
+    fn ends_in_escape(s: &str) -> bool {
+        s.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1
     }
 
     fn tokenize(s: &str) -> Result<Vec<Token>, String> {
         s.chars()
             .try_fold((None, Vec::new()), |(mut bracket_buf, mut out), c| {
                 match (bracket_buf.as_mut(), c) {
-                    (None, '[') => bracket_buf = Some(String::new()),
-                    (Some(buf), ']') => {
-                        let token = Self::process_range_token(buf)?;
-                        out.push(token);
-                        bracket_buf = None;
+                    (None, '[') => bracket_buf = Some(String::from("[")),
+                    (None, '{') => bracket_buf = Some(String::from("{")),
+                    (None, '"') => bracket_buf = Some(String::from("\"")),
+                    (None, '}') => {
+                        return Err("Unmatched '}', write '\\}' to match a literal brace".into())
                     }
                     (None, '\\') => bracket_buf = Some(String::from("\\")),
+                    // a quoted string takes every char up to its closing quote
+                    (Some(buf), '"') if buf.starts_with('"') && !Self::ends_in_escape(buf) => {
+                        out.push(Self::process_string_token(&buf[1..])?);
+                        bracket_buf = None;
+                    }
+                    (Some(buf), x) if buf.starts_with('"') => buf.push(x),
+                    // escapes come before ranges so `\]` is a literal bracket
+                    (Some(buf), 'w') if buf == "\\" => {
+                        out.push(Token::Alphanumeric);
+                        bracket_buf = None;
+                    }
                     (Some(buf), x) if buf == "\\" => {
                         out.push(Token::Lit(x));
                         bracket_buf = None;
                     }
+                    (Some(buf), '}') if buf.starts_with('{') => {
+                        out.push(Self::process_repeat_token(&buf[1..])?);
+                        bracket_buf = None;
+                    }
+                    (Some(buf), ']') if buf.starts_with('[') => {
+                        let token = Self::process_range_token(&buf[1..])?;
+                        out.push(token);
+                        bracket_buf = None;
+                    }
                     (Some(buf), x) => buf.push(x),
                     (None, '(') => out.push(Token::OpenParenthesis),
                     (None, ')') => out.push(Token::ClosedParenthesis),
@@ -112,39 +258,53 @@ impl Expr {
                 }
                 Ok((bracket_buf, out))
             })
-            .and_then(|(bracket_buf, out)| {
-                if bracket_buf.is_some() {
-                    Err("Unclosed '['".into())
-                } else {
-                    Ok(out)
+            .and_then(|(bracket_buf, out)| match bracket_buf {
+                Some(buf) if buf.starts_with('"') => Err("Unclosed '\"'".into()),
+                Some(buf) if buf.starts_with('{') => {
+                    Err("Unclosed '{', write '\\{' to match a literal brace".into())
                 }
+                Some(_) => Err("Unclosed '['".into()),
+                None => Ok(out),
             })
     }
 
-    fn parse_all(tokens: Vec<Token>) -> Result<Vec<Expr>, String> {
+    // parse_all converts the tokens to postfix. With groups set, each
+    // parenthesised operand is followed by a Group numbered by the position
+    // of its opening paren, counting from 1.
+    fn parse_all(tokens: Vec<Token>, groups: bool) -> Result<Vec<Expr>, String> {
+        let mut opened = 0;
+        let mut open_groups = Vec::new();
         tokens
-            .iter()
+            .into_iter()
             .try_fold((Vec::new(), Vec::new()), |(mut ops, mut out), t| {
                 if t.is_op() {
                     match t {
-                        Token::OpenParenthesis => ops.push(*t),
+                        Token::OpenParenthesis => {
+                            opened += 1;
+                            open_groups.push(opened);
+                            ops.push(t)
+                        }
                         Token::ClosedParenthesis => {
                             while let Some(op) = ops.pop() {
                                 if op == Token::OpenParenthesis {
                                     break;
                                 }
-                                out.push(op.to_expr().ok_or("Invalid token")?);
+                                Self::push_op(&mut out, op)?;
+                            }
+                            match open_groups.pop() {
+                                Some(n) if groups => out.push(Expr::Group(n)),
+                                _ => {}
                             }
                         }
                         _ => {
-                            while ops.last().map_or(false, |op| op.is_op() && op >= t) {
-                                out.push(ops.pop().unwrap().to_expr().unwrap());
+                            while ops.last().is_some_and(|op| op.is_op() && *op >= t) {
+                                Self::push_op(&mut out, ops.pop().unwrap())?;
                             }
-                            ops.push(*t);
+                            ops.push(t);
                         }
                     }
                 } else {
-                    out.push(t.to_expr().unwrap());
+                    out.push(t.into_expr().unwrap());
                 }
                 Ok((ops, out))
             })
@@ -153,14 +313,19 @@ impl Expr {
                     if op == Token::OpenParenthesis {
                         return Err("Unmatched '('".into());
                     }
-                    out.push(op.to_expr().ok_or("Invalid token")?);
+                    Self::push_op(&mut out, op)?;
                 }
                 Ok(out)
             })
     }
 
     pub fn build(s: &str) -> Result<Vec<Expr>, String> {
-        Self::tokenize(s).and_then(Self::parse_all)
+        Self::tokenize(s).and_then(|tokens| Self::parse_all(tokens, false))
+    }
+
+    // build_with_groups is build, also marking where each capture group is
+    pub fn build_with_groups(s: &str) -> Result<Vec<Expr>, String> {
+        Self::tokenize(s).and_then(|tokens| Self::parse_all(tokens, true))
     }
 }
 
@@ -258,6 +423,27 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_groups() {
+        // plain builds leave groups out
+        run_test(
+            "(a)|(b)",
+            &vec![Expr::Literal('a'), Expr::Literal('b'), Expr::Alt],
+        );
+        assert_eq!(
+            Expr::build_with_groups("(a|(b)).c").unwrap(),
+            vec![
+                Expr::Literal('a'),
+                Expr::Literal('b'),
+                Expr::Group(2),
+                Expr::Alt,
+                Expr::Group(1),
+                Expr::Literal('c'),
+                Expr::Concat,
+            ]
+        );
+    }
+
     #[test]
     fn test_char_range() {
         run_test("[a-z]", &vec![Expr::CharRange('a', 'z')]);
@@ -291,4 +477,113 @@ mod tests {
             ],
         );
     }
+
+    #[test]
+    fn test_descending_range_error() {
+        let err = Expr::build("a.[z-a]").unwrap_err();
+        assert_eq!(
+            err,
+            "Invalid range '[z-a]': ranges must be specified in ascending order"
+        );
+        assert_eq!(Expr::build("[-]").unwrap_err(), "Invalid range '[-]'");
+    }
+
+    #[test]
+    fn test_alphanumeric_escape() {
+        run_test(
+            "\\w.\\.",
+            &vec![Expr::Alphanumeric, Expr::Literal('.'), Expr::Concat],
+        );
+    }
+
+    #[test]
+    fn test_escaped_brace_is_literal() {
+        run_test("\\{", &vec![Expr::Literal('{')]);
+        run_test(
+            "\\{.\\}",
+            &vec![Expr::Literal('{'), Expr::Literal('}'), Expr::Concat],
+        );
+    }
+
+    #[test]
+    fn test_repeat_quantifier() {
+        run_test(
+            "a{2}",
+            &vec![Expr::Literal('a'), Expr::Literal('a'), Expr::Concat],
+        );
+        run_test(
+            "a{1,2}",
+            &vec![
+                Expr::Literal('a'),
+                Expr::Literal('a'),
+                Expr::Opt,
+                Expr::Concat,
+            ],
+        );
+        run_test(
+            "(a.b){1,}",
+            &vec![
+                Expr::Literal('a'),
+                Expr::Literal('b'),
+                Expr::Concat,
+                Expr::Literal('a'),
+                Expr::Literal('b'),
+                Expr::Concat,
+                Expr::Star,
+                Expr::Concat,
+            ],
+        );
+        run_test(
+            "x.[0-9]{0,1}",
+            &vec![
+                Expr::Literal('x'),
+                Expr::CharRange('0', '9'),
+                Expr::Opt,
+                Expr::Concat,
+            ],
+        );
+    }
+
+    #[test]
+    fn test_stray_brace_errors() {
+        assert_eq!(
+            Expr::build("a.{").unwrap_err(),
+            "Unclosed '{', write '\\{' to match a literal brace"
+        );
+        assert_eq!(
+            Expr::build("{a}").unwrap_err(),
+            "Invalid quantifier '{a}', write '\\{' to match a literal brace"
+        );
+        assert_eq!(
+            Expr::build("a}").unwrap_err(),
+            "Unmatched '}', write '\\}' to match a literal brace"
+        );
+        assert_eq!(
+            Expr::build("a{3,2}").unwrap_err(),
+            "Invalid quantifier '{3,2}': the minimum is larger than the maximum"
+        );
+        assert_eq!(
+            Expr::build("a{0}").unwrap_err(),
+            "Invalid quantifier '{0}': it repeats nothing"
+        );
+        assert_eq!(
+            Expr::build("{2}").unwrap_err(),
+            "Missing fragment for quantifier"
+        );
+    }
+
+    #[test]
+    fn test_string_literal() {
+        run_test("\"section\"", &vec![Expr::StringLiteral("section".into())]);
+        // operators, escapes and quotes are literal inside the quotes
+        run_test(
+            "\"a.b|\\\"c\"+",
+            &vec![Expr::StringLiteral("a.b|\"c".into()), Expr::Plus],
+        );
+        assert_eq!(Expr::build("\"abc").unwrap_err(), "Unclosed '\"'");
+        assert_eq!(
+            Expr::build("\"\"").unwrap_err(),
+            "Empty string literal '\"\"'"
+        );
+    }
 }
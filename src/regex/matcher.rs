@@ -14,16 +14,25 @@ pub trait Match: Sync {
 
 pub struct Matcher {
     pub nfa: NFA,
+    // the same pattern with Save states around each group, only walked by
+    // captures so matching doesn't pay for them
+    capture_nfa: NFA,
     epsilon_closure_cache: Mutex<HashMap<usize, Vec<State>>>,
 }
 
+// Thread is a position in the capture NFA along with the capture slots
+// recorded on the way there, as byte offsets
+type Thread = (usize, Vec<Option<usize>>);
+
 impl Matcher {
     pub fn new(s: &str) -> Result<Self, String> {
         let expr = Expr::build(s)?;
         let nfa = NFA::build(expr)?;
+        let capture_nfa = NFA::build(Expr::build_with_groups(s)?)?;
         let epsilon_closure_cache = Self::precompute_epsilon_closures(&nfa);
         Ok(Self {
             nfa,
+            capture_nfa,
             epsilon_closure_cache: Mutex::new(epsilon_closure_cache),
         })
     }
@@ -44,42 +53,215 @@ impl Matcher {
         if !seen.insert(state.get_id()) {
             return Vec::new();
         }
-        match state {
-            State::Split { left, right, .. } => {
-                let mut out = vec![state.clone()];
-                out.extend(
-                    left.map(|idx| Self::compute_epsilon_closure(nfa, seen, &nfa.get_state(idx)))
-                        .unwrap_or_default(),
-                );
-                out.extend(
-                    right
-                        .map(|idx| Self::compute_epsilon_closure(nfa, seen, &nfa.get_state(idx)))
-                        .unwrap_or_default(),
-                );
-                out
-            }
-            _ => vec![state.clone()],
+        let mut out = vec![state.clone()];
+        for idx in state.epsilon_outputs() {
+            out.extend(Self::compute_epsilon_closure(
+                nfa,
+                seen,
+                &nfa.get_state(idx),
+            ));
         }
+        out
     }
 
+    // matches reports whether the whole of `s` is accepted.
     pub fn matches(&self, s: &str) -> bool {
+        Self::accepts(&self.simulate(s).0)
+    }
+
+    // matches_prefix reports whether `s` is a prefix of some accepted string,
+    // i.e. the simulation still has live states after consuming all of `s`.
+    pub fn matches_prefix(&self, s: &str) -> bool {
+        !self.simulate(s).0.is_empty()
+    }
+
+    // longest_match returns the length, in chars, of the longest prefix of `s`
+    // that is accepted, found in a single left-to-right pass.
+    pub fn longest_match(&self, s: &str) -> Option<usize> {
         let ecc = self.epsilon_closure_cache.lock().unwrap();
-        let start = ecc.get(&self.nfa.start()).cloned().unwrap_or_default();
-        let final_states = s.chars().fold(start, |current, c| {
-            current
-                .into_iter()
-                .flat_map(|st| match st {
-                    State::Transition { output, .. } if st.matches_condition(c) => output
-                        .and_then(|o| ecc.get(&o))
-                        .cloned()
-                        .unwrap_or_default(),
-                    _ => Vec::new(),
-                })
-                .collect()
+        let mut current = ecc.get(&self.nfa.start()).cloned().unwrap_or_default();
+        let mut longest = Self::accepts(&current).then_some(0);
+        for (i, c) in s.chars().enumerate() {
+            current = Self::step(&ecc, current, c);
+            if current.is_empty() {
+                break;
+            }
+            if Self::accepts(&current) {
+                longest = Some(i + 1);
+            }
+        }
+        longest
+    }
+
+    // find_all returns the byte ranges of the non-overlapping, non-empty
+    // matches in `s`, scanning left to right and taking the longest match
+    // at each position.
+    pub fn find_all(&self, s: &str) -> Vec<(usize, usize)> {
+        let mut spans = Vec::new();
+        let mut start = 0;
+        while start < s.len() {
+            let rest = &s[start..];
+            match self.longest_match(rest).filter(|&len| len > 0) {
+                Some(len) => {
+                    let end = rest
+                        .char_indices()
+                        .nth(len)
+                        .map_or(s.len(), |(idx, _)| start + idx);
+                    spans.push((start, end));
+                    start = end;
+                }
+                None => start += rest.chars().next().map_or(1, char::len_utf8),
+            }
+        }
+        spans
+    }
+
+    // split returns the pieces of `s` between matches of the pattern.
+    pub fn split(&self, s: &str) -> Vec<String> {
+        let mut pieces = Vec::new();
+        let mut last = 0;
+        for (start, end) in self.find_all(s) {
+            pieces.push(s[last..start].to_string());
+            last = end;
+        }
+        pieces.push(s[last..].to_string());
+        pieces
+    }
+
+    // captures matches the whole of `s`, returning the text of each group:
+    // the whole match first, then the groups numbered by the position of
+    // their opening paren. A group that took no part in the match is None,
+    // one that matched empty text is Some(""). Where several ways to match
+    // exist, the one preferring the left branch of each alternation and the
+    // most repetitions wins, as in PCRE.
+    pub fn captures<'s>(&self, s: &'s str) -> Option<Vec<Option<&'s str>>> {
+        let nfa = &self.capture_nfa;
+        let slots = vec![None; 2 * (nfa.group_count() + 1)];
+        let mut current = Vec::new();
+        Self::add_thread(
+            nfa,
+            &mut current,
+            &mut HashSet::new(),
+            nfa.start(),
+            slots,
+            0,
+        );
+        for (i, c) in s.char_indices() {
+            let mut next = Vec::new();
+            let mut seen = HashSet::new();
+            for (idx, slots) in current {
+                let state = nfa.get_state(idx);
+                if let State::Transition {
+                    output: Some(output),
+                    ..
+                } = state
+                {
+                    if state.matches_condition(c) {
+                        let pos = i + c.len_utf8();
+                        Self::add_thread(nfa, &mut next, &mut seen, output, slots, pos);
+                    }
+                }
+            }
+            if next.is_empty() {
+                return None;
+            }
+            current = next;
+        }
+        let (_, slots) = current
+            .into_iter()
+            .find(|(idx, _)| matches!(nfa.get_state(*idx), State::Accept { .. }))?;
+        let groups = slots.chunks(2).skip(1).map(|slot| match slot {
+            [Some(start), Some(end)] if start <= end => Some(&s[*start..*end]),
+            _ => None,
         });
-        final_states
-            .iter()
-            .any(|st| matches!(st, State::Accept { .. }))
+        Some(std::iter::once(Some(s)).chain(groups).collect())
+    }
+
+    // add_thread follows the moves that consume nothing from idx, left
+    // branch first, adding the threads that wait on a char or accept. A
+    // state already reached this step was reached by a preferred path.
+    fn add_thread(
+        nfa: &NFA,
+        threads: &mut Vec<Thread>,
+        seen: &mut HashSet<usize>,
+        idx: usize,
+        mut slots: Vec<Option<usize>>,
+        pos: usize,
+    ) {
+        if !seen.insert(idx) {
+            return;
+        }
+        match nfa.get_state(idx) {
+            State::Split { left, right, .. } => {
+                for branch in left.into_iter().chain(right) {
+                    Self::add_thread(nfa, threads, seen, branch, slots.clone(), pos);
+                }
+            }
+            State::Epsilon { output, .. } => {
+                if let Some(output) = output {
+                    Self::add_thread(nfa, threads, seen, output, slots, pos);
+                }
+            }
+            State::Save { slot, output, .. } => {
+                slots[slot] = Some(pos);
+                if let Some(output) = output {
+                    Self::add_thread(nfa, threads, seen, output, slots, pos);
+                }
+            }
+            State::Transition { .. } | State::Accept { .. } => threads.push((idx, slots)),
+        }
+    }
+
+    // trace records, for each char of `s`, the ids of the states active after
+    // consuming it. It runs the same steps as `matches`, so it shows where a
+    // pattern stops matching.
+    pub fn trace(&self, s: &str) -> Vec<(char, Vec<usize>)> {
+        let ecc = self.epsilon_closure_cache.lock().unwrap();
+        let mut current = ecc.get(&self.nfa.start()).cloned().unwrap_or_default();
+        s.chars()
+            .map(|c| {
+                current = Self::step(&ecc, std::mem::take(&mut current), c);
+                let mut ids: Vec<usize> = current.iter().map(State::get_id).collect();
+                ids.sort_unstable();
+                ids.dedup();
+                (c, ids)
+            })
+            .collect()
+    }
+
+    // simulate runs the NFA over `s`, returning the set of states active at
+    // the end along with how many chars were consumed. Once no state is
+    // active nothing can match, so the rest of `s` is never looked at.
+    fn simulate(&self, s: &str) -> (Vec<State>, usize) {
+        let ecc = self.epsilon_closure_cache.lock().unwrap();
+        let mut current = ecc.get(&self.nfa.start()).cloned().unwrap_or_default();
+        let mut consumed = 0;
+        for c in s.chars() {
+            if current.is_empty() {
+                break;
+            }
+            current = Self::step(&ecc, current, c);
+            consumed += 1;
+        }
+        (current, consumed)
+    }
+
+    // step advances every active state over `c`, following epsilon closures.
+    fn step(ecc: &HashMap<usize, Vec<State>>, current: Vec<State>, c: char) -> Vec<State> {
+        current
+            .into_iter()
+            .flat_map(|st| match st {
+                State::Transition { output, .. } if st.matches_condition(c) => output
+                    .and_then(|o| ecc.get(&o))
+                    .cloned()
+                    .unwrap_or_default(),
+                _ => Vec::new(),
+            })
+            .collect()
+    }
+
+    fn accepts(states: &[State]) -> bool {
+        states.iter().any(|st| matches!(st, State::Accept { .. }))
     }
 }
 
@@ -190,4 +372,229 @@ mod tests {
         assert!(!matcher.matches("a"));
         assert!(!matcher.matches("abc"));
     }
+
+    #[test]
+    fn test_prefix_vs_full_match() {
+        let matcher = Matcher::new("s.e.c").expect("Failed to build Matcher");
+        assert!(matcher.matches_prefix(""));
+        assert!(matcher.matches_prefix("se"));
+        assert!(!matcher.matches("se"));
+        assert!(matcher.matches_prefix("sec"));
+        assert!(matcher.matches("sec"));
+        assert!(!matcher.matches_prefix("secs"));
+        assert!(!matcher.matches_prefix("x"));
+    }
+
+    #[test]
+    fn test_longest_match() {
+        let matcher = Matcher::new("a*").expect("Failed to build Matcher");
+        assert_eq!(matcher.longest_match("aaab"), Some(3));
+        assert_eq!(matcher.longest_match("b"), Some(0));
+
+        let matcher = Matcher::new("a.b").expect("Failed to build Matcher");
+        assert_eq!(matcher.longest_match("abab"), Some(2));
+        assert_eq!(matcher.longest_match("aa"), None);
+    }
+
+    #[test]
+    fn test_find_all() {
+        let matcher = Matcher::new("a+").expect("Failed to build Matcher");
+        assert_eq!(matcher.find_all("baaxa"), vec![(1, 3), (4, 5)]);
+        assert!(matcher.find_all("xyz").is_empty());
+    }
+
+    #[test]
+    fn test_split() {
+        let matcher = Matcher::new(",|;").expect("Failed to build Matcher");
+        assert_eq!(matcher.split("a,b;c"), vec!["a", "b", "c"]);
+        assert_eq!(matcher.split("a,,b"), vec!["a", "", "b"]);
+        assert_eq!(matcher.split("abc"), vec!["abc"]);
+
+        let matcher = Matcher::new("( )+").expect("Failed to build Matcher");
+        assert_eq!(matcher.split("é  b c"), vec!["é", "b", "c"]);
+    }
+
+    #[test]
+    fn test_matches_stops_once_no_state_is_active() {
+        let matcher = Matcher::new("a.(b)*").expect("Failed to build Matcher");
+        let long = format!("x{}", "b".repeat(100_000));
+        assert!(!matcher.matches(&long));
+        assert!(!matcher.matches_prefix(&long));
+        // the x leaves no state active, the bs after it are never stepped over
+        assert_eq!(matcher.simulate(&long).1, 1);
+
+        let matching = format!("a{}", "b".repeat(1_000));
+        assert!(matcher.matches(&matching));
+        assert_eq!(matcher.simulate(&matching).1, 1_001);
+        assert!(!matcher.matches(&format!("{}c", matching)));
+    }
+
+    #[test]
+    fn test_trace() {
+        let matcher = Matcher::new("a.b").expect("Failed to build Matcher");
+        // ids are state indices: a is 0, b is 1 and the accept state is 2
+        assert_eq!(matcher.trace("ab"), vec![('a', vec![1]), ('b', vec![2])]);
+        assert_eq!(matcher.trace("ax"), vec![('a', vec![1]), ('x', vec![])]);
+    }
+
+    #[test]
+    fn test_alphanumeric_class() {
+        let matcher = Matcher::new("(\\w)+").expect("Failed to build Matcher");
+        assert!(matcher.matches("café"));
+        assert!(matcher.matches("Straße42"));
+        assert!(!matcher.matches("a-b"));
+        assert!(!matcher.matches(""));
+    }
+
+    #[test]
+    fn test_wide_alternation_match() {
+        let matcher = Matcher::new("a|b|c|d|e").expect("Failed to build Matcher");
+        for s in ["a", "b", "c", "d", "e"] {
+            assert!(matcher.matches(s));
+        }
+        assert!(!matcher.matches("f"));
+        assert!(!matcher.matches("ab"));
+        assert!(!matcher.matches(""));
+
+        let matcher = Matcher::new("x.(a|b|c)*.y").expect("Failed to build Matcher");
+        assert!(matcher.matches("xy"));
+        assert!(matcher.matches("xabcacy"));
+        assert!(!matcher.matches("xady"));
+    }
+
+    #[test]
+    fn test_quantifier_corpus() {
+        // (pattern, accepted, rejected) across every quantifier, nested and
+        // combined with concatenation and alternation
+        let corpus: &[(&str, &[&str], &[&str])] = &[
+            ("a?", &["", "a"], &["aa", "b"]),
+            ("a*", &["", "a", "aaaa"], &["b", "ab"]),
+            ("a+", &["a", "aaa"], &["", "b"]),
+            ("(a*)*", &["", "a", "aaa"], &["b"]),
+            ("(a+)+", &["a", "aaaa"], &["", "ab"]),
+            ("(a?)*", &["", "a", "aa"], &["b"]),
+            ("a.b*.c", &["ac", "abc", "abbbc"], &["ab", "bc", "abcc"]),
+            ("a.b+.c", &["abc", "abbc"], &["ac", "abcb"]),
+            ("(a.b)*.c", &["c", "abc", "ababc"], &["ac", "abab"]),
+            ("(a|b.c)*", &["", "a", "bc", "abca"], &["b", "ac"]),
+            ("(a|b)+.c", &["ac", "babc"], &["c", "abd"]),
+            ("(s.e.c.t.i.o.n)", &["section"], &["sectio", "sections"]),
+            ("(h.[1-3])", &["h1", "h3"], &["h4", "h"]),
+            ("(\\w.(\\w|-)*)", &["a", "intro-section", "h2"], &["", "-a"]),
+            ("\\[.a.\\]", &["[a]"], &["a", "[a"]),
+            ("a{2,3}", &["aa", "aaa"], &["a", "aaaa"]),
+            ("(a|b){2,}.c", &["abc", "bbac"], &["ac", "abd"]),
+            ("\\{.[0-9]{1,2}.\\}", &["{1}", "{42}"], &["{}", "{123}"]),
+        ];
+        for (pattern, accepted, rejected) in corpus {
+            let matcher = Matcher::new(pattern).expect("Failed to build Matcher");
+            for s in *accepted {
+                assert!(matcher.matches(s), "{} should match {:?}", pattern, s);
+            }
+            for s in *rejected {
+                assert!(!matcher.matches(s), "{} should not match {:?}", pattern, s);
+            }
+        }
+    }
+
+    #[test]
+    fn test_plus_over_group() {
+        let matcher = Matcher::new("(a.b)+").expect("Failed to build Matcher");
+        assert!(matcher.matches("ab"));
+        assert!(matcher.matches("abab"));
+        assert!(!matcher.matches("aba"));
+        assert!(!matcher.matches(""));
+
+        // every output of an alternation loops back
+        let matcher = Matcher::new("(a.b|c)+").expect("Failed to build Matcher");
+        assert!(matcher.matches("cabc"));
+        assert!(!matcher.matches("ca"));
+    }
+
+    #[test]
+    fn test_optional_group() {
+        let matcher = Matcher::new("(a|b)?").expect("Failed to build Matcher");
+        for s in ["", "a", "b"] {
+            assert!(matcher.matches(s), "{:?}", s);
+        }
+        assert!(!matcher.matches("ab"));
+
+        // an option after a concatenation starts from the first literal
+        let matcher = Matcher::new("x.(a|b)?").expect("Failed to build Matcher");
+        assert!(matcher.matches("x"));
+        assert!(matcher.matches("xb"));
+        assert!(!matcher.matches(""));
+        assert!(!matcher.matches("a"));
+
+        let matcher = Matcher::new("-.-.v.(=.(a)*)?").expect("Failed to build Matcher");
+        assert!(matcher.matches("--v"));
+        assert!(matcher.matches("--v=aa"));
+        assert!(!matcher.matches("--"));
+    }
+
+    #[test]
+    fn test_string_literal_matches_like_char_chain() {
+        let quoted = Matcher::new("\"section\"").unwrap();
+        let chained = Matcher::new("s.e.c.t.i.o.n").unwrap();
+        for input in ["section", "sectio", "sections", "", "Section", "xsection"] {
+            assert_eq!(quoted.matches(input), chained.matches(input), "{}", input);
+        }
+        assert!(quoted.matches("section"));
+    }
+
+    #[test]
+    fn test_captures_across_alternation() {
+        let matcher = Matcher::new("(a)|(b)").unwrap();
+        assert!(matcher.matches("a"));
+        assert_eq!(
+            matcher.captures("a"),
+            Some(vec![Some("a"), Some("a"), None])
+        );
+        assert_eq!(
+            matcher.captures("b"),
+            Some(vec![Some("b"), None, Some("b")])
+        );
+        assert_eq!(matcher.captures("c"), None);
+        assert_eq!(matcher.captures("ab"), None);
+    }
+
+    #[test]
+    fn test_captures_numbered_by_opening_paren() {
+        let matcher = Matcher::new("(a|(b)).(c)").unwrap();
+        assert_eq!(
+            matcher.captures("bc"),
+            Some(vec![Some("bc"), Some("b"), Some("b"), Some("c")])
+        );
+        assert_eq!(
+            matcher.captures("ac"),
+            Some(vec![Some("ac"), Some("a"), None, Some("c")])
+        );
+        let matcher = Matcher::new("((a).(b))").unwrap();
+        assert_eq!(
+            matcher.captures("ab"),
+            Some(vec![Some("ab"), Some("ab"), Some("a"), Some("b")])
+        );
+    }
+
+    #[test]
+    fn test_captures_empty_and_unentered() {
+        // a group that matched nothing is set, one never entered isn't
+        assert_eq!(
+            Matcher::new("(a*).b").unwrap().captures("b"),
+            Some(vec![Some("b"), Some("")])
+        );
+        assert_eq!(
+            Matcher::new("(a)*.b").unwrap().captures("b"),
+            Some(vec![Some("b"), None])
+        );
+        // a repeated group holds its last repetition
+        assert_eq!(
+            Matcher::new("([a-z])+").unwrap().captures("xyz"),
+            Some(vec![Some("xyz"), Some("z")])
+        );
+        assert_eq!(
+            Matcher::new("a.b").unwrap().captures("ab"),
+            Some(vec![Some("ab")])
+        );
+    }
 }
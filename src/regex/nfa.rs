@@ -1,18 +1,30 @@
-use std::{collections::HashMap, fmt::Debug, sync::Mutex};
+use std::fmt::{self, Debug};
 
 use super::expr::Expr;
 
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Condition {
     Id(char),
-    CharClass(Vec<char>),
+    // matches when any of the conditions does, built by coalescing an
+    // alternation of single char branches
+    CharClass(Vec<Condition>),
+    // an inclusive range, checked by comparison so wide ranges cost nothing
+    Range { start: char, end: char },
+    // any char for which char::is_alphanumeric holds, so letters outside
+    // ASCII don't need enumerating
+    Alphanumeric,
 }
 
-impl Condition {
-    pub fn to_string(&self) -> String {
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::Id(c) => c.to_string(),
-            Self::CharClass(chars) => format!("{chars:?}"),
+            Self::Id(c) => write!(f, "{c}"),
+            Self::CharClass(conditions) => {
+                let branches: Vec<String> = conditions.iter().map(|c| c.to_string()).collect();
+                write!(f, "{}", branches.join("|"))
+            }
+            Self::Range { start, end } => write!(f, "{start}-{end}"),
+            Self::Alphanumeric => write!(f, "\\w"),
         }
     }
 }
@@ -29,56 +41,93 @@ pub enum State {
         left: Option<usize>,
         right: Option<usize>,
     },
+    // Epsilon moves to output without consuming a char, it is the way out
+    // of a quantified fragment
+    Epsilon {
+        id: usize,
+        output: Option<usize>,
+    },
+    // Save moves to output without consuming a char, recording the position
+    // in capture slot. Group n starts at slot 2n and ends at 2n + 1.
+    Save {
+        id: usize,
+        slot: usize,
+        output: Option<usize>,
+    },
     Accept {
         id: usize,
     },
 }
 
-impl State {
-    pub fn to_string(&self) -> String {
+impl fmt::Display for State {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Transition {
                 condition, output, ..
             } => {
-                format!("[match '{}' -> {output:?}]", condition.to_string())
+                write!(f, "[match '{condition}' -> {output:?}]")
             }
-            Self::Split { left, right, .. } => format!("[-> ({left:?} | {right:?})]"),
-            Self::Accept { .. } => "[accept]".to_string(),
-        }
-    }
-
-    pub fn set_out(&mut self, next_state: Option<usize>) {
-        match self {
-            Self::Transition { output, .. } => *output = next_state,
-            Self::Split { left, right, .. } => {
-                if left.is_none() && right.is_none() {
-                    *left = next_state;
-                }
-                *right = next_state;
-            }
-            _ => {}
+            Self::Split { left, right, .. } => write!(f, "[-> ({left:?} | {right:?})]"),
+            Self::Epsilon { output, .. } => write!(f, "[epsilon -> {output:?}]"),
+            Self::Save { slot, output, .. } => write!(f, "[save {slot} -> {output:?}]"),
+            Self::Accept { .. } => write!(f, "[accept]"),
         }
     }
+}
 
+impl State {
+    // get_id returns the state's index in the NFA
     pub fn get_id(&self) -> usize {
         match self {
             Self::Transition { id, .. } => *id,
             Self::Split { id, .. } => *id,
+            Self::Epsilon { id, .. } => *id,
+            Self::Save { id, .. } => *id,
             Self::Accept { id } => *id,
         }
     }
 
     pub fn matches_condition(&self, ch: char) -> bool {
         match self {
-            Self::Transition { condition, .. } => match condition {
-                Condition::Id(c) => *c == ch,
-                Condition::CharClass(v) => v.contains(&ch),
-            },
+            Self::Transition { condition, .. } => condition.matches(ch),
             _ => false,
         }
     }
+
+    // epsilon_outputs are the states reachable without consuming a char
+    pub fn epsilon_outputs(&self) -> Vec<usize> {
+        match self {
+            Self::Split { left, right, .. } => left.iter().chain(right.iter()).copied().collect(),
+            Self::Epsilon { output, .. } | Self::Save { output, .. } => {
+                output.iter().copied().collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, ch: char) -> bool {
+        match self {
+            Condition::Id(c) => *c == ch,
+            Condition::CharClass(conditions) => conditions.iter().any(|c| c.matches(ch)),
+            Condition::Range { start, end } => *start <= ch && ch <= *end,
+            Condition::Alphanumeric => ch.is_alphanumeric(),
+        }
+    }
+
+    // branches flattens a char class into its conditions
+    fn branches(self) -> Vec<Condition> {
+        match self {
+            Condition::CharClass(conditions) => conditions,
+            other => vec![other],
+        }
+    }
 }
 
+// Fragment is a partially built piece of the NFA. out holds the indices of
+// its Transition, Epsilon and Save states whose output is dangling, waiting to be
+// linked to whatever state follows the fragment.
 #[derive(Debug)]
 struct Fragment {
     head: usize,
@@ -86,25 +135,17 @@ struct Fragment {
 }
 
 impl Fragment {
-    fn detached(head: usize) -> Self {
-        Self {
-            head,
-            out: vec![head],
-        }
+    fn new(head: usize, out: Vec<usize>) -> Self {
+        Self { head, out }
     }
-    fn single_link(head: usize, out: usize) -> Self {
-        Self {
-            head,
-            out: vec![out],
-        }
-    }
-    fn multi_link(head: usize, left: Vec<usize>, right: Vec<usize>) -> Self {
+    fn merged(head: usize, left: Vec<usize>, right: Vec<usize>) -> Self {
         let mut outs = left;
         outs.extend(right);
         Self { head, out: outs }
     }
 }
 
+#[allow(clippy::upper_case_acronyms)]
 #[derive(Debug)]
 pub struct NFA {
     head: usize,
@@ -119,148 +160,189 @@ impl NFA {
         }
     }
 
-    fn add_state(&mut self, state: State) -> usize {
-        self.state_list.push(state.clone());
-        let idx = self.state_list.len() - 1;
-
+    // add_state appends the state built by f, which is given the new
+    // state's index to use as its id
+    fn add_state(&mut self, f: impl FnOnce(usize) -> State) -> usize {
+        let idx = self.state_list.len();
+        self.state_list.push(f(idx));
         idx
     }
 
-    fn link_state(&mut self, f_idx: usize, t_idx: usize) -> Result<(), String> {
-        self.state_list[f_idx].set_out(Some(t_idx));
-        let mut updated = self.state_list[f_idx].clone();
-        updated.set_out(Some(t_idx));
-        Ok(())
+    // add_transition adds a single state fragment matching condition
+    fn add_transition(&mut self, condition: Condition) -> Fragment {
+        let idx = self.add_state(|id| State::Transition {
+            id,
+            condition,
+            output: None,
+        });
+        Fragment::new(idx, vec![idx])
     }
 
-    fn link_fragment(&mut self, frag: &mut Fragment, t_idx: usize) -> Result<(), String> {
-        frag.out
-            .iter()
-            .try_for_each(|&idx| self.link_state(idx, t_idx))
+    // add_quantifier adds the Split in front of a quantified fragment. Its
+    // left branch enters e and its right leaves through a new Epsilon, which
+    // becomes the fragment's only way out.
+    fn add_quantifier(&mut self, e: &Fragment) -> (usize, usize) {
+        let split = self.add_state(|id| State::Split {
+            id,
+            left: Some(e.head),
+            right: Some(id + 1),
+        });
+        let exit = self.add_state(|id| State::Epsilon { id, output: None });
+        (split, exit)
     }
 
-    fn link_fragments(&mut self, from: &mut Fragment, to: Fragment) -> Result<(), String> {
-        self.link_fragment(from, to.head)?;
-        from.out.iter_mut().for_each(|o| *o = to.head);
-        Ok(())
+    // single_condition returns the condition of a fragment made of one
+    // unlinked Transition, which is what a literal, range or char class
+    // builds
+    fn single_condition(&self, frag: &Fragment) -> Option<&Condition> {
+        match (&frag.out[..], &self.state_list[frag.head]) {
+            ([out], State::Transition { condition, .. }) if *out == frag.head => Some(condition),
+            _ => None,
+        }
     }
 
-    fn range_chars(start: char, end: char) -> Result<Vec<char>, String> {
-        (start <= end)
-            .then(|| {
-                (start as u32..=end as u32)
-                    .filter_map(std::char::from_u32)
-                    .collect()
-            })
-            .ok_or_else(|| "Ranges must be specified in ascending order".into())
+    // coalesce merges the alternation of two single char fragments into one
+    // char class transition, so a wide alternation such as a|b|c|d costs a
+    // single state rather than a chain of splits. The right fragment's state
+    // is always the newest, so it is dropped from the end of the list.
+    fn coalesce(&mut self, left: &Fragment, right: &Fragment) -> bool {
+        let (Some(l), Some(r)) = (self.single_condition(left), self.single_condition(right)) else {
+            return false;
+        };
+        if right.head != self.state_list.len() - 1 {
+            return false;
+        }
+        let mut branches = l.clone().branches();
+        branches.extend(r.clone().branches());
+        self.state_list.pop();
+        if let State::Transition { condition, .. } = &mut self.state_list[left.head] {
+            *condition = Condition::CharClass(branches);
+        }
+        true
+    }
+
+    fn link_hole(&mut self, hole: usize, t_idx: usize) {
+        match &mut self.state_list[hole] {
+            State::Transition { output, .. }
+            | State::Epsilon { output, .. }
+            | State::Save { output, .. } => *output = Some(t_idx),
+            state => unreachable!("state {} has no output to link", state),
+        }
+    }
+
+    fn link_fragment(&mut self, frag: &Fragment, t_idx: usize) {
+        frag.out
+            .iter()
+            .for_each(|&hole| self.link_hole(hole, t_idx));
+    }
+
+    // link_fragments points every hole of from at the head of to, after
+    // which from's holes are to's holes
+    fn link_fragments(&mut self, from: &mut Fragment, to: Fragment) {
+        self.link_fragment(from, to.head);
+        from.out = to.out;
     }
 
     pub fn build(expr: Vec<Expr>) -> Result<Self, String> {
         let mut nfa = Self::new();
         let mut stack = Vec::new();
-        let mut counter = 0;
 
         for e in expr {
             match e {
-                Expr::Literal(c) => {
-                    let st = State::Transition {
-                        id: counter,
-                        condition: Condition::Id(c),
-                        output: None,
-                    };
-                    let idx = nfa.add_state(st);
-                    stack.push(Fragment::detached(idx));
+                Expr::Literal(c) => stack.push(nfa.add_transition(Condition::Id(c))),
+                // a straight chain, the states a concatenation of each char
+                // would build
+                Expr::StringLiteral(s) => {
+                    let mut chars = s.chars();
+                    let first = chars.next().ok_or("Empty string literal")?;
+                    let mut frag = nfa.add_transition(Condition::Id(first));
+                    for c in chars {
+                        let next = nfa.add_transition(Condition::Id(c));
+                        nfa.link_fragments(&mut frag, next);
+                    }
+                    stack.push(frag);
                 }
                 Expr::CharRange(l, r) => {
-                    let chars = Self::range_chars(l, r)?;
-                    let st = State::Transition {
-                        id: counter,
-                        condition: Condition::CharClass(chars),
-                        output: None,
-                    };
-                    let idx = nfa.add_state(st);
-                    stack.push(Fragment::detached(idx));
+                    if l > r {
+                        return Err("Ranges must be specified in ascending order".into());
+                    }
+                    stack.push(nfa.add_transition(Condition::Range { start: l, end: r }));
                 }
+                Expr::Alphanumeric => stack.push(nfa.add_transition(Condition::Alphanumeric)),
                 Expr::Concat => {
                     let right = stack.pop().ok_or("Missing right fragment")?;
                     let mut left = stack.pop().ok_or("Missing left fragment")?;
-                    nfa.link_fragments(&mut left, right)?;
+                    nfa.link_fragments(&mut left, right);
                     stack.push(left);
                 }
                 Expr::Alt => {
                     let right = stack.pop().ok_or("Missing right fragment")?;
                     let left = stack.pop().ok_or("Missing left fragment")?;
-                    let split = State::Split {
-                        id: counter,
+                    if nfa.coalesce(&left, &right) {
+                        stack.push(left);
+                        continue;
+                    }
+                    let idx = nfa.add_state(|id| State::Split {
+                        id,
                         left: Some(left.head),
                         right: Some(right.head),
-                    };
-                    let idx = nfa.add_state(split);
-                    if stack.is_empty() {
-                        nfa.head = idx;
-                    }
-                    let merged = Fragment::multi_link(idx, left.out, right.out);
+                    });
+                    let merged = Fragment::merged(idx, left.out, right.out);
                     stack.push(merged);
                 }
+                // the split either enters e or skips straight to the exit
                 Expr::Opt => {
                     let e = stack.pop().ok_or("Missing fragment for '?' operator")?;
-                    let split = State::Split {
-                        id: counter,
-                        left: Some(e.head),
-                        right: None,
-                    };
-                    let idx = nfa.add_state(split);
-                    nfa.head = idx;
-                    let new_frag = Fragment::multi_link(idx, e.out, vec![idx]);
-                    stack.push(new_frag);
+                    let (idx, exit) = nfa.add_quantifier(&e);
+                    nfa.link_fragment(&e, exit);
+                    stack.push(Fragment::new(idx, vec![exit]));
                 }
+                // e loops back to the split, which is also the entry
                 Expr::Star => {
-                    let mut e = stack.pop().ok_or("Missing fragment for '*' operator")?;
-
-                    let split = State::Split {
-                        id: counter,
-                        left: Some(e.head),
-                        right: None,
-                    };
-                    let idx = nfa.add_state(split.clone());
-                    nfa.link_fragment(&mut e, idx)?;
-                    if stack.is_empty() {
-                        nfa.head = idx;
-                    }
-                    stack.push(Fragment::detached(idx));
+                    let e = stack.pop().ok_or("Missing fragment for '*' operator")?;
+                    let (idx, exit) = nfa.add_quantifier(&e);
+                    nfa.link_fragment(&e, idx);
+                    stack.push(Fragment::new(idx, vec![exit]));
                 }
+                // as Star, but entered through e so it runs at least once
                 Expr::Plus => {
-                    let mut e = stack.pop().ok_or("Missing fragment for '+' operator")?;
-                    let split = State::Split {
-                        id: counter,
-                        left: Some(e.head),
-                        right: None,
-                    };
-                    let idx = nfa.add_state(split.clone());
-                    nfa.link_fragment(&mut e, idx)?;
-                    let new_frag = Fragment::single_link(e.head, idx);
-                    stack.push(new_frag);
+                    let e = stack.pop().ok_or("Missing fragment for '+' operator")?;
+                    let (idx, exit) = nfa.add_quantifier(&e);
+                    nfa.link_fragment(&e, idx);
+                    stack.push(Fragment::new(e.head, vec![exit]));
+                }
+                // e is entered through a Save of its start and left through
+                // a Save of its end
+                Expr::Group(n) => {
+                    let e = stack.pop().ok_or("Missing fragment for group")?;
+                    let start = nfa.add_state(|id| State::Save {
+                        id,
+                        slot: 2 * n,
+                        output: Some(e.head),
+                    });
+                    let end = nfa.add_state(|id| State::Save {
+                        id,
+                        slot: 2 * n + 1,
+                        output: None,
+                    });
+                    nfa.link_fragment(&e, end);
+                    stack.push(Fragment::new(start, vec![end]));
                 }
             }
-            counter += 1;
         }
 
-        let mut final_fragment = stack.pop().ok_or("No final fragment on stack")?;
-        let accept_idx = nfa.add_state(State::Accept { id: counter });
-        nfa.link_fragments(&mut final_fragment, Fragment::detached(accept_idx))?;
+        // The NFA starts wherever the whole expression's fragment does. This
+        // used to be tracked per operator, which left the head on a `?` split
+        // even when the option came after a concatenation, as in a.(b)?
+        let final_fragment = stack.pop().ok_or("No final fragment on stack")?;
+        nfa.head = final_fragment.head;
+        let accept_idx = nfa.add_state(|id| State::Accept { id });
+        nfa.link_fragment(&final_fragment, accept_idx);
         Ok(nfa)
     }
 
-    pub fn to_string(&self) -> String {
-        let mut s = format!("head = {}\n", self.head);
-        for (i, st) in self.state_list.iter().enumerate() {
-            s.push_str(&format!("(idx = {i} {})\n", st.to_string()));
-        }
-        s
-    }
-
     pub fn print(&self) {
-        println!("{}", self.to_string());
+        println!("{}", self);
     }
 
     pub fn start(&self) -> usize {
@@ -274,11 +356,33 @@ impl NFA {
     pub fn size(&self) -> usize {
         self.state_list.len()
     }
+
+    // group_count is the number of the highest capture group
+    pub fn group_count(&self) -> usize {
+        self.state_list
+            .iter()
+            .filter_map(|state| match state {
+                State::Save { slot, .. } => Some(slot / 2),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl fmt::Display for NFA {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "head = {}", self.head)?;
+        for (i, st) in self.state_list.iter().enumerate() {
+            writeln!(f, "(idx = {i} {st})")?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::{Expr, NFA};
+    use super::{Condition, Expr, State, NFA};
 
     fn run_test(input: &str, expected: &str) {
         let expr = Expr::build(input).unwrap();
@@ -306,11 +410,9 @@ head = 0
         run_test(
             "a|b",
             r#"
-head = 2
-(idx = 0 [match 'a' -> Some(3)])
-(idx = 1 [match 'b' -> Some(3)])
-(idx = 2 [-> (Some(0) | Some(1))])
-(idx = 3 [accept])
+head = 0
+(idx = 0 [match 'a|b' -> Some(1)])
+(idx = 1 [accept])
 "#,
         );
     }
@@ -320,14 +422,12 @@ head = 2
         run_test(
             "(a.b)|(c|d)",
             r#"
-head = 5
+head = 3
 (idx = 0 [match 'a' -> Some(1)])
-(idx = 1 [match 'b' -> Some(6)])
-(idx = 2 [match 'c' -> Some(6)])
-(idx = 3 [match 'd' -> Some(6)])
-(idx = 4 [-> (Some(2) | Some(3))])
-(idx = 5 [-> (Some(0) | Some(4))])
-(idx = 6 [accept])
+(idx = 1 [match 'b' -> Some(4)])
+(idx = 2 [match 'c|d' -> Some(4)])
+(idx = 3 [-> (Some(0) | Some(2))])
+(idx = 4 [accept])
 "#,
         );
     }
@@ -340,7 +440,8 @@ head = 5
 head = 1
 (idx = 0 [match 'a' -> Some(1)])
 (idx = 1 [-> (Some(0) | Some(2))])
-(idx = 2 [accept])
+(idx = 2 [epsilon -> Some(3)])
+(idx = 3 [accept])
 "#,
         );
     }
@@ -353,7 +454,8 @@ head = 1
 head = 0
 (idx = 0 [match 'a' -> Some(1)])
 (idx = 1 [-> (Some(0) | Some(2))])
-(idx = 2 [accept])
+(idx = 2 [epsilon -> Some(3)])
+(idx = 3 [accept])
 "#,
         );
     }
@@ -366,7 +468,8 @@ head = 0
 head = 1
 (idx = 0 [match 'a' -> Some(2)])
 (idx = 1 [-> (Some(0) | Some(2))])
-(idx = 2 [accept])
+(idx = 2 [epsilon -> Some(3)])
+(idx = 3 [accept])
 "#,
         );
     }
@@ -376,15 +479,90 @@ head = 1
         run_test(
             "a.b.c|d*",
             r#"
-head = 5
+head = 6
 (idx = 0 [match 'a' -> Some(1)])
 (idx = 1 [match 'b' -> Some(2)])
-(idx = 2 [match 'c' -> Some(6)])
+(idx = 2 [match 'c' -> Some(7)])
 (idx = 3 [match 'd' -> Some(4)])
-(idx = 4 [-> (Some(3) | Some(6))])
-(idx = 5 [-> (Some(0) | Some(4))])
+(idx = 4 [-> (Some(3) | Some(5))])
+(idx = 5 [epsilon -> Some(7)])
+(idx = 6 [-> (Some(0) | Some(4))])
+(idx = 7 [accept])
+"#,
+        );
+    }
+
+    #[test]
+    fn test_star_over_alternation() {
+        run_test(
+            "(a.b|c)*",
+            r#"
+head = 4
+(idx = 0 [match 'a' -> Some(1)])
+(idx = 1 [match 'b' -> Some(4)])
+(idx = 2 [match 'c' -> Some(4)])
+(idx = 3 [-> (Some(0) | Some(2))])
+(idx = 4 [-> (Some(3) | Some(5))])
+(idx = 5 [epsilon -> Some(6)])
 (idx = 6 [accept])
 "#,
         );
     }
+
+    #[test]
+    fn test_range_is_not_expanded() {
+        let nfa = NFA::build(Expr::build("[a-z]").unwrap()).unwrap();
+        let state = nfa.get_state(0);
+        assert!(matches!(
+            state,
+            State::Transition {
+                condition: Condition::Range {
+                    start: 'a',
+                    end: 'z'
+                },
+                ..
+            }
+        ));
+        assert!(state.matches_condition('a'));
+        assert!(state.matches_condition('m'));
+        assert!(state.matches_condition('z'));
+        assert!(!state.matches_condition('A'));
+        assert!(!state.matches_condition('{'));
+
+        // a range spanning the whole BMP is still a single comparison
+        let nfa = NFA::build(Expr::build("[\u{0}-\u{ffff}]").unwrap()).unwrap();
+        assert!(nfa.get_state(0).matches_condition('中'));
+        assert!(!nfa.get_state(0).matches_condition('😀'));
+    }
+
+    #[test]
+    fn test_wide_alternation_is_coalesced() {
+        // the naive chain is five transitions, four splits and the accept
+        let nfa = NFA::build(Expr::build("a|b|c|d|e").unwrap()).unwrap();
+        assert_eq!(nfa.size(), 2);
+        let state = nfa.get_state(nfa.start());
+        for c in ['a', 'b', 'c', 'd', 'e'] {
+            assert!(state.matches_condition(c));
+        }
+        assert!(!state.matches_condition('f'));
+
+        let nfa = NFA::build(Expr::build("[a-z]|[0-9]|_").unwrap()).unwrap();
+        assert_eq!(nfa.size(), 2);
+        assert_eq!(
+            nfa.get_state(0).to_string(),
+            "[match 'a-z|0-9|_' -> Some(1)]"
+        );
+    }
+
+    #[test]
+    fn test_string_literal_is_a_chain() {
+        let quoted = NFA::build(Expr::build("\"section\"").unwrap()).unwrap();
+        let chained = NFA::build(Expr::build("s.e.c.t.i.o.n").unwrap()).unwrap();
+        assert_eq!(quoted.to_string(), chained.to_string());
+        assert_eq!(quoted.size(), 8);
+
+        let quoted = NFA::build(Expr::build("\"ab\"*.c").unwrap()).unwrap();
+        let chained = NFA::build(Expr::build("(a.b)*.c").unwrap()).unwrap();
+        assert_eq!(quoted.to_string(), chained.to_string());
+    }
 }
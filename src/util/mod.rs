@@ -0,0 +1,207 @@
+// slugify turns s into an ASCII anchor for use as an element id: lowercased,
+// with each run of other characters collapsed into a single hyphen and no
+// hyphen at either end. Accented Latin letters are transliterated (é to e,
+// ß to ss), any other non-ASCII letter is dropped.
+pub fn slugify(s: &str) -> String {
+    let mut slug = String::with_capacity(s.len());
+    let mut pending_hyphen = false;
+    for ch in s.chars().flat_map(char::to_lowercase) {
+        let ascii = if ch.is_ascii_alphanumeric() {
+            Some(ch.to_string())
+        } else {
+            transliterate(ch).map(str::to_string)
+        };
+        match ascii {
+            Some(text) => {
+                if pending_hyphen && !slug.is_empty() {
+                    slug.push('-');
+                }
+                pending_hyphen = false;
+                slug.push_str(&text);
+            }
+            // dropped letters don't split a word, everything else does
+            None if ch.is_alphabetic() => {}
+            None => pending_hyphen = true,
+        }
+    }
+    slug
+}
+
+// collapse_whitespace replaces each run of whitespace in s, newlines
+// included, with a single space and trims both ends, the way HTML renders text
+pub fn collapse_whitespace(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+// dedent strips the leading whitespace every non-blank line of s shares,
+// keeping the indentation of lines relative to each other. Blank lines don't
+// count towards the common indent and lose whatever part of it they have.
+pub fn dedent(s: &str) -> String {
+    let indent = s
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| &line[..line.len() - line.trim_start().len()])
+        .reduce(common_prefix)
+        .unwrap_or("");
+    s.split('\n')
+        .map(|line| match line.strip_prefix(indent) {
+            Some(rest) => rest,
+            None => line.trim_start(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn common_prefix<'a>(a: &'a str, b: &str) -> &'a str {
+    let len = a
+        .chars()
+        .zip(b.chars())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x.len_utf8())
+        .sum();
+    &a[..len]
+}
+
+// decode_entities replaces the common named HTML entities in s, such as
+// `&mdash;`, with the chars they stand for. Unknown names and anything that
+// isn't a well formed `&name;` are left as written.
+pub fn decode_entities(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let decoded = rest[1..]
+            .find(';')
+            .and_then(|end| Some((entity(&rest[1..=end])?, end + 2)));
+        match decoded {
+            Some((ch, len)) => {
+                out.push(ch);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// entity maps the name of a named HTML entity to its char
+fn entity(name: &str) -> Option<char> {
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "mdash" => '—',
+        "ndash" => '–',
+        "hellip" => '…',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        _ => return None,
+    })
+}
+
+// transliterate maps a lowercase accented Latin letter to its ASCII spelling
+fn transliterate(ch: char) -> Option<&'static str> {
+    Some(match ch {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'č' => "c",
+        'ď' | 'đ' | 'ð' => "d",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+        'ğ' => "g",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' | 'į' | 'ı' => "i",
+        'ł' | 'ľ' => "l",
+        'ñ' | 'ń' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+        'œ' => "oe",
+        'ř' => "r",
+        'ś' | 'š' | 'ş' => "s",
+        'ß' => "ss",
+        'ť' | 'ţ' => "t",
+        'þ' => "th",
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' | 'ų' => "u",
+        'ý' | 'ÿ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{collapse_whitespace, decode_entities, dedent, slugify};
+
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Getting Started"), "getting-started");
+        assert_eq!(
+            slugify("  Mixed CASE  with   Spaces "),
+            "mixed-case-with-spaces"
+        );
+        assert_eq!(slugify("Getting_Started  now"), "getting-started-now");
+        assert_eq!(slugify("What's new in v2.0?"), "what-s-new-in-v2-0");
+        assert_eq!(slugify("--already-slugged--"), "already-slugged");
+        assert_eq!(slugify("h2"), "h2");
+    }
+
+    #[test]
+    fn test_dedent() {
+        assert_eq!(
+            dedent("\n    fn main() {\n        run();\n\n    }\n"),
+            "\nfn main() {\n    run();\n\n}\n"
+        );
+        // tabs and spaces only match themselves
+        assert_eq!(dedent("\t\ta\n\t  b"), "\ta\n  b");
+        assert_eq!(dedent("a\n  b"), "a\n  b");
+        assert_eq!(dedent(""), "");
+    }
+
+    #[test]
+    fn test_slugify_empty() {
+        assert_eq!(slugify(""), "");
+        assert_eq!(slugify("   "), "");
+        assert_eq!(slugify("?!... --- ***"), "");
+    }
+
+    #[test]
+    fn test_slugify_unicode() {
+        assert_eq!(slugify("Café Crème"), "cafe-creme");
+        assert_eq!(slugify("Straße"), "strasse");
+        assert_eq!(slugify("ÉTÉ"), "ete");
+        // letters with no ASCII spelling are dropped without splitting words
+        assert_eq!(slugify("Hello 世界"), "hello");
+        assert_eq!(slugify("naïve日本 approach"), "naive-approach");
+        assert_eq!(slugify("中文"), "");
+    }
+
+    #[test]
+    fn test_collapse_whitespace() {
+        assert_eq!(
+            collapse_whitespace("\n    Some   hand\n    formatted\ttext.\n  "),
+            "Some hand formatted text."
+        );
+        assert_eq!(collapse_whitespace(" \n "), "");
+    }
+
+    #[test]
+    fn test_decode_entities() {
+        assert_eq!(
+            decode_entities("Wait&hellip; it&rsquo;s here &mdash; A &amp; B"),
+            "Wait… it’s here — A & B"
+        );
+        // unknown or malformed entities are kept
+        assert_eq!(decode_entities("&bogus; & &amp &;"), "&bogus; & &amp &;");
+        assert_eq!(decode_entities("&amp;lt;"), "&lt;");
+    }
+}